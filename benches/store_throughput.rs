@@ -0,0 +1,185 @@
+// Mixed read/write throughput of the sharded `Store` against the single `RwLock<HashMap>` the server used to have.
+//
+// Run with `cargo bench --bench store_throughput -- [--tasks N] [--seconds S] [--businesses B] [--write-percent W]`.
+// Every operation does the same work a handler does under the lock: reads clone and serialize a business,
+// writes add a photo or change a caption and build the JSON response.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use playground_site::business::{Business, BusinessResponse, Category, NewPhoto, PhotoCaptionUpdate};
+use playground_site::store::Store;
+use tokio::sync::RwLock;
+
+struct Settings {
+    tasks: usize,
+    seconds: u64,
+    businesses: usize,
+    write_percent: u64,
+}
+
+impl Settings {
+    fn from_args() -> Self {
+        let mut settings = Settings { tasks: 64, seconds: 3, businesses: 1000, write_percent: 20 };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            // Cargo passes `--bench` along, anything we don't know about is ignored.
+            let mut value = || args.next().and_then(|value| value.parse().ok());
+            match arg.as_str() {
+                "--tasks" => settings.tasks = value().unwrap_or(settings.tasks as u64) as usize,
+                "--seconds" => settings.seconds = value().unwrap_or(settings.seconds),
+                "--businesses" => settings.businesses = value().unwrap_or(settings.businesses as u64) as usize,
+                "--write-percent" => settings.write_percent = value().unwrap_or(settings.write_percent).min(100),
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+/// The operations the benchmark needs, so both stores go through the exact same loop.
+trait BenchStore: Send + Sync + 'static {
+    fn read_business(&self, name: &str) -> impl Future<Output = Option<String>> + Send;
+    fn write_business(&self, name: &str, op: u64) -> impl Future<Output = ()> + Send;
+}
+
+type GlobalLock = RwLock<HashMap<String, BusinessResponse>>;
+
+impl BenchStore for GlobalLock {
+    async fn read_business(&self, name: &str) -> Option<String> {
+        let database = self.read().await;
+        database.get(name).map(|business| serde_json::to_string(business).unwrap())
+    }
+
+    async fn write_business(&self, name: &str, op: u64) {
+        let mut database = self.write().await;
+        if let Some(business) = database.get_mut(name) {
+            write_op(business, op);
+        }
+    }
+}
+
+impl BenchStore for Store {
+    async fn read_business(&self, name: &str) -> Option<String> {
+        self.read(name, |business| serde_json::to_string(business).unwrap())
+    }
+
+    async fn write_business(&self, name: &str, op: u64) {
+        self.update(name, |business| write_op(business, op));
+    }
+}
+
+fn write_op(business: &mut BusinessResponse, op: u64) {
+    // Keep the photo list from growing without bound, so later writes don't get slower than earlier ones.
+    if business.photos.as_ref().map_or(0, Vec::len) < 8 {
+        business.add_business_photo(
+            "bench".into(),
+            NewPhoto { photo_url: format!("https://example.com/{op}.png"), photo_caption: None },
+        );
+    } else {
+        let photo_id = business.photos.as_ref().unwrap()[(op % 8) as usize].photo_id;
+        business.update_business_photo(
+            "bench".into(),
+            photo_id,
+            PhotoCaptionUpdate { photo_caption: Some(format!("caption {op}")) },
+        );
+    }
+}
+
+fn business(index: usize) -> BusinessResponse {
+    BusinessResponse::new(
+        Business {
+            name: format!("Business {index}"),
+            street_addr: format!("{index} Main St"),
+            city: "Houston".into(),
+            state: "Texas".into(),
+            zip: "77001".parse().unwrap(),
+            phone_num: "713-555-0100".parse().unwrap(),
+            category: Category { main_category: "Restaurant".into(), subcategory: "Pizza".into() },
+            category_ids: Vec::new(),
+            email: None,
+            website: None,
+            latitude: None,
+            longitude: None,
+            hours: None,
+            attributes: Default::default(),
+        },
+        None,
+        Some(Vec::new()),
+    )
+}
+
+/// xorshift, so every task gets its own cheap and reproducible stream of businesses and operations.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+async fn run<S: BenchStore>(label: &str, store: Arc<S>, settings: &Settings) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+    let writes = Arc::new(AtomicU64::new(0));
+
+    let workers: Vec<_> = (0..settings.tasks)
+        .map(|task| {
+            let (store, stop, reads, writes) = (store.clone(), stop.clone(), reads.clone(), writes.clone());
+            let (businesses, write_percent) = (settings.businesses as u64, settings.write_percent);
+            tokio::spawn(async move {
+                let mut random = 0x9E37_79B9_7F4A_7C15 ^ (task as u64 + 1);
+                while !stop.load(Ordering::Relaxed) {
+                    let name = format!("Business {}", next_random(&mut random) % businesses);
+                    let op = next_random(&mut random);
+                    if op % 100 < write_percent {
+                        store.write_business(&name, op).await;
+                        writes.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        store.read_business(&name).await;
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_secs(settings.seconds)).await;
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let (reads, writes) = (reads.load(Ordering::Relaxed), writes.load(Ordering::Relaxed));
+    println!(
+        "{label:<28} {:>12.0} ops/s  ({:.0} reads/s, {:.0} writes/s)",
+        (reads + writes) as f64 / elapsed,
+        reads as f64 / elapsed,
+        writes as f64 / elapsed,
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let settings = Settings::from_args();
+    println!(
+        "{} tasks on {} threads, {} businesses, {}% writes, {}s per run",
+        settings.tasks,
+        std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        settings.businesses,
+        settings.write_percent,
+        settings.seconds,
+    );
+
+    let global: GlobalLock = RwLock::new((0..settings.businesses).map(|index| (format!("Business {index}"), business(index))).collect());
+    run("global RwLock<HashMap>", Arc::new(global), &settings).await;
+
+    let sharded = Store::new();
+    for index in 0..settings.businesses {
+        sharded.insert_new(format!("Business {index}"), business(index));
+    }
+    run("sharded Store", Arc::new(sharded), &settings).await;
+}
@@ -0,0 +1,27 @@
+// Bakes build information into the binary for the /version endpoint.
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=BELP_GIT_HASH={git_hash}");
+
+    // Cargo tells build scripts which features are on through CARGO_FEATURE_<NAME> variables.
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| name.strip_prefix("CARGO_FEATURE_").map(|feature| feature.to_lowercase().replace('_', "-")))
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BELP_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,283 @@
+// US postal addresses. States are stored as USPS codes and street suffixes as their USPS abbreviations, so
+// "Texas"/"TX" and "Pine View Point"/"Pine View Pt" are the same thing everywhere. Normalizing happens while a
+// business is deserialized, so request bodies, patches, snapshots and seed files all go through it.
+//
+// ZIP codes are strings, with or without the +4 part, and are checked against the state with the bundled ZIP table.
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::zips;
+
+/// Full names of every state, district, territory and military "state", with their USPS codes.
+const STATES: &[(&str, &str)] = &[
+    ("AL", "Alabama"), ("AK", "Alaska"), ("AZ", "Arizona"), ("AR", "Arkansas"), ("CA", "California"),
+    ("CO", "Colorado"), ("CT", "Connecticut"), ("DE", "Delaware"), ("DC", "District of Columbia"),
+    ("FL", "Florida"), ("GA", "Georgia"), ("HI", "Hawaii"), ("ID", "Idaho"), ("IL", "Illinois"), ("IN", "Indiana"),
+    ("IA", "Iowa"), ("KS", "Kansas"), ("KY", "Kentucky"), ("LA", "Louisiana"), ("ME", "Maine"), ("MD", "Maryland"),
+    ("MA", "Massachusetts"), ("MI", "Michigan"), ("MN", "Minnesota"), ("MS", "Mississippi"), ("MO", "Missouri"),
+    ("MT", "Montana"), ("NE", "Nebraska"), ("NV", "Nevada"), ("NH", "New Hampshire"), ("NJ", "New Jersey"),
+    ("NM", "New Mexico"), ("NY", "New York"), ("NC", "North Carolina"), ("ND", "North Dakota"), ("OH", "Ohio"),
+    ("OK", "Oklahoma"), ("OR", "Oregon"), ("PA", "Pennsylvania"), ("RI", "Rhode Island"), ("SC", "South Carolina"),
+    ("SD", "South Dakota"), ("TN", "Tennessee"), ("TX", "Texas"), ("UT", "Utah"), ("VT", "Vermont"),
+    ("VA", "Virginia"), ("WA", "Washington"), ("WV", "West Virginia"), ("WI", "Wisconsin"), ("WY", "Wyoming"),
+    ("AS", "American Samoa"), ("GU", "Guam"), ("MP", "Northern Mariana Islands"), ("PR", "Puerto Rico"),
+    ("VI", "U.S. Virgin Islands"), ("FM", "Federated States of Micronesia"), ("MH", "Marshall Islands"),
+    ("PW", "Palau"), ("AA", "Armed Forces Americas"), ("AE", "Armed Forces Europe"), ("AP", "Armed Forces Pacific"),
+];
+
+/// Street suffixes from USPS Publication 28 (appendix C1): the abbreviation, then every spelling that means it.
+const STREET_SUFFIXES: &[(&str, &[&str])] = &[
+    ("Aly", &["alley", "allee", "ally"]), ("Anx", &["annex", "annx", "anex"]), ("Arc", &["arcade"]),
+    ("Ave", &["avenue", "av", "aven", "avenu", "avn", "avnue"]), ("Byu", &["bayou", "bayoo"]), ("Bch", &["beach"]),
+    ("Bnd", &["bend"]), ("Blf", &["bluff", "bluf"]), ("Btm", &["bottom", "bottm", "bot"]),
+    ("Blvd", &["boulevard", "boul", "boulv"]), ("Br", &["branch", "brnch"]), ("Brg", &["bridge", "brdge"]),
+    ("Brk", &["brook"]), ("Byp", &["bypass", "bypa", "bypas", "byps"]), ("Cp", &["camp", "cmp"]),
+    ("Cyn", &["canyon", "canyn", "cnyn"]), ("Cpe", &["cape"]), ("Cswy", &["causeway", "causwa"]),
+    ("Ctr", &["center", "cen", "cent", "centr", "centre", "cnter", "cntr"]),
+    ("Cir", &["circle", "circ", "circl", "crcl", "crcle"]), ("Clf", &["cliff"]), ("Clb", &["club"]),
+    ("Cmn", &["common"]), ("Cor", &["corner"]), ("Cors", &["corners"]), ("Crse", &["course"]), ("Ct", &["court"]),
+    ("Cts", &["courts"]), ("Cv", &["cove"]), ("Crk", &["creek"]), ("Cres", &["crescent", "crsent", "crsnt"]),
+    ("Crst", &["crest"]), ("Xing", &["crossing", "crssng"]), ("Xrd", &["crossroad"]), ("Curv", &["curve"]),
+    ("Dl", &["dale"]), ("Dm", &["dam"]), ("Dv", &["divide", "div", "dvd"]), ("Dr", &["drive", "driv", "drv"]),
+    ("Est", &["estate"]), ("Ests", &["estates"]), ("Expy", &["expressway", "exp", "expr", "express", "expw"]),
+    ("Ext", &["extension", "extn", "extnsn"]), ("Fls", &["falls"]), ("Fry", &["ferry", "frry"]),
+    ("Fld", &["field"]), ("Flds", &["fields"]), ("Flt", &["flat"]), ("Frst", &["forest", "forests"]),
+    ("Frg", &["forge", "forg"]), ("Frk", &["fork"]), ("Ft", &["fort", "frt"]), ("Fwy", &["freeway", "freewy", "frway", "frwy"]),
+    ("Gdn", &["garden", "gardn", "grden", "grdn"]), ("Gdns", &["gardens", "grdns"]),
+    ("Gtwy", &["gateway", "gatewy", "gatway", "gtway"]), ("Gln", &["glen"]), ("Grn", &["green"]),
+    ("Grv", &["grove", "grov"]), ("Hbr", &["harbor", "harb", "harbr", "hrbor"]), ("Hvn", &["haven"]),
+    ("Hts", &["heights", "ht"]), ("Hwy", &["highway", "highwy", "hiway", "hiwy", "hway"]), ("Hl", &["hill"]),
+    ("Hls", &["hills"]), ("Holw", &["hollow", "hllw", "hollows", "holws"]), ("Is", &["island", "islnd"]),
+    ("Jct", &["junction", "jction", "jctn", "junctn", "juncton"]), ("Knl", &["knoll", "knol"]), ("Lk", &["lake"]),
+    ("Lks", &["lakes"]), ("Lndg", &["landing", "lndng"]), ("Ln", &["lane"]), ("Lgt", &["light"]), ("Loop", &["loops"]),
+    ("Mall", &[]), ("Mnr", &["manor"]), ("Mdw", &["meadow"]), ("Mdws", &["meadows", "medows"]), ("Ml", &["mill"]),
+    ("Msn", &["mission", "missn", "mssn"]), ("Mt", &["mount", "mnt"]), ("Mtn", &["mountain", "mntain", "mntn", "mountin", "mtin"]),
+    ("Orch", &["orchard", "orchrd"]), ("Oval", &["ovl"]), ("Park", &["prk"]), ("Pkwy", &["parkway", "parkwy", "pkway", "pky"]),
+    ("Pass", &[]), ("Path", &["paths"]), ("Pike", &["pikes"]), ("Pne", &["pine"]), ("Pnes", &["pines"]),
+    ("Pl", &["place"]), ("Pln", &["plain"]), ("Plns", &["plains"]), ("Plz", &["plaza", "plza"]), ("Pt", &["point"]),
+    ("Pts", &["points"]), ("Prt", &["port"]), ("Pr", &["prairie", "prr"]), ("Rnch", &["ranch", "ranches", "rnchs"]),
+    ("Rdg", &["ridge", "rdge"]), ("Riv", &["river", "rvr", "rivr"]), ("Rd", &["road"]), ("Rte", &["route"]),
+    ("Row", &[]), ("Run", &[]), ("Shr", &["shore", "shoar"]), ("Spg", &["spring", "spng", "sprng"]),
+    ("Spgs", &["springs", "spngs", "sprngs"]), ("Sq", &["square", "sqr", "sqre", "squ"]),
+    ("Sta", &["station", "statn", "stn"]), ("St", &["street", "strt", "str"]), ("Smt", &["summit", "sumit", "sumitt"]),
+    ("Ter", &["terrace", "terr"]), ("Trce", &["trace", "traces"]), ("Trl", &["trail", "trails", "trls"]),
+    ("Tunl", &["tunnel", "tunel", "tunls", "tunnels", "tunnl"]), ("Tpke", &["turnpike", "trnpk", "turnpk"]),
+    ("Un", &["union"]), ("Vly", &["valley", "vally", "vlly"]), ("Vw", &["view"]), ("Vlg", &["village", "vill", "villag", "villg", "villiage"]),
+    ("Vl", &["ville"]), ("Vis", &["vista", "vist", "vst", "vsta"]), ("Walk", &["walks"]), ("Way", &["wy"]), ("Wl", &["well"]),
+];
+
+/// Directions that can follow the suffix, as in "Main St N".
+const DIRECTIONS: &[(&str, &str)] = &[
+    ("N", "north"), ("S", "south"), ("E", "east"), ("W", "west"),
+    ("NE", "northeast"), ("NW", "northwest"), ("SE", "southeast"), ("SW", "southwest"),
+];
+
+/// The USPS code for a state given by name or code, in any case.
+pub fn state_code(state: &str) -> Option<&'static str> {
+    let state = state.trim().trim_end_matches('.');
+    STATES
+        .iter()
+        .find(|(code, name)| code.eq_ignore_ascii_case(state) || name.eq_ignore_ascii_case(state))
+        .map(|(code, _)| *code)
+}
+
+/// The state as a USPS code when it's one we know, left as it was otherwise so validation can point at it.
+pub fn normalize_state(state: &str) -> String {
+    state_code(state).map(str::to_string).unwrap_or_else(|| state.trim().to_string())
+}
+
+/// Tidies spacing and replaces the street suffix (and a trailing direction) with the USPS abbreviation.
+pub fn normalize_street(street: &str) -> String {
+    let mut words: Vec<String> = street.split_whitespace().map(|word| word.trim_end_matches('.').to_string()).collect();
+    words.retain(|word| !word.is_empty());
+
+    let mut suffix_index = words.len().checked_sub(1);
+    if let Some(index) = suffix_index {
+        if let Some(direction) = direction(&words[index]) {
+            words[index] = direction.to_string();
+            suffix_index = index.checked_sub(1);
+        }
+    }
+    // The suffix needs a name in front of it, "100 Park" is a street called Park.
+    if let Some(index) = suffix_index.filter(|index| words[..*index].iter().any(|word| !is_house_number(word))) {
+        if let Some(abbreviation) = street_suffix(&words[index]) {
+            words[index] = abbreviation.to_string();
+        }
+    }
+    words.join(" ")
+}
+
+fn direction(word: &str) -> Option<&'static str> {
+    DIRECTIONS
+        .iter()
+        .find(|(code, name)| code.eq_ignore_ascii_case(word) || name.eq_ignore_ascii_case(word))
+        .map(|(code, _)| *code)
+}
+
+fn street_suffix(word: &str) -> Option<&'static str> {
+    let word = word.to_lowercase();
+    STREET_SUFFIXES
+        .iter()
+        .find(|(abbreviation, spellings)| abbreviation.to_lowercase() == word || spellings.contains(&word.as_str()))
+        .map(|(abbreviation, _)| *abbreviation)
+}
+
+fn is_house_number(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_digit())
+}
+
+pub(crate) fn deserialize_state<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|state| normalize_state(&state))
+}
+
+pub(crate) fn deserialize_street<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|street| normalize_street(&street))
+}
+
+pub(crate) fn deserialize_city<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|city| city.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Problems with a normalized address: states that aren't US states, and ZIP codes from a different state.
+pub fn address_problems(state: &str, zip: &ZipCode) -> Vec<String> {
+    let mut problems = Vec::new();
+    if state_code(state) != Some(state) {
+        problems.push(format!("`{state}` is not a US state"));
+        return problems;
+    }
+    // ZIP codes that aren't in the table can't be checked, they're let through rather than guessed at.
+    if let Some(info) = zips::lookup(zip.five_digit()) {
+        if info.state != state {
+            problems.push(format!("ZIP code {zip} is in {}, not {state}", info.state));
+        }
+    }
+    problems
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("`{0}` is not a ZIP code, it should look like 77002 or 77002-1234")]
+pub struct ZipCodeError(String);
+
+/// A five digit ZIP code, with the +4 part when it's known.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ZipCode {
+    zip: String,
+    plus_four: Option<String>,
+}
+
+impl ZipCode {
+    pub fn parse(input: &str) -> Result<Self, ZipCodeError> {
+        let error = || ZipCodeError(input.to_string());
+        let digits: String = input.trim().chars().filter(|c| *c != '-' && *c != ' ').collect();
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(error());
+        }
+        // A separator is only allowed between the two parts.
+        let separators = input.trim().chars().filter(|c| *c == '-' || *c == ' ').count();
+        match (digits.len(), separators) {
+            (5, 0) => Ok(ZipCode { zip: digits, plus_four: None }),
+            (9, 0) => Ok(ZipCode { zip: digits[..5].to_string(), plus_four: Some(digits[5..].to_string()) }),
+            (9, 1) if matches!(input.trim().chars().nth(5), Some('-' | ' ')) => {
+                Ok(ZipCode { zip: digits[..5].to_string(), plus_four: Some(digits[5..].to_string()) })
+            }
+            _ => Err(error()),
+        }
+    }
+
+    /// The five digit ZIP code as a number, for looking it up in the ZIP table.
+    pub fn five_digit(&self) -> u32 {
+        self.zip.parse().unwrap_or_default()
+    }
+
+    pub fn plus_four(&self) -> Option<&str> {
+        self.plus_four.as_deref()
+    }
+}
+
+impl fmt::Display for ZipCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.plus_four {
+            Some(plus_four) => write!(f, "{}-{plus_four}", self.zip),
+            None => write!(f, "{}", self.zip),
+        }
+    }
+}
+
+impl FromStr for ZipCode {
+    type Err = ZipCodeError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        ZipCode::parse(input)
+    }
+}
+
+impl Serialize for ZipCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ZipCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ZipCodeVisitor;
+
+        impl Visitor<'_> for ZipCodeVisitor {
+            type Value = ZipCode;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a ZIP code")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<ZipCode, E> {
+                ZipCode::parse(value).map_err(E::custom)
+            }
+
+            // The old API took ZIP codes as numbers, which lost their leading zeros.
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<ZipCode, E> {
+                match value {
+                    0..=99_999 => self.visit_str(&format!("{value:05}")),
+                    _ => self.visit_str(&format!("{value:09}")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(ZipCodeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_and_streets_normalize() {
+        assert_eq!(normalize_state("texas"), "TX");
+        assert_eq!(normalize_state(" District of Columbia "), "DC");
+        assert_eq!(normalize_state("Texass"), "Texass");
+        assert_eq!(normalize_street("47  Pine View Point"), "47 Pine View Pt");
+        assert_eq!(normalize_street("47 Pine View Pt."), "47 Pine View Pt");
+        assert_eq!(normalize_street("1200 Main Street north"), "1200 Main St N");
+        assert_eq!(normalize_street("100 Park"), "100 Park");
+    }
+
+    #[test]
+    fn zip_codes_keep_leading_zeros_and_plus_four() {
+        assert_eq!(ZipCode::parse("02108").unwrap().to_string(), "02108");
+        assert_eq!(ZipCode::parse("77002 1234").unwrap().to_string(), "77002-1234");
+        assert_eq!(ZipCode::parse("770021234").unwrap().plus_four(), Some("1234"));
+        assert!(ZipCode::parse("7700").is_err());
+        assert!(ZipCode::parse("770-02").is_err());
+        assert_eq!(serde_json::from_str::<ZipCode>("2108").unwrap().to_string(), "02108");
+    }
+
+    #[test]
+    fn zip_codes_are_checked_against_the_state() {
+        assert!(address_problems("TX", &"77002".parse().unwrap()).is_empty());
+        assert_eq!(address_problems("PA", &"77002".parse().unwrap()), ["ZIP code 77002 is in TX, not PA"]);
+        assert_eq!(address_problems("Texass", &"77002".parse().unwrap()), ["`Texass` is not a US state"]);
+    }
+}
@@ -0,0 +1,119 @@
+// Admin-only endpoints (managing the category tree, merging duplicates, overriding business claims) are guarded by
+// a single bearer token from the config. Without one configured they're switched off entirely rather than left open.
+//
+// Some writes are for a business' owner. Owners send the owner token they were handed when they became the owner
+// (see `claims`) in the same header, and the admin token gets through those too.
+use actix_web::{
+    http::header::{self, HeaderValue},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+
+use crate::claims::Claims;
+
+/// Shortest token the config accepts.
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+/// The token in the request's `Authorization: Bearer` header, if there is one.
+fn bearer(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Checks the request's `Authorization: Bearer` header against the admin token.
+/// Returns the response to send instead of carrying on, if the request isn't from an admin.
+pub fn authorize(request: &HttpRequest, token: Option<&str>) -> Option<HttpResponse> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return Some(HttpResponse::Forbidden().json(json!({
+                "error": "Admin endpoints are disabled, no admin token is configured"
+            })))
+        }
+    };
+    match bearer(request) {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => None,
+        _ => {
+            let mut response = HttpResponse::Unauthorized().json(json!({
+                "error": "This endpoint needs an admin token, as `Authorization: Bearer <token>`"
+            }));
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            Some(response)
+        }
+    }
+}
+
+/// Lets the request through if it carries the owner's token or the admin token. `None` as the owner token means only
+/// an admin will do. Returns the response to send instead of carrying on, like `authorize`.
+pub fn authorize_owner(request: &HttpRequest, token: Option<&str>, owner_token: Option<&str>) -> Option<HttpResponse> {
+    if let (Some(presented), Some(owner_token)) = (bearer(request), owner_token) {
+        if constant_time_eq(presented.as_bytes(), owner_token.as_bytes()) {
+            return None;
+        }
+    }
+    // Anything but an admin is turned away with a 403, whatever `authorize` would have said.
+    authorize(request, token).map(|_| {
+        let error = match owner_token {
+            Some(_) => "Only the owner or an admin can do this, as `Authorization: Bearer <token>`",
+            None => "Only an admin can do this right now",
+        };
+        HttpResponse::Forbidden().json(json!({ "error": error }))
+    })
+}
+
+/// Who the request is from, if it carries a token that says so: `admin` for the admin token, `owner:<user>` for an
+/// owner token. Anything else, including a wrong token, is anonymous.
+pub fn identity(request: &HttpRequest, token: Option<&str>, claims: &Claims) -> Option<String> {
+    let presented = bearer(request)?;
+    if token.is_some_and(|token| constant_time_eq(presented.as_bytes(), token.as_bytes())) {
+        return Some("admin".into());
+    }
+    claims.owner_of_token(presented).map(|owner| format!("owner:{owner}"))
+}
+
+/// Compares without stopping at the first difference, so the time taken doesn't give away how much of a guess was right.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    #[test]
+    fn only_the_configured_token_gets_in() {
+        let token = Some("correct horse battery staple");
+        let with = |value: &str| TestRequest::default().insert_header((header::AUTHORIZATION, value)).to_http_request();
+
+        assert!(authorize(&with("Bearer correct horse battery staple"), token).is_none());
+        let wrong = authorize(&with("Bearer correct horse battery stapler"), token).unwrap();
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        assert!(wrong.headers().contains_key(header::WWW_AUTHENTICATE));
+        let disabled = authorize(&with("Bearer correct horse battery staple"), None).unwrap();
+        assert_eq!(disabled.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn the_owner_or_an_admin_gets_in() {
+        let token = Some("correct horse battery staple");
+        let owner_token = Some("olivia's owner token");
+        let with = |value: &str| TestRequest::default().insert_header((header::AUTHORIZATION, value)).to_http_request();
+        let admin = with("Bearer correct horse battery staple");
+
+        assert!(authorize_owner(&with("Bearer olivia's owner token"), token, owner_token).is_none());
+        assert_eq!(authorize_owner(&with("Bearer olivia"), token, owner_token).unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(authorize_owner(&TestRequest::default().to_http_request(), token, owner_token).unwrap().status(), StatusCode::FORBIDDEN);
+        assert!(authorize_owner(&admin, token, owner_token).is_none());
+        // Without an owner, no token but the admin's will do.
+        assert_eq!(authorize_owner(&with("Bearer "), token, None).unwrap().status(), StatusCode::FORBIDDEN);
+        assert!(authorize_owner(&admin, token, None).is_none());
+    }
+}
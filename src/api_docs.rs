@@ -0,0 +1,179 @@
+// The OpenAPI document for the server, built from the `#[utoipa::path]` annotations on the handlers
+// and the schemas of the business types. Part of the binary, since that's where the handlers live.
+use std::sync::OnceLock;
+
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use playground_site::attributes::{AttributeDefinition, AttributeKind, AttributeValue};
+use playground_site::business::{
+    Business, BusinessResponse, Category, CoverPhoto, MergeSummary, NewPhoto, OwnerResponse, OwnerResponseText, Photo,
+    PhotoCaptionUpdate, PhotoOrder, Review, UserReviews,
+};
+use playground_site::claims::{AuditEntry, Channel, ClaimConfirmation, ClaimEvent, ClaimRequest, ClaimSent, OwnerAssignment, Ownership};
+use playground_site::duplicates::{DuplicateCandidate, MergeRequest};
+use playground_site::hours::{Day, Hours, SpecialHours, TimeRange};
+use playground_site::taxonomy::{CategoryNode, CategoryRecord, NewCategory};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Belp",
+        description = "A Yelp-like API for businesses, their reviews and their photos.\n\n\
+            Every route documented under `/v1` is also served under `/v2`, except for listing businesses, \
+            which is paginated in v2. Requests without a version in the path are served by the version in \
+            `Accept: application/vnd.belp.v<N>+json`, or v1."
+    ),
+    paths(
+        crate::add_business,
+        crate::get_businesses,
+        crate::list_businesses,
+        crate::search_businesses,
+        crate::autocomplete_names,
+        crate::list_attributes,
+        crate::list_categories,
+        crate::find_category,
+        crate::add_category,
+        crate::update_category,
+        crate::delete_category,
+        crate::list_duplicates,
+        crate::merge_duplicates,
+        crate::request_claim,
+        crate::confirm_claim,
+        crate::assign_owner,
+        crate::remove_owner,
+        crate::claim_audit,
+        crate::delete_business,
+        crate::find_business,
+        crate::update_business,
+        crate::patch_business,
+        crate::add_review,
+        crate::delete_review,
+        crate::update_review,
+        crate::show_business_reviews,
+        crate::business_user_reviews,
+        crate::add_owner_response,
+        crate::update_owner_response,
+        crate::delete_owner_response,
+        crate::add_photo,
+        crate::reorder_photos,
+        crate::set_cover_photo,
+        crate::delete_photo,
+        crate::update_photo,
+        crate::healthz,
+        crate::readyz,
+        crate::build_version,
+        crate::prometheus_metrics,
+    ),
+    components(schemas(
+        AttributeDefinition,
+        AttributeKind,
+        AttributeValue,
+        AuditEntry,
+        Business,
+        BusinessResponse,
+        Category,
+        CategoryNode,
+        CategoryRecord,
+        Channel,
+        ClaimConfirmation,
+        ClaimEvent,
+        ClaimRequest,
+        ClaimSent,
+        CoverPhoto,
+        Day,
+        DuplicateCandidate,
+        Hours,
+        MergeRequest,
+        MergeSummary,
+        NewCategory,
+        NewPhoto,
+        OwnerAssignment,
+        OwnerResponse,
+        Ownership,
+        OwnerResponseText,
+        Photo,
+        PhotoCaptionUpdate,
+        PhotoOrder,
+        Review,
+        SpecialHours,
+        TimeRange,
+        UserReviews,
+    )),
+    tags(
+        (name = "businesses", description = "Adding, finding and editing businesses"),
+        (name = "categories", description = "The category tree businesses are filed under, managed by admins"),
+        (name = "claims", description = "Claiming ownership of a business, verified with a code sent to its contact details"),
+        (name = "moderation", description = "Finding and merging businesses that were added twice, for admins"),
+        (name = "reviews", description = "User reviews of businesses, and their owners' replies"),
+        (name = "photos", description = "User photos of businesses"),
+        (name = "operations", description = "Health checks, build information and metrics"),
+    ),
+    modifiers(&BearerTokens)
+)]
+struct ApiDoc;
+
+/// Declares the bearer tokens the admin and owner routes are marked with: the admin token from the config, and the
+/// owner token handed out when someone becomes a business' owner.
+struct BearerTokens;
+
+impl Modify for BearerTokens {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            for name in ["admin_token", "owner_token"] {
+                components.add_security_scheme(name, SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+            }
+        }
+    }
+}
+
+/// The document never changes while the server runs, so it's only serialized once.
+fn openapi_json() -> &'static str {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    DOCUMENT.get_or_init(|| ApiDoc::openapi().to_json().expect("the OpenAPI document serializes"))
+}
+
+#[get("/openapi.json")]
+async fn openapi_document() -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().content_type("application/json").body(openapi_json()))
+}
+
+/// A Redoc page rendering `/openapi.json`. Redoc itself comes from its CDN, so nothing has to be bundled, pinned to
+/// one release so the page only changes when the version here does.
+#[get("/docs")]
+async fn docs() -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body(DOCS_PAGE))
+}
+
+const DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Belp API</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>body { margin: 0; padding: 0; }</style>
+  </head>
+  <body>
+    <redoc spec-url="openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/v2.1.5/bundles/redoc.standalone.js" crossorigin="anonymous"></script>
+  </body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn document_covers_the_routes_and_validator_limits() {
+        let document: Value = serde_json::from_str(openapi_json()).unwrap();
+        assert_eq!(document["openapi"].as_str().map(|version| &version[..2]), Some("3."));
+        assert!(document["paths"]["/v1/review/{reviewer_name}/{business_name}"]["post"].is_object());
+        assert!(document["paths"]["/v1/photos/{user_name}/{business_name}/{photo_id}"]["delete"].is_object());
+        assert!(document["paths"]["/v2/business"]["get"].is_object());
+        let rating = &document["components"]["schemas"]["Review"]["properties"]["rating"];
+        assert_eq!(rating["maximum"], 5);
+    }
+}
@@ -0,0 +1,248 @@
+// Amenities and other attributes of a business ("wheelchair accessible", "wifi", "price range"). Which attributes
+// exist and what values they take is defined once, in the bundled data/attributes.json, and businesses are checked
+// against it when they're stored. Every attribute is optional on a business, one that's left out is unknown rather
+// than false.
+//
+// Listings and search filter on attributes with a small syntax, comma separated and all of them have to hold:
+// `outdoor_seating` (a yes/no attribute that's true), `dogs_allowed:false`, `wifi:free|paid` (any of those values),
+// `price_range:..2` or `seating_capacity:50..` (number ranges, inclusive, either end can be left off).
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+const SCHEMA: &str = include_str!("../data/attributes.json");
+
+/// What an attribute's values can be.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AttributeKind {
+    Boolean,
+    /// One of a fixed set of values.
+    Enum { values: Vec<String> },
+    Number {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+        /// Whole numbers only.
+        #[serde(default)]
+        integer: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AttributeDefinition {
+    /// What the attribute is called on a business, e.g. `outdoor_seating`.
+    pub key: String,
+    /// How to show it, e.g. `Outdoor seating`.
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: AttributeKind,
+}
+
+/// An attribute's value on a business. Which of these it has to be depends on the attribute's kind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Boolean(bool),
+    #[schema(value_type = f64)]
+    Number(Number),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AttributeFilterError {
+    #[error("there's no attribute called `{0}`")]
+    UnknownAttribute(String),
+    #[error("`{key}` can't be `{value}`, it should be {expected}")]
+    InvalidValue { key: String, value: String, expected: String },
+    #[error("`{key}` needs a value, {expected}")]
+    MissingValue { key: String, expected: String },
+}
+
+/// Every attribute, in the order they're defined in.
+pub fn definitions() -> &'static [AttributeDefinition] {
+    static DEFINITIONS: OnceLock<Vec<AttributeDefinition>> = OnceLock::new();
+    DEFINITIONS.get_or_init(|| serde_json::from_str(SCHEMA).expect("data/attributes.json is valid"))
+}
+
+fn definition(key: &str) -> Option<&'static AttributeDefinition> {
+    definitions().iter().find(|definition| definition.key == key)
+}
+
+impl AttributeKind {
+    /// What a value of this kind looks like, for error messages.
+    fn expected(&self) -> String {
+        match self {
+            AttributeKind::Boolean => "true or false".into(),
+            AttributeKind::Enum { values } => format!("one of {}", values.join(", ")),
+            AttributeKind::Number { min, max, integer } => {
+                let number = if *integer { "a whole number" } else { "a number" };
+                match (min, max) {
+                    (Some(min), Some(max)) => format!("{number} from {min} to {max}"),
+                    (Some(min), None) => format!("{number} of at least {min}"),
+                    (None, Some(max)) => format!("{number} of at most {max}"),
+                    (None, None) => number.into(),
+                }
+            }
+        }
+    }
+
+    fn accepts(&self, value: &AttributeValue) -> bool {
+        match (self, value) {
+            (AttributeKind::Boolean, AttributeValue::Boolean(_)) => true,
+            (AttributeKind::Enum { values }, AttributeValue::Text(text)) => values.contains(text),
+            (AttributeKind::Number { min, max, integer }, AttributeValue::Number(number)) => {
+                let value = number.as_f64().unwrap_or(f64::NAN);
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max) && (!integer || value.fract() == 0.0)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Attributes a business can't have: ones that aren't in the schema, and values of the wrong kind or out of range.
+pub fn problems(attributes: &BTreeMap<String, AttributeValue>) -> Vec<String> {
+    attributes
+        .iter()
+        .filter_map(|(key, value)| match definition(key) {
+            None => Some(format!("there's no attribute called `{key}`")),
+            Some(definition) if !definition.kind.accepts(value) => Some(format!(
+                "attribute `{key}` should be {}, not {}",
+                definition.kind.expected(),
+                serde_json::to_string(value).unwrap_or_default()
+            )),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    /// The attribute has any of these values.
+    AnyOf(&'static str, Vec<AttributeValue>),
+    /// The attribute is a number within the range, both ends included.
+    Between(&'static str, Option<f64>, Option<f64>),
+}
+
+/// Parsed `?attributes=`, every condition has to hold for a business to match.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AttributeFilter {
+    conditions: Vec<Condition>,
+}
+
+impl AttributeFilter {
+    pub fn parse(input: &str) -> Result<AttributeFilter, AttributeFilterError> {
+        let conditions = input
+            .split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(parse_condition)
+            .collect::<Result<_, _>>()?;
+        Ok(AttributeFilter { conditions })
+    }
+
+    pub fn matches(&self, attributes: &BTreeMap<String, AttributeValue>) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::AnyOf(key, values) => attributes.get(*key).is_some_and(|value| values.contains(value)),
+            Condition::Between(key, low, high) => match attributes.get(*key) {
+                Some(AttributeValue::Number(number)) => number.as_f64().is_some_and(|value| {
+                    low.is_none_or(|low| value >= low) && high.is_none_or(|high| value <= high)
+                }),
+                _ => false,
+            },
+        })
+    }
+}
+
+fn parse_condition(term: &str) -> Result<Condition, AttributeFilterError> {
+    let (key, value) = match term.split_once(':') {
+        Some((key, value)) => (key.trim(), Some(value.trim())),
+        None => (term, None),
+    };
+    let definition = definition(key).ok_or_else(|| AttributeFilterError::UnknownAttribute(key.to_string()))?;
+    let key = definition.key.as_str();
+    let invalid = |value: &str| AttributeFilterError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        expected: definition.kind.expected(),
+    };
+
+    match (&definition.kind, value) {
+        (AttributeKind::Boolean, None) => Ok(Condition::AnyOf(key, vec![AttributeValue::Boolean(true)])),
+        (AttributeKind::Boolean, Some(value)) => match value {
+            "true" | "yes" => Ok(Condition::AnyOf(key, vec![AttributeValue::Boolean(true)])),
+            "false" | "no" => Ok(Condition::AnyOf(key, vec![AttributeValue::Boolean(false)])),
+            _ => Err(invalid(value)),
+        },
+        (AttributeKind::Enum { values }, Some(wanted)) => {
+            let wanted: Vec<AttributeValue> = wanted
+                .split('|')
+                .map(|option| match values.iter().find(|value| value.eq_ignore_ascii_case(option.trim())) {
+                    Some(value) => Ok(AttributeValue::Text(value.clone())),
+                    None => Err(invalid(option)),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Condition::AnyOf(key, wanted))
+        }
+        (AttributeKind::Number { .. }, Some(range)) => {
+            let number = |end: &str| -> Result<Option<f64>, AttributeFilterError> {
+                match end.trim() {
+                    "" => Ok(None),
+                    end => end.parse().map(Some).map_err(|_| invalid(range)),
+                }
+            };
+            match range.split_once("..") {
+                Some((low, high)) => Ok(Condition::Between(key, number(low)?, number(high)?)),
+                None => {
+                    let exact = number(range)?.ok_or_else(|| invalid(range))?;
+                    Ok(Condition::Between(key, Some(exact), Some(exact)))
+                }
+            }
+        }
+        (kind, None) => Err(AttributeFilterError::MissingValue { key: key.to_string(), expected: kind.expected() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn attributes(document: serde_json::Value) -> BTreeMap<String, AttributeValue> {
+        serde_json::from_value(document).unwrap()
+    }
+
+    #[test]
+    fn values_are_checked_against_the_schema() {
+        let fine = attributes(json!({ "outdoor_seating": true, "wifi": "free", "price_range": 2 }));
+        assert!(problems(&fine).is_empty());
+
+        let wrong = attributes(json!({ "outdoor_seating": "yes", "wifi": "fast", "price_range": 2.5, "pool": true }));
+        assert_eq!(problems(&wrong), [
+            "attribute `outdoor_seating` should be true or false, not \"yes\"",
+            "there's no attribute called `pool`",
+            "attribute `price_range` should be a whole number from 1 to 4, not 2.5",
+            "attribute `wifi` should be one of free, paid, none, not \"fast\"",
+        ]);
+    }
+
+    #[test]
+    fn filters_combine_booleans_enums_and_ranges() {
+        let cafe = attributes(json!({ "outdoor_seating": true, "wifi": "free", "price_range": 1 }));
+        let bistro = attributes(json!({ "outdoor_seating": true, "wifi": "paid", "price_range": 3 }));
+        let filter = AttributeFilter::parse("outdoor_seating, wifi:free|Paid, price_range:..2").unwrap();
+        assert!(filter.matches(&cafe));
+        assert!(!filter.matches(&bistro));
+        // Left out isn't the same as false.
+        assert!(!AttributeFilter::parse("dogs_allowed:false").unwrap().matches(&cafe));
+
+        assert_eq!(AttributeFilter::parse("pool"), Err(AttributeFilterError::UnknownAttribute("pool".into())));
+        assert!(matches!(AttributeFilter::parse("price_range:cheap"), Err(AttributeFilterError::InvalidValue { .. })));
+        assert!(matches!(AttributeFilter::parse("wifi"), Err(AttributeFilterError::MissingValue { .. })));
+    }
+}
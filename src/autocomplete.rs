@@ -0,0 +1,313 @@
+// Typo-tolerant autocomplete over business and category names. Every name goes into a trie, once from its start and
+// once from each later word, so "doyle" finds "Toy, Doyle and Kuvalis". Lookups walk the trie carrying a row of the
+// Levenshtein table for the query, and give up on a branch as soon as every cell in the row is over the allowed
+// distance, so only the few branches close to the query are ever visited.
+//
+// The index observes the store like the search index does, and category names are counted, so a category is
+// suggested for as long as at least one business is in it.
+use std::collections::{BTreeMap, HashMap};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::business::BusinessResponse;
+use crate::store::StoreObserver;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    Business,
+    Category,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suggestion {
+    /// The name as it was written, not normalized.
+    pub text: String,
+    pub kind: SuggestionKind,
+    /// How many edits away from the query the closest matching prefix is.
+    pub distance: usize,
+}
+
+/// What a trie key leads to. The same name is under several keys, one per word it can be found from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Entry {
+    kind: SuggestionKind,
+    text: String,
+    /// Whether the key is the whole name rather than starting at a later word.
+    from_start: bool,
+}
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<char, Node>,
+    /// Entries whose key ends here, with how many businesses put them there.
+    entries: HashMap<Entry, usize>,
+}
+
+impl Node {
+    fn insert(&mut self, key: &[char], entry: Entry) {
+        match key.split_first() {
+            Some((c, rest)) => self.children.entry(*c).or_default().insert(rest, entry),
+            None => *self.entries.entry(entry).or_insert(0) += 1,
+        }
+    }
+
+    /// Removes one count of `entry` under `key`, dropping nodes that end up empty. Returns whether this node is empty.
+    fn remove(&mut self, key: &[char], entry: &Entry) -> bool {
+        match key.split_first() {
+            Some((c, rest)) => {
+                if let Some(child) = self.children.get_mut(c) {
+                    if child.remove(rest, entry) {
+                        self.children.remove(c);
+                    }
+                }
+            }
+            None => {
+                if let Some(count) = self.entries.get_mut(entry) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.entries.remove(entry);
+                    }
+                }
+            }
+        }
+        self.children.is_empty() && self.entries.is_empty()
+    }
+
+    /// Everything at or below this node, as candidates `distance` edits from the query.
+    fn collect(&self, distance: usize, candidates: &mut HashMap<(SuggestionKind, String), Candidate>) {
+        for (entry, count) in &self.entries {
+            let candidate = Candidate { distance, from_start: entry.from_start, count: *count };
+            candidates
+                .entry((entry.kind, entry.text.clone()))
+                .and_modify(|best| {
+                    if candidate.rank_key() < best.rank_key() {
+                        *best = candidate;
+                    }
+                })
+                .or_insert(candidate);
+        }
+        for child in self.children.values() {
+            child.collect(distance, candidates);
+        }
+    }
+
+    /// Descends into every child that can still match `query` within `max_distance`. `row` is the Levenshtein row
+    /// for the key so far against every prefix of the query.
+    fn search(
+        &self,
+        query: &[char],
+        row: &[usize],
+        max_distance: usize,
+        candidates: &mut HashMap<(SuggestionKind, String), Candidate>,
+    ) {
+        for (c, child) in &self.children {
+            let mut next = Vec::with_capacity(row.len());
+            next.push(row[0] + 1);
+            for (index, query_char) in query.iter().enumerate() {
+                let substitution = row[index] + usize::from(query_char != c);
+                next.push(substitution.min(row[index + 1] + 1).min(next[index] + 1));
+            }
+            let distance = next[query.len()];
+            if distance <= max_distance {
+                child.collect(distance, candidates);
+            }
+            if next.iter().min().is_some_and(|closest| *closest <= max_distance) {
+                child.search(query, &next, max_distance, candidates);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: usize,
+    from_start: bool,
+    count: usize,
+}
+
+impl Candidate {
+    /// Lower is better: closer matches, then matches from the start of the name, then more popular names.
+    fn rank_key(&self) -> (usize, bool, std::cmp::Reverse<usize>) {
+        (self.distance, !self.from_start, std::cmp::Reverse(self.count))
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    root: Node,
+    /// What each business put in the trie, so it can be taken out again when the business changes.
+    contributed: HashMap<String, Vec<(SuggestionKind, String)>>,
+}
+
+#[derive(Default)]
+pub struct Autocomplete {
+    index: RwLock<Index>,
+}
+
+impl Autocomplete {
+    pub fn new() -> Self {
+        Autocomplete::default()
+    }
+
+    /// Up to `limit` names that start with something close to `query`, best first.
+    pub fn suggest(&self, query: &str, limit: usize) -> Vec<Suggestion> {
+        let query: Vec<char> = normalize(query).chars().collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let max_distance = max_distance(query.len());
+        let first_row: Vec<usize> = (0..=query.len()).collect();
+        let mut candidates = HashMap::new();
+        self.index.read().root.search(&query, &first_row, max_distance, &mut candidates);
+
+        let mut ranked: Vec<((SuggestionKind, String), Candidate)> = candidates.into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| {
+            a.rank_key()
+                .cmp(&b.rank_key())
+                .then_with(|| a_id.1.len().cmp(&b_id.1.len()))
+                .then_with(|| a_id.cmp(b_id))
+        });
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|((kind, text), candidate)| Suggestion { text, kind, distance: candidate.distance })
+            .collect()
+    }
+}
+
+impl StoreObserver for Autocomplete {
+    fn business_changed(&self, name: &str, business: Option<&BusinessResponse>) {
+        let contributed = business.map(|business| {
+            let business = &business.business;
+            vec![
+                (SuggestionKind::Business, business.name.clone()),
+                (SuggestionKind::Category, business.category.main_category.clone()),
+                (SuggestionKind::Category, business.category.subcategory.clone()),
+            ]
+        });
+        // Only a new name or category changes the suggestions.
+        if self.index.read().contributed.get(name) == contributed.as_ref() {
+            return;
+        }
+
+        let mut index = self.index.write();
+        for (kind, text) in index.contributed.remove(name).unwrap_or_default() {
+            for (key, from_start) in keys(&text) {
+                index.root.remove(&key, &Entry { kind, text: text.clone(), from_start });
+            }
+        }
+        let contributed = match contributed {
+            Some(contributed) => contributed,
+            None => return,
+        };
+        for (kind, text) in &contributed {
+            for (key, from_start) in keys(text) {
+                index.root.insert(&key, Entry { kind: *kind, text: text.clone(), from_start });
+            }
+        }
+        index.contributed.insert(name.to_string(), contributed);
+    }
+}
+
+/// How many typos a query of this many characters can have. Short queries have to be typed right, or everything
+/// would match them.
+fn max_distance(query_len: usize) -> usize {
+    match query_len {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// Lowercase words separated by single spaces, so punctuation and spacing never count as typos.
+fn normalize(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The trie keys for a name: the whole name, then the name from each later word on.
+fn keys(text: &str) -> Vec<(Vec<char>, bool)> {
+    let normalized: Vec<char> = normalize(text).chars().collect();
+    let mut keys = vec![(normalized.clone(), true)];
+    for (index, c) in normalized.iter().enumerate() {
+        if *c == ' ' {
+            keys.push((normalized[index + 1..].to_vec(), false));
+        }
+    }
+    keys.retain(|(key, _)| !key.is_empty());
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::business::{Business, Category};
+
+    fn business(name: &str, main_category: &str, subcategory: &str) -> BusinessResponse {
+        BusinessResponse::new(
+            Business {
+                name: name.into(),
+                street_addr: "1 Main St".into(),
+                city: "Houston".into(),
+                state: "Texas".into(),
+                zip: "77002".parse().unwrap(),
+                phone_num: "713-555-0100".parse().unwrap(),
+                category: Category { main_category: main_category.into(), subcategory: subcategory.into() },
+                category_ids: Vec::new(),
+                email: None,
+                website: None,
+                latitude: None,
+                longitude: None,
+                hours: None,
+                attributes: Default::default(),
+            },
+            None,
+            None,
+        )
+    }
+
+    fn autocomplete(businesses: &[BusinessResponse]) -> Autocomplete {
+        let autocomplete = Autocomplete::new();
+        for business in businesses {
+            autocomplete.business_changed(&business.business.name, Some(business));
+        }
+        autocomplete
+    }
+
+    #[test]
+    fn typos_and_later_words_still_match() {
+        let autocomplete = autocomplete(&[
+            business("Toy, Doyle and Kuvalis", "Restaurant", "Pizza"),
+            business("Toys R Fun", "Shopping", "Toys"),
+        ]);
+        let texts = |query: &str| autocomplete.suggest(query, 10).into_iter().map(|s| s.text).collect::<Vec<_>>();
+
+        assert_eq!(texts("toy doyel"), ["Toy, Doyle and Kuvalis"]);
+        assert_eq!(texts("kuvails"), ["Toy, Doyle and Kuvalis"]);
+        // Exact prefixes come before the ones with a typo, whole names before later words.
+        assert_eq!(texts("toy"), ["Toys", "Toys R Fun", "Toy, Doyle and Kuvalis"]);
+        assert_eq!(autocomplete.suggest("pizz", 10)[0], Suggestion {
+            text: "Pizza".into(),
+            kind: SuggestionKind::Category,
+            distance: 0
+        });
+    }
+
+    #[test]
+    fn categories_stay_while_a_business_uses_them() {
+        let autocomplete = autocomplete(&[
+            business("Luigi's", "Restaurant", "Pizza"),
+            business("Slice", "Restaurant", "Pizza"),
+        ]);
+        autocomplete.business_changed("Luigi's", None);
+        assert_eq!(autocomplete.suggest("pizza", 10).len(), 1);
+        autocomplete.business_changed("Slice", None);
+        assert!(autocomplete.suggest("pizza", 10).is_empty());
+        assert!(autocomplete.index.read().root.children.is_empty());
+    }
+}
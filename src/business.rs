@@ -1,47 +1,274 @@
 #![allow(unused)]
+use std::collections::{BTreeMap, HashMap, HashSet};
+
 use actix_web::{body::BoxBody, http::header::ContentType, HttpResponse, Responder};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use json_patch::PatchOperation;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use thiserror::Error;
+use utoipa::ToSchema;
 use validator_derive::Validate;
+
+use crate::address::ZipCode;
+use crate::attributes::AttributeValue;
+use crate::hours::Hours;
+use crate::phone::PhoneNumber;
+use crate::taxonomy::CategoryId;
 // use std::sync::Arc;
 
-#[derive(Deserialize, Serialize, Clone)]
+// The derived (de)serializers are the plain field by field ones, `Serialize` below adds `is_open_now` on top.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(remote = "Self")]
 pub struct Business {
     pub name: String,
+    #[serde(deserialize_with = "crate::address::deserialize_street")]
     pub street_addr: String,
+    #[serde(deserialize_with = "crate::address::deserialize_city")]
     pub city: String,
+    /// USPS code, e.g. `TX`. Full state names are accepted and stored as the code.
+    #[serde(deserialize_with = "crate::address::deserialize_state")]
     pub state: String,
-    pub zip: usize,
-    pub phone_num: usize, // TODO: Make a struct for phone numbers if it's important.
+    #[schema(value_type = String, example = "77002-1234")]
+    pub zip: ZipCode,
+    #[schema(value_type = String, example = "+17135980157")]
+    pub phone_num: PhoneNumber,
     pub category: Category,
+    /// IDs from the category tree, a business can be in any number of categories. Filled in from `category`
+    /// when left out.
+    #[serde(default)]
+    pub category_ids: Vec<CategoryId>,
     pub email: Option<String>,
     pub website: Option<String>,
+    /// Where the business is. Without coordinates it's placed at the centre of its ZIP code.
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Weekly and holiday opening hours. Businesses are sent with `is_open_now` worked out from these (`null`
+    /// without hours), it's ignored when sent in with a whole business and refused in a patch.
+    #[serde(default)]
+    pub hours: Option<Hours>,
+    /// Amenities and the like, keyed by the attribute names listed at `/attributes`, e.g. `"outdoor_seating": true`.
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub attributes: BTreeMap<String, AttributeValue>,
+}
+
+impl Serialize for Business {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct WithOpenNow<'a> {
+            #[serde(flatten, with = "Business")]
+            business: &'a Business,
+            is_open_now: Option<bool>,
+        }
+        WithOpenNow { business: self, is_open_now: self.hours.as_ref().map(Hours::is_open_now) }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Business {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Business::deserialize(deserializer)
+    }
+}
+
+/// A partial update to a `Business`. Patches only ever see the business fields, so reviews and photos can't be touched.
+pub enum BusinessPatch {
+    /// RFC 7396, `application/merge-patch+json`.
+    Merge(Value),
+    /// RFC 6902, `application/json-patch+json`.
+    Json(json_patch::Patch),
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+/// Fields a client gets back with a business but can't change through a patch, since they're worked out from the
+/// rest (`is_open_now` from the hours). Anything else that isn't a business field is turned away as unknown.
+const READ_ONLY_FIELDS: &[&str] = &["is_open_now"];
+
+impl BusinessPatch {
+    /// The first read-only field the patch would change, if any. `test` operations only look, so they're allowed.
+    pub fn read_only_field(&self) -> Option<&str> {
+        let read_only = |field: &str| READ_ONLY_FIELDS.iter().copied().find(|read_only| *read_only == field);
+        let in_object = |value: &Value| {
+            value.as_object().and_then(|object| object.keys().find_map(|field| read_only(field)))
+        };
+        match self {
+            BusinessPatch::Merge(merge_patch) => in_object(merge_patch),
+            BusinessPatch::Json(json_patch) => json_patch.0.iter().find_map(|operation| {
+                let (paths, value) = match operation {
+                    PatchOperation::Add(add) => (vec![&add.path], Some(&add.value)),
+                    PatchOperation::Remove(remove) => (vec![&remove.path], None),
+                    PatchOperation::Replace(replace) => (vec![&replace.path], Some(&replace.value)),
+                    PatchOperation::Move(move_) => (vec![&move_.from, &move_.path], None),
+                    PatchOperation::Copy(copy) => (vec![&copy.path], None),
+                    PatchOperation::Test(_) => return None,
+                };
+                // An empty path is the whole business, so it's the fields of the new value that count.
+                paths.into_iter().find_map(|path| match path.first() {
+                    Some(field) => read_only(&field.decoded()),
+                    None => value.and_then(in_object),
+                })
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BusinessPatchError {
+    #[error("the patch could not be applied: {0}")]
+    Apply(#[from] json_patch::PatchError),
+    #[error("the patch touches a field that isn't part of a business: {0}")]
+    UnknownField(String),
+    #[error("the patch changes a field that can't be changed: {0}")]
+    ReadOnlyField(String),
+    #[error("the patched business is invalid: {0}")]
+    Invalid(#[from] serde_json::Error),
+}
+
+impl Business {
+    /// Everything wrong with the business that should keep it out of the store. Empty if it's fine.
+    ///
+    /// An address that's the same as `previous`'s isn't checked again: records from before the address checks (the
+    /// seed data has ZIP codes from other states) stay editable as long as the address is left alone.
+    pub fn problems(&self, previous: Option<&Business>) -> Vec<String> {
+        let same_address = previous.is_some_and(|previous| {
+            previous.street_addr == self.street_addr && previous.state == self.state && previous.zip == self.zip
+        });
+        let mut problems = if same_address { Vec::new() } else { crate::address::address_problems(&self.state, &self.zip) };
+        problems.extend(crate::geo::coordinate_problems(self));
+        problems.extend(crate::attributes::problems(&self.attributes));
+        if let Some(hours) = &self.hours {
+            problems.extend(hours.problems().into_iter().map(|problem| format!("hours: {problem}")));
+        }
+        problems
+    }
+
+    /// Applies a patch to a copy of the business. The original is only replaced by the caller if this succeeds.
+    pub fn apply_patch(&self, patch: &BusinessPatch) -> Result<Business, BusinessPatchError> {
+        if let Some(field) = patch.read_only_field() {
+            return Err(BusinessPatchError::ReadOnlyField(field.to_string()));
+        }
+        let mut document = serde_json::to_value(self)?;
+        match patch {
+            BusinessPatch::Merge(merge_patch) => json_patch::merge(&mut document, merge_patch),
+            BusinessPatch::Json(json_patch) => json_patch::patch(&mut document, json_patch)?,
+        }
+
+        // Anything outside of the known business fields (e.g. "reviews") gets rejected instead of silently dropped.
+        let known_fields = serde_json::to_value(self)?;
+        if let (Some(patched), Some(known)) = (document.as_object(), known_fields.as_object()) {
+            if let Some(field) = patched.keys().find(|field| !known.contains_key(*field)) {
+                return Err(BusinessPatchError::UnknownField(field.clone()));
+            }
+        }
+        Ok(serde_json::from_value(document)?)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 pub struct Category {
     pub main_category: String,
     pub subcategory: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Validate)]
+#[derive(Deserialize, Serialize, Clone, Validate, ToSchema)]
 pub struct Review {
     #[validate(range(min = 0, max = 5))]
+    #[schema(minimum = 0, maximum = 5)]
     pub rating: usize,
     #[validate(range(min = 1, max = 4))]
+    #[schema(minimum = 1, maximum = 4)]
     pub dollar_signs: usize,
     pub review: Option<String>,
+    /// Bumped by the server on every edit, exposed as the review's ETag.
+    #[serde(default)]
+    pub version: u64,
+    /// The business owner's public reply. Only written through the owner response endpoints, never with the review.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub owner_response: Option<OwnerResponse>,
+}
+
+/// Longest owner response, in characters.
+pub const MAX_OWNER_RESPONSE_CHARS: usize = 2000;
+
+/// A business owner's reply to a review. A review has at most one.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct OwnerResponse {
+    /// Who wrote the reply: the owner at the time, or an admin stepping in.
+    pub owner: String,
+    pub text: String,
+    #[schema(value_type = String, example = "2026-10-18T21:00:00Z")]
+    pub responded_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>)]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Bumped by the server on every edit, exposed as the response's ETag.
+    pub version: u64,
+}
+
+/// What an owner sends to reply to a review, or to change their reply.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OwnerResponseText {
+    pub text: String,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum OwnerResponseError {
+    #[error("there's no review by {0}")]
+    NoReview(String),
+    /// Several reviews are filed under the name (only possible for "Anonymous"), so a reply can't pick one.
+    #[error("there's more than one review by {0}, so it's not clear which one is meant")]
+    Ambiguous(String),
+    #[error("the review already has a response, edit that one instead")]
+    AlreadyResponded,
+    #[error("the review has no response")]
+    NoResponse,
+    #[error("the response can't be empty")]
+    Empty,
+    #[error("the response can be at most {MAX_OWNER_RESPONSE_CHARS} characters")]
+    TooLong,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, ToSchema)]
 pub struct Photo {
     pub user_name: String,
     pub photo_id: usize,
     pub photo_url: String,
     pub photo_caption: Option<String>,
+    /// Bumped by the server on every edit, exposed as the photo's ETag.
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// What a client sends when uploading a photo. The uploader comes from the route and the ID is handed out by the server.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewPhoto {
+    pub photo_url: String,
+    pub photo_caption: Option<String>,
+}
+
+/// The full display order for a business' photos, every photo ID exactly once.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+pub struct PhotoOrder {
+    pub photo_ids: Vec<usize>,
+}
+
+/// Picks the photo shown first on the business page. `null` clears the cover photo.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+pub struct CoverPhoto {
+    pub photo_id: Option<usize>,
+}
+
+/// The only thing a user is allowed to change on a photo they've uploaded is the caption.
+/// Anything else in the body (a new url, a different owner, ...) gets rejected by the extractor.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PhotoCaptionUpdate {
+    pub photo_caption: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+/// Every review of a business, as `[reviewer_name, review]` pairs.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 pub struct UserReviews(Vec<(String, Review)>);
 
 /// The User reviews impl deals with working with reviews on a lower level.
@@ -53,52 +280,270 @@ impl UserReviews {
     // Adds a review to the list and checks if the user has already reviewed the business. If they have then it will return an error.
     fn add_review(&mut self, user: String, review: Review) {
         // If we find any reviews with the same user, then we will return withoutt doing anything.
-        if user == "Anonymous" {
-            self.0.push((user, review));
-        } else if self.0.iter().any(|(user_name, _)| user_name == &user) {
-            return;
-        } else {
+        if user == "Anonymous" || !self.0.iter().any(|(user_name, _)| user_name == &user) {
             self.0.push((user, review));
         }
     }
 
     // Finds a review by the user name for a specific business.
     fn get_review(&self, user_name: String) -> Option<&Review> {
-        Some(
-            &self
-                .0
-                .iter()
-                .find(|(user_name_in_review, _)| user_name_in_review == &user_name)
-                .unwrap()
-                .1,
-        )
+        self.0
+            .iter()
+            .find(|(user_name_in_review, _)| user_name_in_review == &user_name)
+            .map(|(_, review)| review)
+    }
+
+    /// Where the only review by `user_name` is. Several count as an error, unlike `get_review` which goes for the first.
+    fn only_review(&self, user_name: &str) -> Result<usize, OwnerResponseError> {
+        let mut positions = self.0.iter().enumerate().filter(|(_, (user_name_in_review, _))| user_name_in_review == user_name);
+        match (positions.next(), positions.next()) {
+            (Some((position, _)), None) => Ok(position),
+            (Some(_), Some(_)) => Err(OwnerResponseError::Ambiguous(user_name.to_string())),
+            (None, _) => Err(OwnerResponseError::NoReview(user_name.to_string())),
+        }
     }
 
     fn delete_review(&mut self, user: String) {
         // Checks through the array and deletes anything that matches the username.
-        if user == "Anonymous" {
-            return;
-        } else {
+        if user != "Anonymous" {
             self.0.retain(|(user_name, _)| user_name != &user);
         }
     }
+
+    /// Every review with the name of the user who wrote it.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Review)> {
+        self.0.iter().map(|(user_name, review)| (user_name.as_str(), review))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+/// What merging a duplicate into a business moved over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct MergeSummary {
+    pub reviews_moved: usize,
+    /// Reviews left behind because the same user had reviewed the surviving business too.
+    pub reviews_dropped: usize,
+    pub photos_moved: usize,
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 pub struct BusinessResponse {
     pub business: Business,
     pub reviews: Option<UserReviews>,
+    /// Photos are kept in display order.
     pub photos: Option<Vec<Photo>>,
+    #[serde(default)]
+    pub cover_photo_id: Option<usize>,
+    /// The ID the next uploaded photo gets. IDs are never reused within a business, even after deletes.
+    #[serde(default)]
+    pub next_photo_id: usize,
+    /// Bumped on every change to the business or anything nested in it, exposed as the business' ETag.
+    #[serde(default)]
+    pub version: u64,
+    /// The user who claimed the business. Only set by verifying a claim, or by an admin.
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
 impl BusinessResponse {
     pub fn new(business: Business, reviews: Option<UserReviews>, photos: Option<Vec<Photo>>) -> Self {
-        BusinessResponse { business, reviews, photos}
+        let next_photo_id = photos
+            .iter()
+            .flatten()
+            .map(|photo| photo.photo_id + 1)
+            .max()
+            .unwrap_or(0);
+        BusinessResponse { business, reviews, photos, cover_photo_id: None, next_photo_id, version: 0, owner: None }
+    }
+
+    /// Marks the business as changed, so any ETag handed out before is stale.
+    pub fn touch(&mut self) {
+        self.version += 1;
+    }
+
+    pub fn review_version(&self, user: &str) -> Option<u64> {
+        self.reviews
+            .as_ref()
+            .and_then(|reviews| reviews.get_review(user.to_string()))
+            .map(|review| review.version)
+    }
+
+    /// The version of the reply to `reviewer`'s review, if there is one. Fails the same way the reply endpoints do when
+    /// there's no single review by `reviewer`.
+    pub fn owner_response_version(&self, reviewer: &str) -> Result<Option<u64>, OwnerResponseError> {
+        let review = match &self.reviews {
+            Some(reviews) => &reviews.0[reviews.only_review(reviewer)?].1,
+            None => return Err(OwnerResponseError::NoReview(reviewer.to_string())),
+        };
+        Ok(review.owner_response.as_ref().map(|response| response.version))
+    }
+
+    /// Replies to `reviewer`'s review, signed by `author`. Whether they're allowed to is up to the caller to check.
+    pub fn add_owner_response(&mut self, author: &str, reviewer: &str, text: &str) -> Result<OwnerResponse, OwnerResponseError> {
+        let text = response_text(text)?;
+        let review = self.only_review_mut(reviewer)?;
+        if review.owner_response.is_some() {
+            return Err(OwnerResponseError::AlreadyResponded);
+        }
+        let response = OwnerResponse { owner: author.to_string(), text, responded_at: Utc::now(), edited_at: None, version: 1 };
+        review.owner_response = Some(response.clone());
+        self.touch();
+        Ok(response)
+    }
+
+    /// Changes the reply to `reviewer`'s review, which is signed by `author` from then on.
+    pub fn update_owner_response(&mut self, author: &str, reviewer: &str, text: &str) -> Result<OwnerResponse, OwnerResponseError> {
+        let text = response_text(text)?;
+        let response = self.only_review_mut(reviewer)?.owner_response.as_mut().ok_or(OwnerResponseError::NoResponse)?;
+        response.owner = author.to_string();
+        response.text = text;
+        response.edited_at = Some(Utc::now());
+        response.version += 1;
+        let response = response.clone();
+        self.touch();
+        Ok(response)
+    }
+
+    pub fn delete_owner_response(&mut self, reviewer: &str) -> Result<OwnerResponse, OwnerResponseError> {
+        let response = self.only_review_mut(reviewer)?.owner_response.take().ok_or(OwnerResponseError::NoResponse)?;
+        self.touch();
+        Ok(response)
+    }
+
+    /// `reviewer`'s review, as long as they only have the one.
+    fn only_review_mut(&mut self, reviewer: &str) -> Result<&mut Review, OwnerResponseError> {
+        match &mut self.reviews {
+            Some(reviews) => {
+                let position = reviews.only_review(reviewer)?;
+                Ok(&mut reviews.0[position].1)
+            }
+            None => Err(OwnerResponseError::NoReview(reviewer.to_string())),
+        }
+    }
+
+    pub fn photo_version(&self, photo_id: usize) -> Option<u64> {
+        self.photos
+            .iter()
+            .flatten()
+            .find(|photo| photo.photo_id == photo_id)
+            .map(|photo| photo.version)
+    }
+
+    /// Folds a duplicate of this business into it. Reviews and photos are moved over, photos getting new IDs, and
+    /// anything this business doesn't have yet (categories, attributes, hours, contact details) is taken from the
+    /// duplicate. When a user reviewed both, the review already here is the one that's kept. The duplicate's owner
+    /// doesn't come along, since owning the duplicate doesn't make them the survivor's owner.
+    pub fn absorb(&mut self, duplicate: BusinessResponse) -> MergeSummary {
+        let mut summary = MergeSummary::default();
+        for (user, review) in duplicate.reviews.into_iter().flat_map(|reviews| reviews.0) {
+            let reviews = self.reviews.get_or_insert_with(|| UserReviews(Vec::new()));
+            let before = reviews.len();
+            reviews.add_review(user, review);
+            if reviews.len() > before {
+                summary.reviews_moved += 1;
+            } else {
+                summary.reviews_dropped += 1;
+            }
+        }
+
+        let cover_photo_id = duplicate.cover_photo_id;
+        for mut photo in duplicate.photos.into_iter().flatten() {
+            let old_photo_id = photo.photo_id;
+            photo.photo_id = self.next_photo_id;
+            self.next_photo_id += 1;
+            if self.cover_photo_id.is_none() && cover_photo_id == Some(old_photo_id) {
+                self.cover_photo_id = Some(photo.photo_id);
+            }
+            self.photos.get_or_insert_with(Vec::new).push(photo);
+            summary.photos_moved += 1;
+        }
+
+        let (business, duplicate) = (&mut self.business, duplicate.business);
+        for id in duplicate.category_ids {
+            if !business.category_ids.contains(&id) {
+                business.category_ids.push(id);
+            }
+        }
+        for (key, value) in duplicate.attributes {
+            business.attributes.entry(key).or_insert(value);
+        }
+        business.hours = business.hours.take().or(duplicate.hours);
+        business.email = business.email.take().or(duplicate.email);
+        business.website = business.website.take().or(duplicate.website);
+        if business.latitude.is_none() && business.longitude.is_none() {
+            (business.latitude, business.longitude) = (duplicate.latitude, duplicate.longitude);
+        }
+        self.touch();
+        summary
+    }
+
+    /// Hands out IDs to the photos of a whole business from a client, keeping their order. The IDs in there can't be
+    /// trusted, so only a photo that's still there from `previous` (same ID, uploader and URL) keeps its ID. Every other
+    /// photo gets one `previous` never handed out, and the cover stays only if its photo did.
+    pub fn reassign_photo_ids(&mut self, previous: Option<&BusinessResponse>) {
+        let previous_photos: HashMap<usize, &Photo> = previous
+            .and_then(|previous| previous.photos.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|photo| (photo.photo_id, photo))
+            .collect();
+        let mut next_photo_id = previous.map_or(0, |previous| previous.next_photo_id);
+        let mut kept = HashSet::new();
+        for photo in self.photos.get_or_insert_with(Vec::new) {
+            let still_there = previous_photos
+                .get(&photo.photo_id)
+                .is_some_and(|old| old.user_name == photo.user_name && old.photo_url == photo.photo_url);
+            if !(still_there && kept.insert(photo.photo_id)) {
+                photo.photo_id = next_photo_id;
+                next_photo_id += 1;
+            }
+        }
+        self.next_photo_id = next_photo_id;
+        self.cover_photo_id = previous.and_then(|previous| previous.cover_photo_id).filter(|cover| kept.contains(cover));
+    }
+
+    /// Owner responses are only written through their own endpoints, so a whole business from a client keeps the ones
+    /// `previous` had (matching reviews by reviewer, in order) and drops anything else it came with.
+    pub fn keep_owner_responses(&mut self, previous: Option<&BusinessResponse>) {
+        let mut kept: HashMap<&str, Vec<Option<OwnerResponse>>> = HashMap::new();
+        for (user, review) in previous.and_then(|previous| previous.reviews.as_ref()).into_iter().flat_map(UserReviews::iter) {
+            kept.entry(user).or_default().push(review.owner_response.clone());
+        }
+        for kept in kept.values_mut() {
+            kept.reverse();
+        }
+        for (user, review) in self.reviews.iter_mut().flat_map(|reviews| reviews.0.iter_mut()) {
+            review.owner_response = kept.get_mut(user.as_str()).and_then(Vec::pop).flatten();
+        }
+    }
+
+    /// Versions every review and photo of a whole business from a client, whatever versions it claims to be at. They
+    /// move past the version they had in `previous` (reviews matched by reviewer, photos by the IDs `reassign_photo_ids`
+    /// kept) and never go below the business' own version, so no ETag handed out before a replace can match again,
+    /// not even one for a review that was deleted since. A new business starts them all at 1.
+    pub fn carry_nested_versions(&mut self, previous: Option<&BusinessResponse>) {
+        let floor = self.version.max(1);
+        for (user, review) in self.reviews.iter_mut().flat_map(|reviews| reviews.0.iter_mut()) {
+            let before = previous.and_then(|previous| previous.review_version(user)).unwrap_or(0);
+            review.version = (before + 1).max(floor);
+        }
+        for photo in self.photos.iter_mut().flatten() {
+            let before = previous.and_then(|previous| previous.photo_version(photo.photo_id)).unwrap_or(0);
+            photo.version = (before + 1).max(floor);
+        }
     }
 
     pub fn delete_business_review(&mut self, user: String) -> HttpResponse {
         if let Some(reviews) = &mut self.reviews {
             reviews.delete_review(user.clone());
+            self.touch();
             HttpResponse::Ok().json(json!({
                 "message": "Review deleted.",
                 "deleted_review": user,
@@ -110,9 +555,12 @@ impl BusinessResponse {
         }
     }
 
-    pub fn add_business_review(&mut self, user: String, review: Review) -> HttpResponse {
+    pub fn add_business_review(&mut self, user: String, mut review: Review) -> HttpResponse {
+        review.version = 1;
+        review.owner_response = None;
         if let Some(reviews) = &mut self.reviews {
             reviews.add_review(user.clone(), review.clone());
+            self.touch();
             HttpResponse::Ok().json(json!({
                 "message": "Review added.",
                 "added_review": review,
@@ -125,10 +573,15 @@ impl BusinessResponse {
         }
     }
 
-    pub fn update_business_review(&mut self, user: String, review: Review) -> HttpResponse {
+    pub fn update_business_review(&mut self, user: String, mut review: Review) -> HttpResponse {
         if let Some(reviews) = &mut self.reviews {
+            let old = reviews.get_review(user.clone());
+            review.version = old.map_or(1, |old| old.version + 1);
+            // Editing a review keeps the owner's reply to it.
+            review.owner_response = old.and_then(|old| old.owner_response.clone());
             reviews.delete_review(user.clone());
             reviews.add_review(user.clone(), review.clone());
+            self.touch();
             HttpResponse::Ok().json(json!({
                 "message": "Review updated.",
                 "updated_review": review,
@@ -157,10 +610,8 @@ impl BusinessResponse {
                 .iter()
                 .filter(|(name, review)| name == &user_name)
                 .collect::<Vec<_>>();
-            user_reviews
-                .sort_by(|(_, review_1), (_, review_2)| review_1.rating.cmp(&review_2.rating));
+            user_reviews.sort_by_key(|(_, review)| review.rating);
             // let index_at = page * per_page;
-            println!("Review posted");
             HttpResponse::Ok().json(user_reviews)
         } else {
             HttpResponse::Ok().json(json!({
@@ -169,51 +620,138 @@ impl BusinessResponse {
         }
     }
 
-    pub fn add_business_photo(&mut self, user_name: String, photo: Photo) -> HttpResponse {
-        if let Some(photos) = &mut self.photos {
-            photos.push(photo.clone());
-            HttpResponse::Ok().json(json!({
-                "message": "Photo added.",
-                "added_photo": photo.clone(),
-            }))
-        } else {
-            HttpResponse::Ok().json(json!({
-                "message": "No photos to add.",
-            }))
+    /// Adds a photo at the end of the display order, with an ID allocated by the server.
+    pub fn add_business_photo(&mut self, user_name: String, photo: NewPhoto) -> HttpResponse {
+        let photo = Photo {
+            user_name,
+            photo_id: self.next_photo_id,
+            photo_url: photo.photo_url,
+            photo_caption: photo.photo_caption,
+            version: 1,
+        };
+        self.next_photo_id += 1;
+        self.touch();
+        self.photos.get_or_insert_with(Vec::new).push(photo.clone());
+        HttpResponse::Created().json(json!({
+            "message": "Photo added.",
+            "added_photo": photo,
+        }))
+    }
+
+    /// Replaces the display order. The new order has to mention every photo exactly once.
+    pub fn reorder_business_photos(&mut self, order: PhotoOrder) -> HttpResponse {
+        let photos = self.photos.get_or_insert_with(Vec::new);
+        let mut current_ids: Vec<usize> = photos.iter().map(|photo| photo.photo_id).collect();
+        let mut requested_ids = order.photo_ids.clone();
+        current_ids.sort_unstable();
+        requested_ids.sort_unstable();
+        if current_ids != requested_ids {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "The new order must contain every photo ID of the business exactly once",
+                "photo_ids": current_ids,
+            }));
         }
+
+        photos.sort_by_key(|photo| order.photo_ids.iter().position(|id| *id == photo.photo_id));
+        self.touch();
+        HttpResponse::Ok().json(json!({
+            "message": "Photos reordered.",
+            "photo_ids": order.photo_ids,
+        }))
     }
 
+    pub fn set_cover_photo(&mut self, cover: CoverPhoto) -> HttpResponse {
+        if let Some(photo_id) = cover.photo_id {
+            let exists = self
+                .photos
+                .iter()
+                .flatten()
+                .any(|photo| photo.photo_id == photo_id);
+            if !exists {
+                return photo_not_found(photo_id);
+            }
+        }
+        self.cover_photo_id = cover.photo_id;
+        self.touch();
+        HttpResponse::Ok().json(json!({
+            "message": "Cover photo updated.",
+            "cover_photo_id": self.cover_photo_id,
+        }))
+    }
+
+    /// Deletes exactly one photo, and only if `user_name` is the one who uploaded it.
     pub fn delete_business_photo(&mut self, user_name: String, photo_id: usize) -> HttpResponse {
-        if let Some(photos) = &mut self.photos {
-            photos.retain(|photo| photo.user_name != user_name && photo.photo_id != photo_id);
-            HttpResponse::Ok().json(json!({
-                "message": "Photo deleted.",
-                "deleted_photo": photo_id,
-            }))
-        } else {
-            HttpResponse::Ok().json(json!({
-                "message": "No photos to delete.",
-            }))
+        let photos = match &mut self.photos {
+            Some(photos) => photos,
+            None => return photo_not_found(photo_id),
+        };
+        match photos.iter().position(|photo| photo.photo_id == photo_id) {
+            Some(index) if photos[index].user_name == user_name => {
+                let deleted_photo = photos.remove(index);
+                if self.cover_photo_id == Some(photo_id) {
+                    self.cover_photo_id = None;
+                }
+                self.touch();
+                HttpResponse::Ok().json(json!({
+                    "message": "Photo deleted.",
+                    "deleted_photo": deleted_photo,
+                }))
+            }
+            Some(_) => photo_not_owned(photo_id),
+            None => photo_not_found(photo_id),
         }
     }
 
-    pub fn update_business_photo(&mut self, user_name: String, photo: Photo) -> HttpResponse {
-        if let Some(photos) = &mut self.photos {
-            
-            photos.retain(|photo| photo.user_name != user_name);
-            photos.push(photo.clone());
-            HttpResponse::Ok().json(json!({
-                "message": "Photo updated.",
-                "updated_photo": photo,
-            }))
-        } else {
-            HttpResponse::Ok().json(json!({
-                "message": "No photos to update.",
-            }))
+    /// Changes the caption of a single photo. Photos are looked up by ID, and only the uploader can edit them.
+    pub fn update_business_photo(&mut self, user_name: String, photo_id: usize, update: PhotoCaptionUpdate) -> HttpResponse {
+        let photo = match self
+            .photos
+            .as_mut()
+            .and_then(|photos| photos.iter_mut().find(|photo| photo.photo_id == photo_id))
+        {
+            Some(photo) => photo,
+            None => return photo_not_found(photo_id),
+        };
+        if photo.user_name != user_name {
+            return photo_not_owned(photo_id);
         }
+        photo.photo_caption = update.photo_caption;
+        photo.version += 1;
+        let updated_photo = photo.clone();
+        self.touch();
+        HttpResponse::Ok().json(json!({
+            "message": "Photo updated.",
+            "updated_photo": updated_photo,
+        }))
     }
 }
 
+fn photo_not_found(photo_id: usize) -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "error": "Photo not found",
+        "photo_id": photo_id,
+    }))
+}
+
+fn photo_not_owned(photo_id: usize) -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "error": "Photo belongs to another user",
+        "photo_id": photo_id,
+    }))
+}
+
+/// The trimmed text of an owner response, if it's not empty or too long.
+fn response_text(text: &str) -> Result<String, OwnerResponseError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(OwnerResponseError::Empty);
+    }
+    if text.chars().count() > MAX_OWNER_RESPONSE_CHARS {
+        return Err(OwnerResponseError::TooLong);
+    }
+    Ok(text.to_string())
+}
+
 impl Responder for BusinessResponse {
     type Body = BoxBody;
 
@@ -225,3 +763,270 @@ impl Responder for BusinessResponse {
             .body(body)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use proptest::prelude::*;
+
+    const USERS: [&str; 3] = ["alice", "bob", "carol"];
+
+    fn test_business(photos: Vec<Photo>) -> BusinessResponse {
+        let business = Business {
+            name: "Belp Test Diner".into(),
+            street_addr: "1 Main St".into(),
+            city: "Corvallis".into(),
+            state: "Oregon".into(),
+            zip: "97331".parse().unwrap(),
+            phone_num: "541-555-0100".parse().unwrap(),
+            category: Category {
+                main_category: "Restaurant".into(),
+                subcategory: "Diner".into(),
+            },
+            category_ids: Vec::new(),
+            email: None,
+            website: None,
+            latitude: None,
+            longitude: None,
+            hours: None,
+            attributes: Default::default(),
+        };
+        BusinessResponse::new(business, None, Some(photos))
+    }
+
+    /// Photos with unique IDs (in a shuffled order) spread over a handful of users.
+    fn photos_strategy() -> impl Strategy<Value = Vec<Photo>> {
+        prop::collection::vec((0..USERS.len(), prop::option::of("[a-z ]{0,12}")), 0..16)
+            .prop_flat_map(|entries| {
+                let ids: Vec<usize> = (0..entries.len()).collect();
+                (Just(entries), Just(ids).prop_shuffle())
+            })
+            .prop_map(|(entries, ids)| {
+                entries
+                    .into_iter()
+                    .zip(ids)
+                    .map(|((user, caption), photo_id)| Photo {
+                        user_name: USERS[user].to_string(),
+                        photo_id,
+                        photo_url: format!("https://example.com/{photo_id}.png"),
+                        photo_caption: caption,
+                        version: 1,
+                    })
+                    .collect()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn delete_only_touches_the_requested_photo(
+            photos in photos_strategy(),
+            user in 0..USERS.len(),
+            photo_id in 0usize..20,
+        ) {
+            let user = USERS[user].to_string();
+            let mut business = test_business(photos.clone());
+            let status = business.delete_business_photo(user.clone(), photo_id).status();
+            let remaining = business.photos.unwrap();
+
+            match photos.iter().find(|photo| photo.photo_id == photo_id) {
+                Some(target) if target.user_name == user => {
+                    prop_assert_eq!(status, StatusCode::OK);
+                    let expected: Vec<Photo> = photos.iter().filter(|photo| photo.photo_id != photo_id).cloned().collect();
+                    prop_assert_eq!(remaining, expected);
+                }
+                Some(_) => {
+                    prop_assert_eq!(status, StatusCode::FORBIDDEN);
+                    prop_assert_eq!(remaining, photos);
+                }
+                None => {
+                    prop_assert_eq!(status, StatusCode::NOT_FOUND);
+                    prop_assert_eq!(remaining, photos);
+                }
+            }
+        }
+
+        #[test]
+        fn update_only_changes_the_requested_caption(
+            photos in photos_strategy(),
+            user in 0..USERS.len(),
+            photo_id in 0usize..20,
+            caption in prop::option::of("[a-z ]{0,12}"),
+        ) {
+            let user = USERS[user].to_string();
+            let mut business = test_business(photos.clone());
+            let update = PhotoCaptionUpdate { photo_caption: caption.clone() };
+            let status = business.update_business_photo(user.clone(), photo_id, update).status();
+            let updated = business.photos.unwrap();
+
+            let owns_target = photos.iter().any(|photo| photo.photo_id == photo_id && photo.user_name == user);
+            prop_assert_eq!(updated.len(), photos.len());
+            for (before, after) in photos.iter().zip(&updated) {
+                if owns_target && before.photo_id == photo_id {
+                    prop_assert_eq!(&after.photo_caption, &caption);
+                    prop_assert_eq!(after.version, before.version + 1);
+                    prop_assert_eq!(&after.photo_url, &before.photo_url);
+                    prop_assert_eq!(&after.user_name, &before.user_name);
+                } else {
+                    prop_assert_eq!(after, before);
+                }
+            }
+            prop_assert_eq!(status == StatusCode::OK, owns_target);
+        }
+    }
+
+    #[test]
+    fn photo_ids_are_never_reused() {
+        let mut business = test_business(Vec::new());
+        let new_photo = || NewPhoto { photo_url: "https://example.com/a.png".into(), photo_caption: None };
+        business.add_business_photo("alice".into(), new_photo());
+        business.add_business_photo("alice".into(), new_photo());
+        business.delete_business_photo("alice".into(), 1);
+        business.add_business_photo("alice".into(), new_photo());
+
+        let ids: Vec<usize> = business.photos.unwrap().iter().map(|photo| photo.photo_id).collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    #[test]
+    fn reorder_requires_every_photo_once() {
+        let photos = (0..3)
+            .map(|photo_id| Photo {
+                user_name: "alice".into(),
+                photo_id,
+                photo_url: format!("https://example.com/{photo_id}.png"),
+                photo_caption: None,
+                version: 1,
+            })
+            .collect();
+        let mut business = test_business(photos);
+
+        let status = business.reorder_business_photos(PhotoOrder { photo_ids: vec![2, 0] }).status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let status = business.reorder_business_photos(PhotoOrder { photo_ids: vec![2, 0, 0] }).status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let status = business.reorder_business_photos(PhotoOrder { photo_ids: vec![2, 0, 1] }).status();
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<usize> = business.photos.unwrap().iter().map(|photo| photo.photo_id).collect();
+        assert_eq!(ids, vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn deleting_the_cover_photo_clears_it() {
+        let mut business = test_business(Vec::new());
+        business.add_business_photo("alice".into(), NewPhoto { photo_url: "https://example.com/a.png".into(), photo_caption: None });
+        assert_eq!(business.set_cover_photo(CoverPhoto { photo_id: Some(7) }).status(), StatusCode::NOT_FOUND);
+        assert_eq!(business.set_cover_photo(CoverPhoto { photo_id: Some(0) }).status(), StatusCode::OK);
+        business.delete_business_photo("alice".into(), 0);
+        assert_eq!(business.cover_photo_id, None);
+    }
+
+    #[test]
+    fn absorbing_a_duplicate_moves_reviews_and_photos() {
+        let review = |rating| Review { rating, dollar_signs: 2, review: None, version: 1, owner_response: None };
+        let new_photo = || NewPhoto { photo_url: "https://example.com/a.png".into(), photo_caption: None };
+        let mut survivor = test_business(Vec::new());
+        survivor.reviews = Some(UserReviews(Vec::new()));
+        survivor.add_business_review("alice".into(), review(5));
+        survivor.add_business_photo("alice".into(), new_photo());
+        let mut duplicate = test_business(Vec::new());
+        duplicate.reviews = Some(UserReviews(Vec::new()));
+        duplicate.add_business_review("alice".into(), review(1));
+        duplicate.add_business_review("bob".into(), review(4));
+        duplicate.add_business_photo("bob".into(), new_photo());
+        duplicate.set_cover_photo(CoverPhoto { photo_id: Some(0) });
+        duplicate.business.website = Some("https://belp.example".into());
+
+        let summary = survivor.absorb(duplicate);
+        assert_eq!(summary, MergeSummary { reviews_moved: 1, reviews_dropped: 1, photos_moved: 1 });
+        let reviews = survivor.reviews.as_ref().unwrap();
+        assert_eq!(reviews.get_review("alice".into()).unwrap().rating, 5);
+        assert!(reviews.get_review("bob".into()).is_some());
+        let ids: Vec<usize> = survivor.photos.as_ref().unwrap().iter().map(|photo| photo.photo_id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(survivor.cover_photo_id, Some(1));
+        assert_eq!(survivor.business.website.as_deref(), Some("https://belp.example"));
+    }
+
+    #[test]
+    fn owner_responses_survive_review_edits() {
+        let review = |rating| Review { rating, dollar_signs: 2, review: None, version: 1, owner_response: None };
+        let mut business = test_business(Vec::new());
+        business.reviews = Some(UserReviews(Vec::new()));
+        business.add_business_review("alice".into(), review(2));
+
+        assert_eq!(business.add_owner_response("sam", "bob", "Thanks"), Err(OwnerResponseError::NoReview("bob".into())));
+        assert_eq!(business.add_owner_response("sam", "alice", "  "), Err(OwnerResponseError::Empty));
+        let added = business.add_owner_response("sam", "alice", " Sorry, we'll do better. ").unwrap();
+        assert_eq!(added.text, "Sorry, we'll do better.");
+        assert_eq!(business.add_owner_response("sam", "alice", "Again"), Err(OwnerResponseError::AlreadyResponded));
+
+        // Neither the reviewer editing their review nor sending a response of their own replaces it.
+        let mut edited = review(3);
+        edited.owner_response = Some(OwnerResponse { owner: "alice".into(), text: "Fake".into(), ..added.clone() });
+        business.update_business_review("alice".into(), edited);
+        let response_text = |business: &BusinessResponse| {
+            let review = business.reviews.as_ref().unwrap().get_review("alice".into()).unwrap();
+            review.owner_response.as_ref().unwrap().text.clone()
+        };
+        assert_eq!(response_text(&business), "Sorry, we'll do better.");
+        let updated = business.update_owner_response("sam", "alice", "Come back any time").unwrap();
+        assert_eq!((updated.version, updated.edited_at.is_some()), (2, true));
+        assert_eq!(response_text(&business), "Come back any time");
+        assert_eq!(business.owner_response_version("alice"), Ok(Some(2)));
+
+        assert!(business.delete_owner_response("alice").is_ok());
+        assert_eq!(business.delete_owner_response("alice"), Err(OwnerResponseError::NoResponse));
+    }
+
+    #[test]
+    fn merge_patch_changes_only_the_given_fields() {
+        let business = test_business(Vec::new()).business;
+        let patch = BusinessPatch::Merge(json!({ "phone_num": "(541) 555-0199", "website": "https://belp.example" }));
+        let patched = business.apply_patch(&patch).unwrap();
+        assert_eq!(patched.phone_num.e164(), "+15415550199");
+        assert_eq!(patched.website.as_deref(), Some("https://belp.example"));
+        assert_eq!(patched.name, business.name);
+        assert_eq!(patched.city, business.city);
+    }
+
+    #[test]
+    fn json_patch_rejects_fields_outside_the_business() {
+        let business = test_business(Vec::new()).business;
+        let operations = json!([{ "op": "add", "path": "/reviews", "value": [] }]);
+        let patch = BusinessPatch::Json(serde_json::from_value(operations).unwrap());
+        assert!(matches!(business.apply_patch(&patch), Err(BusinessPatchError::UnknownField(_))));
+
+        let operations = json!([{ "op": "remove", "path": "/name" }]);
+        let patch = BusinessPatch::Json(serde_json::from_value(operations).unwrap());
+        assert!(matches!(business.apply_patch(&patch), Err(BusinessPatchError::Invalid(_))));
+    }
+
+    #[test]
+    fn patches_cant_change_read_only_fields() {
+        let business = test_business(Vec::new()).business;
+        let rejected = |patch: BusinessPatch| match business.apply_patch(&patch) {
+            Err(BusinessPatchError::ReadOnlyField(field)) => field,
+            _ => panic!("expected the patch to be refused"),
+        };
+        let json_patch = |operations: Value| BusinessPatch::Json(serde_json::from_value(operations).unwrap());
+
+        assert_eq!(rejected(BusinessPatch::Merge(json!({ "city": "Austin", "is_open_now": true }))), "is_open_now");
+        assert_eq!(rejected(json_patch(json!([{ "op": "replace", "path": "/is_open_now", "value": true }]))), "is_open_now");
+        let moved = json_patch(json!([{ "op": "move", "from": "/is_open_now", "path": "/website" }]));
+        assert_eq!(rejected(moved), "is_open_now");
+        // What's kept next to the business, like its version, isn't in the patched document at all.
+        let version = business.apply_patch(&BusinessPatch::Merge(json!({ "version": 7 })));
+        assert!(matches!(version, Err(BusinessPatchError::UnknownField(field)) if field == "version"));
+        // Looking is fine.
+        let test_only = json_patch(json!([{ "op": "test", "path": "/is_open_now", "value": null }]));
+        assert!(business.apply_patch(&test_only).is_ok());
+    }
+
+    #[test]
+    fn caption_update_rejects_other_fields() {
+        let body = r#"{"photo_caption": "new", "photo_url": "https://example.com/evil.png"}"#;
+        assert!(serde_json::from_str::<PhotoCaptionUpdate>(body).is_err());
+    }
+}
@@ -0,0 +1,580 @@
+// Claiming a business. Businesses start out without an owner (everything from the seed file does), and a user
+// who wants to own one asks for a verification code. The code goes to the email address or phone number the
+// business already has on record, so only someone who can read those can finish the claim by sending it back.
+//
+// Codes are delivered by a `Notifier`. There's no mail or SMS provider wired up yet, so the stand-ins either log
+// the message or append it to an outbox file, which is also how tests get at the code.
+//
+// Admins can set or remove an owner directly. Every step, from either side, goes into an audit trail.
+//
+// Whoever becomes the owner, either way, is handed an owner token for the business. Owner names are public, so the
+// token is what the owner-only writes check, sent as `Authorization: Bearer <token>`.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::business::{Business, BusinessResponse};
+
+/// How long a code can be used for.
+const CODE_LIFETIME_MINUTES: i64 = 30;
+
+/// Wrong codes allowed on one claim before it's cancelled.
+const MAX_ATTEMPTS: u8 = 5;
+
+/// How long a user who ran out of attempts has to wait before claiming the same business again.
+const LOCKOUT_MINUTES: i64 = 30;
+
+/// Where to send a verification code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Email,
+    /// A text message to the business's phone number.
+    Phone,
+}
+
+/// Starts a claim.
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct ClaimRequest {
+    /// Where the business should get the code, its email address by default.
+    #[serde(default)]
+    pub channel: Channel,
+}
+
+/// Finishes a claim with the code that was sent.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ClaimConfirmation {
+    #[schema(example = "042137")]
+    pub code: String,
+}
+
+/// Who an admin is making the owner.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct OwnerAssignment {
+    pub user: String,
+}
+
+/// One message for a notifier to deliver.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Message {
+    pub channel: Channel,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Error)]
+#[error("could not deliver the message: {0}")]
+pub struct NotifyError(#[from] io::Error);
+
+/// Delivers messages to users. Called with no locks held, so it's free to block for a while.
+pub trait Notifier: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), NotifyError>;
+}
+
+/// Writes messages to the log, for running locally.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send(&self, message: &Message) -> Result<(), NotifyError> {
+        info!(channel = ?message.channel, to = %message.to, subject = %message.subject, "{}", message.body);
+        Ok(())
+    }
+}
+
+/// Appends every message to a file as a line of JSON.
+pub struct FileNotifier {
+    path: PathBuf,
+    // Keeps lines from different workers from interleaving.
+    lock: Mutex<()>,
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        FileNotifier { path, lock: Mutex::new(()) }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn send(&self, message: &Message) -> Result<(), NotifyError> {
+        let entry = serde_json::json!({ "sent_at": Utc::now(), "message": message });
+        let mut line = serde_json::to_vec(&entry).map_err(io::Error::from)?;
+        line.push(b'\n');
+        let _guard = self.lock.lock();
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClaimError {
+    #[error("the business has no {0:?} on record to send a code to")]
+    NoContact(Channel),
+    #[error("there's no claim waiting for a code, start one first")]
+    NotRequested,
+    #[error("the code has expired, start the claim again")]
+    Expired,
+    #[error("the code is wrong, {attempts_left} attempts left")]
+    WrongCode { attempts_left: u8 },
+    #[error("too many wrong codes, start the claim again in {} minutes", LOCKOUT_MINUTES)]
+    TooManyAttempts,
+    #[error("too many wrong codes were sent for this business, try again after {until}")]
+    LockedOut { until: DateTime<Utc> },
+    #[error("the business has a different {0:?} now than the one the code went to, start the claim again")]
+    ContactChanged(Channel),
+    #[error("{0}")]
+    Delivery(String),
+}
+
+/// What happened, in the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClaimEvent {
+    /// A code was sent. `sent_to` is masked, the trail doesn't need the full address.
+    Requested { user: String, channel: Channel, sent_to: String },
+    /// The right code came back and the user became the owner.
+    Verified { user: String },
+    /// A code was sent back that didn't finish the claim.
+    Rejected { user: String, reason: String },
+    /// An admin made the user the owner.
+    OwnerAssigned { user: String, previous: Option<String> },
+    /// An admin took ownership away.
+    OwnerRemoved { previous: String },
+    /// The business was deleted, along with its owner and the claims that were waiting on a code.
+    BusinessDeleted { owner: Option<String>, cancelled: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    #[schema(value_type = String, example = "2026-10-18T21:00:00Z")]
+    pub at: DateTime<Utc>,
+    pub business: String,
+    #[serde(flatten)]
+    pub event: ClaimEvent,
+}
+
+/// A business and the token its new owner acts on it with.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Ownership {
+    pub business: BusinessResponse,
+    /// Sent back as `Authorization: Bearer <owner_token>` to reply to reviews, lay out photos and change contact
+    /// details. It isn't shown again, making someone the owner again hands out a new one.
+    pub owner_token: String,
+}
+
+/// The token the owner of one business acts on it with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OwnerToken {
+    pub owner: String,
+    pub token: String,
+}
+
+/// What was sent, to tell the user where to look.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ClaimSent {
+    pub channel: Channel,
+    /// Masked, e.g. `a***@cmu.edu` or `***0157`.
+    pub sent_to: String,
+    #[schema(value_type = String)]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Where a code went, in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub channel: Channel,
+    pub to: String,
+}
+
+struct PendingClaim {
+    code: String,
+    destination: Destination,
+    expires_at: DateTime<Utc>,
+    attempts_left: u8,
+}
+
+/// The claims waiting on one business. Every claimant guesses at their own code, so each has their own allowance of
+/// wrong codes and running out only cancels their claim.
+#[derive(Default)]
+struct PendingClaims {
+    /// Keyed by user, so one user starting a claim doesn't cancel another's.
+    by_user: HashMap<String, PendingClaim>,
+    /// Users who ran out of attempts, and when they can start again. Keeps them from getting a fresh allowance
+    /// straight away.
+    locked_out: HashMap<String, DateTime<Utc>>,
+}
+
+impl PendingClaims {
+    fn is_empty(&self) -> bool {
+        self.by_user.is_empty() && self.locked_out.is_empty()
+    }
+}
+
+/// Claims waiting for their code, and the audit trail.
+pub struct Claims {
+    notifier: Box<dyn Notifier>,
+    /// Keyed by business.
+    pending: Mutex<HashMap<String, PendingClaims>>,
+    /// Keyed by business.
+    owner_tokens: RwLock<BTreeMap<String, OwnerToken>>,
+    audit: RwLock<Vec<AuditEntry>>,
+}
+
+impl Claims {
+    pub fn new(notifier: Box<dyn Notifier>) -> Self {
+        Claims {
+            notifier,
+            pending: Mutex::new(HashMap::new()),
+            owner_tokens: RwLock::new(BTreeMap::new()),
+            audit: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Sends `user` a code for claiming `business`, replacing any code they were sent for it before. The wrong codes
+    /// they already sent still count, and a user who ran out of them has to wait out the lockout first.
+    pub fn request(&self, business: &Business, user: &str, channel: Channel) -> Result<ClaimSent, ClaimError> {
+        self.request_at(business, user, channel, Utc::now())
+    }
+
+    fn request_at(&self, business: &Business, user: &str, channel: Channel, now: DateTime<Utc>) -> Result<ClaimSent, ClaimError> {
+        let to = contact(business, channel).ok_or(ClaimError::NoContact(channel))?;
+        if let Some(until) = self.locked_out_until(&business.name, user, now) {
+            return Err(ClaimError::LockedOut { until });
+        }
+        let code = new_code();
+        let expires_at = now + Duration::minutes(CODE_LIFETIME_MINUTES);
+        self.notifier
+            .send(&Message {
+                channel,
+                to: to.clone(),
+                subject: format!("Your code for claiming {} on Belp", business.name),
+                body: format!(
+                    "{user} asked to become the owner of {} on Belp. The code is {code}, it works for {CODE_LIFETIME_MINUTES} \
+                     minutes. If that wasn't you, ignore this message.",
+                    business.name
+                ),
+            })
+            .map_err(|error| ClaimError::Delivery(error.to_string()))?;
+
+        let mut pending = self.pending.lock();
+        let claims = pending.entry(business.name.clone()).or_default();
+        let attempts_left = claims.by_user.get(user).map_or(MAX_ATTEMPTS, |claim| claim.attempts_left);
+        let destination = Destination { channel, to: to.clone() };
+        claims.by_user.insert(user.to_string(), PendingClaim { code, destination, expires_at, attempts_left });
+        drop(pending);
+        let sent_to = mask(channel, &to);
+        self.record_at(&business.name, ClaimEvent::Requested { user: user.to_string(), channel, sent_to: sent_to.clone() }, now);
+        Ok(ClaimSent { channel, sent_to, expires_at })
+    }
+
+    /// When `user` can claim `business` again, if they're locked out of it right now. Lockouts that are over are
+    /// cleared on the way.
+    fn locked_out_until(&self, business: &str, user: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut pending = self.pending.lock();
+        let claims = pending.get_mut(business)?;
+        claims.locked_out.retain(|_, until| *until > now);
+        let until = claims.locked_out.get(user).copied();
+        if claims.is_empty() {
+            pending.remove(business);
+        }
+        until
+    }
+
+    /// Checks the code `user` sent back, returning where it was sent. Once this succeeds the claim is used up, and it's
+    /// up to the caller to check the business still has that contact, make them the owner and record a
+    /// `ClaimEvent::Verified`.
+    pub fn confirm(&self, business: &str, user: &str, code: &str) -> Result<Destination, ClaimError> {
+        self.confirm_at(business, user, code, Utc::now())
+    }
+
+    fn confirm_at(&self, business: &str, user: &str, code: &str, now: DateTime<Utc>) -> Result<Destination, ClaimError> {
+        let outcome = {
+            let mut pending = self.pending.lock();
+            match pending.get(business).and_then(|claims| claims.by_user.get(user)) {
+                None => Err(ClaimError::NotRequested),
+                Some(claim) if claim.expires_at <= now => {
+                    remove_claim(&mut pending, business, user);
+                    Err(ClaimError::Expired)
+                }
+                Some(claim) if crate::admin::constant_time_eq(claim.code.as_bytes(), code.trim().as_bytes()) => {
+                    let destination = claim.destination.clone();
+                    remove_claim(&mut pending, business, user);
+                    Ok(destination)
+                }
+                Some(_) => {
+                    let claims = pending.get_mut(business).expect("there's a claim");
+                    let claim = claims.by_user.get_mut(user).expect("there's a claim");
+                    claim.attempts_left -= 1;
+                    if claim.attempts_left == 0 {
+                        claims.by_user.remove(user);
+                        claims.locked_out.insert(user.to_string(), now + Duration::minutes(LOCKOUT_MINUTES));
+                        Err(ClaimError::TooManyAttempts)
+                    } else {
+                        Err(ClaimError::WrongCode { attempts_left: claim.attempts_left })
+                    }
+                }
+            }
+        };
+        if let Err(error) = &outcome {
+            if *error != ClaimError::NotRequested {
+                self.record_at(business, ClaimEvent::Rejected { user: user.to_string(), reason: error.to_string() }, now);
+            }
+        }
+        outcome
+    }
+
+    /// Drops every claim waiting on `business`, e.g. once an admin has picked its owner, or the business is deleted.
+    /// Returns the users whose claims were dropped.
+    pub fn cancel(&self, business: &str) -> Vec<String> {
+        let pending = self.pending.lock().remove(business);
+        let mut cancelled: Vec<String> = pending.into_iter().flat_map(|claims| claims.by_user.into_keys()).collect();
+        cancelled.sort();
+        cancelled
+    }
+
+    /// Hands `owner` a new token for `business`, replacing the one whoever owned it before had.
+    pub fn issue_owner_token(&self, business: &str, owner: &str) -> String {
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let owner_token = OwnerToken { owner: owner.to_string(), token: token.clone() };
+        self.owner_tokens.write().insert(business.to_string(), owner_token);
+        token
+    }
+
+    /// Takes the owner token for `business` away, once it has no owner (or doesn't exist) any more.
+    pub fn revoke_owner_token(&self, business: &str) {
+        self.owner_tokens.write().remove(business);
+    }
+
+    /// The token `owner` acts on `business` with, if they were handed one.
+    pub fn owner_token(&self, business: &str, owner: &str) -> Option<String> {
+        let owner_tokens = self.owner_tokens.read();
+        owner_tokens.get(business).filter(|owner_token| owner_token.owner == owner).map(|owner_token| owner_token.token.clone())
+    }
+
+    /// Who `token` belongs to, if it's anyone's owner token. Looks at every token, so nothing about how they're stored
+    /// shows in how long a wrong guess takes.
+    pub fn owner_of_token(&self, token: &str) -> Option<String> {
+        let owner_tokens = self.owner_tokens.read();
+        let mut found = None;
+        for owner_token in owner_tokens.values() {
+            if crate::admin::constant_time_eq(owner_token.token.as_bytes(), token.as_bytes()) {
+                found = Some(owner_token.owner.clone());
+            }
+        }
+        found
+    }
+
+    /// Every owner token by business, for saving.
+    pub fn owner_tokens(&self) -> BTreeMap<String, OwnerToken> {
+        self.owner_tokens.read().clone()
+    }
+
+    pub fn replace_owner_tokens(&self, owner_tokens: BTreeMap<String, OwnerToken>) {
+        *self.owner_tokens.write() = owner_tokens;
+    }
+
+    /// Files everything under `from` under `to` instead: the claims waiting on a code, the owner token and the audit
+    /// trail. For when a business is renamed or merged into another one.
+    pub fn rename(&self, from: &str, to: &str) {
+        {
+            let mut pending = self.pending.lock();
+            if let Some(claims) = pending.remove(from) {
+                pending.insert(to.to_string(), claims);
+            }
+        }
+        {
+            let mut owner_tokens = self.owner_tokens.write();
+            if let Some(owner_token) = owner_tokens.remove(from) {
+                owner_tokens.insert(to.to_string(), owner_token);
+            }
+        }
+        for entry in self.audit.write().iter_mut().filter(|entry| entry.business == from) {
+            entry.business = to.to_string();
+        }
+    }
+
+    pub fn record(&self, business: &str, event: ClaimEvent) {
+        self.record_at(business, event, Utc::now());
+    }
+
+    fn record_at(&self, business: &str, event: ClaimEvent, at: DateTime<Utc>) {
+        self.audit.write().push(AuditEntry { at, business: business.to_string(), event });
+    }
+
+    /// The audit trail for one business, oldest first.
+    pub fn history(&self, business: &str) -> Vec<AuditEntry> {
+        self.audit.read().iter().filter(|entry| entry.business == business).cloned().collect()
+    }
+
+    /// The whole audit trail, for saving.
+    pub fn audit(&self) -> Vec<AuditEntry> {
+        self.audit.read().clone()
+    }
+
+    pub fn replace_audit(&self, audit: Vec<AuditEntry>) {
+        *self.audit.write() = audit;
+    }
+}
+
+/// Where a code for `business` goes on `channel`, if it has anything on record there.
+pub fn contact(business: &Business, channel: Channel) -> Option<String> {
+    match channel {
+        Channel::Email => business.email.clone().filter(|email| !email.trim().is_empty()),
+        Channel::Phone => Some(business.phone_num.e164()),
+    }
+}
+
+/// Drops `user`'s claim on `business`, and the business' entry once there's nothing left in it.
+fn remove_claim(pending: &mut HashMap<String, PendingClaims>, business: &str, user: &str) {
+    if let Some(claims) = pending.get_mut(business) {
+        claims.by_user.remove(user);
+        if claims.is_empty() {
+            pending.remove(business);
+        }
+    }
+}
+
+/// Six random digits.
+fn new_code() -> String {
+    let random = u32::from_le_bytes(Uuid::new_v4().as_bytes()[..4].try_into().expect("four bytes"));
+    format!("{:06}", random % 1_000_000)
+}
+
+/// Enough of an address for its owner to recognize it.
+fn mask(channel: Channel, to: &str) -> String {
+    match channel {
+        Channel::Email => match to.split_once('@') {
+            Some((local, domain)) => format!("{}***@{domain}", local.chars().next().unwrap_or('*')),
+            None => "***".into(),
+        },
+        Channel::Phone => format!("***{}", &to[to.len().saturating_sub(4)..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn business() -> Business {
+        serde_json::from_value(serde_json::json!({
+            "name": "Toy, Doyle and Kuvalis", "street_addr": "47 Pine View Pt", "city": "Houston", "state": "TX",
+            "zip": "77002", "phone_num": "713-598-0157", "email": "abartolacci0@cmu.edu", "website": null,
+            "category": { "main_category": "Electronics", "subcategory": "Baby" }
+        }))
+        .unwrap()
+    }
+
+    /// The code in the last message in the outbox.
+    fn last_code(outbox: &PathBuf) -> String {
+        let contents = std::fs::read_to_string(outbox).unwrap();
+        let line: serde_json::Value = serde_json::from_str(contents.lines().last().unwrap()).unwrap();
+        let body = line["message"]["body"].as_str().unwrap().to_string();
+        body.split("The code is ").nth(1).unwrap()[..6].to_string()
+    }
+
+    #[test]
+    fn the_code_from_the_outbox_verifies_the_claim() {
+        let outbox = std::env::temp_dir().join(format!("belp-outbox-{}.jsonl", std::process::id()));
+        let claims = Claims::new(Box::new(FileNotifier::new(outbox.clone())));
+        let business = business();
+
+        let sent = claims.request(&business, "sam", Channel::Email).unwrap();
+        assert_eq!(sent.sent_to, "a***@cmu.edu");
+        let code = last_code(&outbox);
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        assert_eq!(claims.confirm(&business.name, "sam", wrong), Err(ClaimError::WrongCode { attempts_left: 4 }));
+        assert_eq!(claims.confirm(&business.name, "alex", &code), Err(ClaimError::NotRequested));
+        let destination = Destination { channel: Channel::Email, to: "abartolacci0@cmu.edu".into() };
+        assert_eq!(claims.confirm(&business.name, "sam", &code), Ok(destination));
+        // Used up once it's worked.
+        assert_eq!(claims.confirm(&business.name, "sam", &code), Err(ClaimError::NotRequested));
+
+        let events: Vec<_> = claims.history(&business.name).into_iter().map(|entry| entry.event).collect();
+        assert_eq!(events, [
+            ClaimEvent::Requested { user: "sam".into(), channel: Channel::Email, sent_to: "a***@cmu.edu".into() },
+            ClaimEvent::Rejected { user: "sam".into(), reason: "the code is wrong, 4 attempts left".into() },
+        ]);
+        std::fs::remove_file(outbox).ok();
+    }
+
+    #[test]
+    fn codes_expire_and_run_out_of_attempts() {
+        let outbox = std::env::temp_dir().join(format!("belp-outbox-expiry-{}.jsonl", std::process::id()));
+        let claims = Claims::new(Box::new(FileNotifier::new(outbox.clone())));
+        let business = business();
+        let now = Utc::now();
+
+        claims.request_at(&business, "sam", Channel::Phone, now).unwrap();
+        let later = now + Duration::minutes(CODE_LIFETIME_MINUTES);
+        assert_eq!(claims.confirm_at(&business.name, "sam", &last_code(&outbox), later), Err(ClaimError::Expired));
+
+        let sent = claims.request_at(&business, "sam", Channel::Phone, now).unwrap();
+        assert_eq!(sent.sent_to, "***0157");
+        let wrong = if last_code(&outbox) == "000000" { "111111" } else { "000000" };
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(matches!(claims.confirm_at(&business.name, "sam", wrong, now), Err(ClaimError::WrongCode { .. })));
+        }
+        assert_eq!(claims.confirm_at(&business.name, "sam", wrong, now), Err(ClaimError::TooManyAttempts));
+        assert_eq!(claims.confirm_at(&business.name, "sam", &last_code(&outbox), now), Err(ClaimError::NotRequested));
+        std::fs::remove_file(outbox).ok();
+    }
+
+    #[test]
+    fn running_out_of_attempts_only_cancels_your_own_claim() {
+        let outbox = std::env::temp_dir().join(format!("belp-outbox-lockout-{}.jsonl", std::process::id()));
+        let claims = Claims::new(Box::new(FileNotifier::new(outbox.clone())));
+        let business = business();
+        let now = Utc::now();
+
+        claims.request_at(&business, "sam", Channel::Email, now).unwrap();
+        let code = last_code(&outbox);
+        claims.request_at(&business, "mallory", Channel::Email, now).unwrap();
+        let wrong = if last_code(&outbox) == "000000" { "111111" } else { "000000" };
+        let attempts_left = MAX_ATTEMPTS - 1;
+        assert_eq!(claims.confirm_at(&business.name, "mallory", wrong, now), Err(ClaimError::WrongCode { attempts_left }));
+        // Asking for another code doesn't top the allowance back up.
+        claims.request_at(&business, "mallory", Channel::Email, now).unwrap();
+        let wrong = if last_code(&outbox) == "000000" { "111111" } else { "000000" };
+        for attempts_left in (1..MAX_ATTEMPTS - 1).rev() {
+            let outcome = claims.confirm_at(&business.name, "mallory", wrong, now);
+            assert_eq!(outcome, Err(ClaimError::WrongCode { attempts_left }));
+        }
+        assert_eq!(claims.confirm_at(&business.name, "mallory", wrong, now), Err(ClaimError::TooManyAttempts));
+
+        // Running out doesn't start mallory over straight away, and sam's claim is still there.
+        let until = now + Duration::minutes(LOCKOUT_MINUTES);
+        assert_eq!(claims.request_at(&business, "mallory", Channel::Email, now), Err(ClaimError::LockedOut { until }));
+        assert!(claims.confirm_at(&business.name, "sam", &code, now).is_ok());
+        assert!(claims.request_at(&business, "mallory", Channel::Email, until).is_ok());
+        std::fs::remove_file(outbox).ok();
+    }
+
+    #[test]
+    fn owner_tokens_belong_to_one_owner_of_one_business() {
+        let claims = Claims::new(Box::new(LogNotifier));
+        let sams = claims.issue_owner_token("Pizza Place", "sam");
+        assert_eq!(claims.owner_token("Pizza Place", "sam"), Some(sams.clone()));
+        assert_eq!(claims.owner_token("Pizza Place", "alex"), None);
+
+        claims.rename("Pizza Place", "Pizza Palace");
+        assert_eq!(claims.owner_token("Pizza Place", "sam"), None);
+        assert_eq!(claims.owner_token("Pizza Palace", "sam"), Some(sams.clone()));
+
+        // A new owner gets a new token, and the old one stops working.
+        let alexs = claims.issue_owner_token("Pizza Palace", "alex");
+        assert_ne!(alexs, sams);
+        assert_eq!(claims.owner_token("Pizza Palace", "sam"), None);
+        claims.revoke_owner_token("Pizza Palace");
+        assert_eq!(claims.owner_token("Pizza Palace", "alex"), None);
+    }
+}
@@ -0,0 +1,584 @@
+// Server configuration. Settings are layered, each layer overriding the one before it:
+// built-in defaults, then a TOML file, then environment variables, then command line flags.
+// Everything gets validated in one go at startup, so a bad deployment reports every problem at once.
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::Parser;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::cors::CorsConfig;
+use crate::rate_limit::{Limit, RateLimitConfig};
+use crate::versioning::{Deprecation, VersioningConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Everything lives in memory and is gone when the process exits.
+    Memory,
+    /// Like memory, but the store is loaded from and written back to a JSON snapshot file.
+    Snapshot,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "memory" => Ok(StorageBackend::Memory),
+            "snapshot" => Ok(StorageBackend::Snapshot),
+            _ => Err(format!("unknown storage backend `{value}` (expected `memory` or `snapshot`)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierBackend {
+    /// Messages (claim verification codes) are written to the log.
+    Log,
+    /// Messages are appended to an outbox file, one JSON object per line.
+    File,
+}
+
+impl FromStr for NotifierBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "log" => Ok(NotifierBackend::Log),
+            "file" => Ok(NotifierBackend::File),
+            _ => Err(format!("unknown notifier `{value}` (expected `log` or `file`)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One readable line per event, for terminals.
+    Human,
+    /// One JSON object per event, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{value}` (expected `human` or `json`)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// Number of actix worker threads. `None` uses one per CPU core.
+    pub workers: Option<usize>,
+    /// Refuse writes to existing resources that don't send an If-Match header.
+    pub require_if_match: bool,
+    /// How long in-flight requests get to finish after a shutdown signal before they're dropped.
+    pub shutdown_timeout_secs: u64,
+    pub storage_backend: StorageBackend,
+    /// Where the snapshot lives, required for the snapshot backend.
+    pub storage_path: Option<PathBuf>,
+    pub storage_shards: usize,
+    /// JSON file with businesses to load into the store at startup.
+    pub seed_file: Option<PathBuf>,
+    pub log_level: String,
+    pub log_format: LogFormat,
+    pub max_body_bytes: usize,
+    pub max_connections: usize,
+    pub rate_limit: RateLimitConfig,
+    pub cors: CorsConfig,
+    pub api: VersioningConfig,
+    /// Bearer token for the admin endpoints. They're disabled without one.
+    pub admin_token: Option<String>,
+    /// How verification codes for business claims are delivered.
+    pub notifier: NotifierBackend,
+    /// The outbox, required for the file notifier.
+    pub notifier_outbox: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".into(),
+            port: 33333,
+            workers: None,
+            require_if_match: false,
+            shutdown_timeout_secs: 30,
+            storage_backend: StorageBackend::Memory,
+            storage_path: None,
+            storage_shards: crate::store::DEFAULT_SHARDS,
+            seed_file: None,
+            log_level: "info".into(),
+            log_format: LogFormat::Human,
+            max_body_bytes: 256 * 1024,
+            max_connections: 25_000,
+            rate_limit: RateLimitConfig::default(),
+            cors: CorsConfig::default(),
+            api: VersioningConfig::default(),
+            admin_token: None,
+            notifier: NotifierBackend::Log,
+            notifier_outbox: None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("could not read config file {path}: {source}")]
+    ReadFile { path: PathBuf, source: std::io::Error },
+    #[error("could not parse config file {path}: {source}")]
+    ParseFile { path: PathBuf, source: Box<toml::de::Error> },
+    #[error("environment variable {name}={value:?} is invalid: {reason}")]
+    Env { name: String, value: String, reason: String },
+    #[error("invalid configuration:\n{}", .0.iter().map(|problem| format!("  - {problem}")).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<String>),
+}
+
+/// The config file. Every section and key is optional, anything left out keeps its earlier value.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    server: FileServer,
+    #[serde(default)]
+    storage: FileStorage,
+    #[serde(default)]
+    log: FileLog,
+    #[serde(default)]
+    limits: FileLimits,
+    #[serde(default)]
+    rate_limit: FileRateLimit,
+    #[serde(default)]
+    cors: FileCors,
+    #[serde(default)]
+    api: FileApi,
+    #[serde(default)]
+    admin: FileAdmin,
+    #[serde(default)]
+    claims: FileClaims,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileServer {
+    host: Option<String>,
+    port: Option<u16>,
+    workers: Option<usize>,
+    require_if_match: Option<bool>,
+    shutdown_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileStorage {
+    backend: Option<StorageBackend>,
+    path: Option<PathBuf>,
+    shards: Option<usize>,
+    seed_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileLog {
+    level: Option<String>,
+    format: Option<LogFormat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileLimits {
+    max_body_bytes: Option<usize>,
+    max_connections: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRateLimit {
+    enabled: Option<bool>,
+    burst: Option<u32>,
+    per_minute: Option<u32>,
+    /// Per route overrides, keyed by route name (e.g. `add_review`). Routes left out keep their built-in limit.
+    #[serde(default)]
+    routes: BTreeMap<String, Limit>,
+    /// Reverse proxies whose X-Forwarded-For is believed when working out who a request is from.
+    trusted_proxies: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileCors {
+    allowed_origins: Option<Vec<String>>,
+    allowed_methods: Option<Vec<String>>,
+    allowed_headers: Option<Vec<String>>,
+    exposed_headers: Option<Vec<String>>,
+    allow_credentials: Option<bool>,
+    max_age_secs: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileApi {
+    default_version: Option<u16>,
+    /// Keyed by version, as `1` or `v1`.
+    #[serde(default)]
+    deprecations: BTreeMap<String, Deprecation>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileAdmin {
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileClaims {
+    notifier: Option<NotifierBackend>,
+    outbox: Option<PathBuf>,
+}
+
+/// Command line flags, the last layer. Anything not given keeps the value from the layers below.
+#[derive(Debug, Default, Parser)]
+#[command(name = "belp", version, about = "Belp, a Yelp-like API server")]
+pub struct Cli {
+    /// TOML config file (also read from BELP_CONFIG)
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    #[arg(long)]
+    pub host: Option<String>,
+    #[arg(long, short)]
+    pub port: Option<u16>,
+    #[arg(long)]
+    pub workers: Option<usize>,
+    #[arg(long)]
+    pub require_if_match: Option<bool>,
+    #[arg(long)]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// `memory` or `snapshot`
+    #[arg(long)]
+    pub storage_backend: Option<StorageBackend>,
+    #[arg(long)]
+    pub storage_path: Option<PathBuf>,
+    #[arg(long)]
+    pub storage_shards: Option<usize>,
+    #[arg(long)]
+    pub seed_file: Option<PathBuf>,
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// `human` or `json`
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+    #[arg(long)]
+    pub max_body_bytes: Option<usize>,
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    #[arg(long)]
+    pub rate_limit_enabled: Option<bool>,
+    /// Comma separated origins allowed to call the API from a browser, or `*`
+    #[arg(long, value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// API version for requests that don't ask for one
+    #[arg(long)]
+    pub api_default_version: Option<u16>,
+    /// Bearer token for the admin endpoints (better passed as BELP_ADMIN_TOKEN, flags show up in `ps`)
+    #[arg(long)]
+    pub admin_token: Option<String>,
+    /// How claim verification codes are delivered, `log` or `file`
+    #[arg(long)]
+    pub notifier: Option<NotifierBackend>,
+    /// File the `file` notifier appends messages to
+    #[arg(long)]
+    pub notifier_outbox: Option<PathBuf>,
+}
+
+impl Config {
+    /// Builds the config from every layer: defaults, the config file, the process environment and `cli`.
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        Config::load_from(cli, |name| std::env::var(name).ok())
+    }
+
+    /// Same as `load`, with the environment passed in so it can be faked.
+    pub fn load_from(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        // Values that parse but can't be used are reported along with everything `validate` finds.
+        let mut problems = Vec::new();
+
+        let config_file = cli.config.clone().or_else(|| env("BELP_CONFIG").map(PathBuf::from));
+        if let Some(path) = config_file {
+            config.apply_file(&path, &mut problems)?;
+        }
+        config.apply_env(&env)?;
+        config.apply_cli(cli);
+        config.validate(problems)?;
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path, problems: &mut Vec<String>) -> Result<(), ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|source| ConfigError::ReadFile { path: path.to_path_buf(), source })?;
+        let file: FileConfig = toml::from_str(&contents)
+            .map_err(|source| ConfigError::ParseFile { path: path.to_path_buf(), source: Box::new(source) })?;
+
+        set(&mut self.host, file.server.host);
+        set(&mut self.port, file.server.port);
+        set_some(&mut self.workers, file.server.workers);
+        set(&mut self.require_if_match, file.server.require_if_match);
+        set(&mut self.shutdown_timeout_secs, file.server.shutdown_timeout_secs);
+        set(&mut self.storage_backend, file.storage.backend);
+        set_some(&mut self.storage_path, file.storage.path);
+        set(&mut self.storage_shards, file.storage.shards);
+        set_some(&mut self.seed_file, file.storage.seed_file);
+        set(&mut self.log_level, file.log.level);
+        set(&mut self.log_format, file.log.format);
+        set(&mut self.max_body_bytes, file.limits.max_body_bytes);
+        set(&mut self.max_connections, file.limits.max_connections);
+        set(&mut self.rate_limit.enabled, file.rate_limit.enabled);
+        set(&mut self.rate_limit.default.burst, file.rate_limit.burst);
+        set(&mut self.rate_limit.default.per_minute, file.rate_limit.per_minute);
+        self.rate_limit.routes.extend(file.rate_limit.routes);
+        set(&mut self.rate_limit.trusted_proxies, file.rate_limit.trusted_proxies);
+        set(&mut self.cors.allowed_origins, file.cors.allowed_origins);
+        set(&mut self.cors.allowed_methods, file.cors.allowed_methods);
+        set(&mut self.cors.allowed_headers, file.cors.allowed_headers);
+        set(&mut self.cors.exposed_headers, file.cors.exposed_headers);
+        set(&mut self.cors.allow_credentials, file.cors.allow_credentials);
+        set(&mut self.cors.max_age_secs, file.cors.max_age_secs);
+        set(&mut self.api.default_version, file.api.default_version);
+        for (version, deprecation) in file.api.deprecations {
+            match version.trim_start_matches('v').parse() {
+                Ok(parsed) => {
+                    self.api.deprecations.insert(parsed, deprecation);
+                }
+                Err(_) => problems.push(format!("API deprecation key `{version}` should be a version like `1` or `v1`")),
+            }
+        }
+        set_some(&mut self.admin_token, file.admin.token);
+        set(&mut self.notifier, file.claims.notifier);
+        set_some(&mut self.notifier_outbox, file.claims.outbox);
+        Ok(())
+    }
+
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        // `PORT` is what container platforms set, `BELP_PORT` wins if both are there.
+        // There's deliberately no plain `HOST`, some shells set it to the machine's hostname.
+        set(&mut self.port, parse_env(env, "PORT")?);
+        set(&mut self.host, env("BELP_HOST"));
+        set(&mut self.port, parse_env(env, "BELP_PORT")?);
+        set_some(&mut self.workers, parse_env(env, "BELP_WORKERS")?);
+        set(&mut self.require_if_match, parse_env(env, "BELP_REQUIRE_IF_MATCH")?);
+        set(&mut self.shutdown_timeout_secs, parse_env(env, "BELP_SHUTDOWN_TIMEOUT_SECS")?);
+        set(&mut self.storage_backend, parse_env(env, "BELP_STORAGE_BACKEND")?);
+        set_some(&mut self.storage_path, env("BELP_STORAGE_PATH").map(PathBuf::from));
+        set(&mut self.storage_shards, parse_env(env, "BELP_STORAGE_SHARDS")?);
+        set_some(&mut self.seed_file, env("BELP_SEED_FILE").map(PathBuf::from));
+        set(&mut self.log_level, env("BELP_LOG_LEVEL"));
+        set(&mut self.log_format, parse_env(env, "BELP_LOG_FORMAT")?);
+        set(&mut self.max_body_bytes, parse_env(env, "BELP_MAX_BODY_BYTES")?);
+        set(&mut self.max_connections, parse_env(env, "BELP_MAX_CONNECTIONS")?);
+        set(&mut self.rate_limit.enabled, parse_env(env, "BELP_RATE_LIMIT_ENABLED")?);
+        set(&mut self.cors.allowed_origins, env("BELP_CORS_ALLOWED_ORIGINS").map(|origins| split_list(&origins)));
+        set(&mut self.cors.allow_credentials, parse_env(env, "BELP_CORS_ALLOW_CREDENTIALS")?);
+        set(&mut self.api.default_version, parse_env(env, "BELP_API_DEFAULT_VERSION")?);
+        set_some(&mut self.admin_token, env("BELP_ADMIN_TOKEN"));
+        set(&mut self.notifier, parse_env(env, "BELP_NOTIFIER")?);
+        set_some(&mut self.notifier_outbox, env("BELP_NOTIFIER_OUTBOX").map(PathBuf::from));
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        set(&mut self.host, cli.host.clone());
+        set(&mut self.port, cli.port);
+        set_some(&mut self.workers, cli.workers);
+        set(&mut self.require_if_match, cli.require_if_match);
+        set(&mut self.shutdown_timeout_secs, cli.shutdown_timeout_secs);
+        set(&mut self.storage_backend, cli.storage_backend);
+        set_some(&mut self.storage_path, cli.storage_path.clone());
+        set(&mut self.storage_shards, cli.storage_shards);
+        set_some(&mut self.seed_file, cli.seed_file.clone());
+        set(&mut self.log_level, cli.log_level.clone());
+        set(&mut self.log_format, cli.log_format);
+        set(&mut self.max_body_bytes, cli.max_body_bytes);
+        set(&mut self.max_connections, cli.max_connections);
+        set(&mut self.rate_limit.enabled, cli.rate_limit_enabled);
+        set(&mut self.cors.allowed_origins, cli.cors_allowed_origins.clone());
+        set(&mut self.api.default_version, cli.api_default_version);
+        set_some(&mut self.admin_token, cli.admin_token.clone());
+        set(&mut self.notifier, cli.notifier);
+        set_some(&mut self.notifier_outbox, cli.notifier_outbox.clone());
+    }
+
+    fn validate(&self, mut problems: Vec<String>) -> Result<(), ConfigError> {
+        if self.host.trim().is_empty() {
+            problems.push("host must not be empty".to_string());
+        }
+        if self.workers == Some(0) {
+            problems.push("workers must be at least 1".to_string());
+        }
+        if self.storage_shards == 0 {
+            problems.push("storage shards must be at least 1".to_string());
+        }
+        if self.storage_backend == StorageBackend::Snapshot && self.storage_path.is_none() {
+            problems.push("the snapshot storage backend needs a storage path".to_string());
+        }
+        if let Some(seed_file) = &self.seed_file {
+            if !seed_file.is_file() {
+                problems.push(format!("seed file {} does not exist", seed_file.display()));
+            }
+        }
+        if !["trace", "debug", "info", "warn", "error"].contains(&self.log_level.to_ascii_lowercase().as_str()) {
+            problems.push(format!(
+                "log level `{}` is not one of trace, debug, info, warn or error",
+                self.log_level
+            ));
+        }
+        if self.max_body_bytes == 0 {
+            problems.push("max body bytes must be at least 1".to_string());
+        }
+        if self.max_connections == 0 {
+            problems.push("max connections must be at least 1".to_string());
+        }
+        let limits = std::iter::once(("default", &self.rate_limit.default))
+            .chain(self.rate_limit.routes.iter().map(|(route, limit)| (route.as_str(), limit)));
+        for (route, limit) in limits {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                problems.push(format!("rate limit for {route} needs a burst and per_minute of at least 1"));
+            }
+        }
+        problems.extend(self.cors.problems());
+        problems.extend(self.api.problems());
+        if self.admin_token.as_ref().is_some_and(|token| token.trim().len() < crate::admin::MIN_TOKEN_LENGTH) {
+            problems.push(format!("the admin token must be at least {} characters", crate::admin::MIN_TOKEN_LENGTH));
+        }
+        if self.notifier == NotifierBackend::File && self.notifier_outbox.is_none() {
+            problems.push("the file notifier needs an outbox path".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+fn set_some<T>(target: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *target = value;
+    }
+}
+
+/// Splits a comma separated environment variable, ignoring blanks.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+fn parse_env<T: FromStr>(env: &impl Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    match env(name) {
+        None => Ok(None),
+        Some(value) => value.trim().parse().map(Some).map_err(|error: T::Err| ConfigError::Env {
+            name: name.to_string(),
+            value,
+            reason: error.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let dir = std::env::temp_dir().join(format!("belp-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("belp.toml");
+        std::fs::write(&file, "[server]\nhost = \"0.0.0.0\"\nport = 8000\nworkers = 2\n").unwrap();
+
+        let cli = Cli { config: Some(file), workers: Some(4), ..Cli::default() };
+        let config = Config::load_from(&cli, env(&[("PORT", "9000")])).unwrap();
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.workers, Some(4));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn rate_limit_routes_are_merged_with_the_defaults() {
+        let dir = std::env::temp_dir().join(format!("belp-rate-limit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("belp.toml");
+        std::fs::write(
+            &file,
+            "[rate_limit]\nburst = 30\ntrusted_proxies = [\"10.0.0.1\"]\n\n[rate_limit.routes.add_review]\nburst = 1\nper_minute = 2\n",
+        )
+        .unwrap();
+
+        let cli = Cli { config: Some(file), ..Cli::default() };
+        let config = Config::load_from(&cli, env(&[])).unwrap();
+        assert_eq!(config.rate_limit.default.burst, 30);
+        assert_eq!(config.rate_limit.routes["add_review"], Limit { burst: 1, per_minute: 2 });
+        assert!(config.rate_limit.routes.contains_key("add_photo"));
+        assert_eq!(config.rate_limit.trusted_proxies, ["10.0.0.1".parse::<IpAddr>().unwrap()]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn bad_env_values_are_reported() {
+        let error = Config::load_from(&Cli::default(), env(&[("PORT", "http")])).unwrap_err();
+        assert!(matches!(error, ConfigError::Env { ref name, .. } if name == "PORT"));
+    }
+
+    #[test]
+    fn every_validation_problem_is_reported() {
+        let env = env(&[("BELP_STORAGE_BACKEND", "snapshot"), ("BELP_LOG_LEVEL", "loud"), ("BELP_WORKERS", "0")]);
+        match Config::load_from(&Cli::default(), env) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(problems.len(), 3),
+            other => panic!("expected validation errors, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn bad_deprecation_keys_are_reported_with_everything_else() {
+        let dir = std::env::temp_dir().join(format!("belp-deprecations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("belp.toml");
+        std::fs::write(&file, "[server]\nworkers = 0\n\n[api.deprecations.latest]\nsince = \"2026-01-01\"\n").unwrap();
+
+        let cli = Cli { config: Some(file), ..Cli::default() };
+        match Config::load_from(&cli, env(&[])) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(problems, [
+                "API deprecation key `latest` should be a version like `1` or `v1`",
+                "workers must be at least 1",
+            ]),
+            other => panic!("expected validation errors, got {other:?}"),
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,135 @@
+// Cross-origin resource sharing for the browser front-end. Nothing is allowed cross-origin until origins are configured.
+use std::str::FromStr;
+
+use actix_cors::Cors;
+use actix_web::http::{header::HeaderName, Method, Uri};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// Full origins like `https://belp.example.com`, or `*` for any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers the browser lets scripts read, on top of the always-safe ones.
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight response.
+    pub max_age_secs: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&["GET", "POST", "PUT", "PATCH", "DELETE"]),
+            // Everything the API itself reads: bodies, admin and owner tokens, conditional requests and request IDs.
+            allowed_headers: strings(&["content-type", "authorization", "if-match", "if-none-match", "x-request-id"]),
+            exposed_headers: strings(&[
+                "etag",
+                "x-request-id",
+                "retry-after",
+                "x-ratelimit-limit",
+                "x-ratelimit-remaining",
+                "x-ratelimit-reset",
+                "api-version",
+                "deprecation",
+                "sunset",
+                "link",
+            ]),
+            allow_credentials: false,
+            max_age_secs: 3600,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Everything that would make `build` fail, as messages for the config validation.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for origin in &self.allowed_origins {
+            if origin == "*" {
+                if self.allow_credentials {
+                    problems.push("CORS can't allow credentials from any origin (`*`), list the origins instead".into());
+                }
+                continue;
+            }
+            let valid = Uri::from_str(origin)
+                .map(|uri| uri.scheme().is_some() && uri.host().is_some() && uri.path() == "/" && !origin.ends_with('/'))
+                .unwrap_or(false);
+            if !valid {
+                problems.push(format!("CORS origin `{origin}` should look like `https://example.com`"));
+            }
+        }
+        for method in &self.allowed_methods {
+            if Method::from_str(method).is_err() {
+                problems.push(format!("CORS method `{method}` is not a valid HTTP method"));
+            }
+        }
+        for header in self.allowed_headers.iter().chain(&self.exposed_headers) {
+            if HeaderName::from_str(header).is_err() {
+                problems.push(format!("CORS header `{header}` is not a valid header name"));
+            }
+        }
+        problems
+    }
+
+    /// The CORS middleware. The config must have been validated with `problems` first.
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.allowed_methods.iter().map(String::as_str))
+            .allowed_headers(self.allowed_headers.iter().map(String::as_str))
+            .expose_headers(self.exposed_headers.iter().map(String::as_str))
+            .max_age(self.max_age_secs);
+        for origin in &self.allowed_origins {
+            cors = if origin == "*" { cors.allow_any_origin().send_wildcard() } else { cors.allowed_origin(origin) };
+        }
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{http::header, http::StatusCode, web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn preflight_only_succeeds_for_allowed_origins() {
+        let config = CorsConfig { allowed_origins: vec!["https://belp.example.com".into()], ..CorsConfig::default() };
+        assert!(config.problems().is_empty());
+        let app = init_service(
+            App::new().wrap(config.build()).route("/business", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let preflight = |origin: &'static str| {
+            TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/business")
+                .insert_header((header::ORIGIN, origin))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type, if-match"))
+                .to_request()
+        };
+        let allowed = call_service(&app, preflight("https://belp.example.com")).await;
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(
+            allowed.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://belp.example.com"
+        );
+        assert_eq!(allowed.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+
+        let refused = call_service(&app, preflight("https://evil.example.com")).await;
+        assert!(refused.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn credentials_with_any_origin_are_rejected() {
+        let config = CorsConfig { allowed_origins: vec!["*".into()], allow_credentials: true, ..CorsConfig::default() };
+        assert_eq!(config.problems().len(), 1);
+    }
+}
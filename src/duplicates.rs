@@ -0,0 +1,237 @@
+// Finding businesses that were added twice, and remembering where merged businesses went.
+//
+// Comparing every pair of businesses would be quadratic, so pairs are only scored when they share something cheap
+// to look up first: a phone number, a ZIP code or a word of their name. Each candidate pair then gets a score from
+// how alike the names are, whether the addresses match and whether the phone numbers are the same.
+//
+// Businesses are identified by name, so when one is merged into another its name keeps pointing at the survivor.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::business::{Business, BusinessResponse};
+
+const NAME_WEIGHT: f64 = 0.5;
+const ADDRESS_WEIGHT: f64 = 0.3;
+const PHONE_WEIGHT: f64 = 0.2;
+
+/// Words that say nothing about which business it is.
+const NOISE_WORDS: &[&str] = &["the", "and", "inc", "llc", "ltd", "co", "corp", "company", "group"];
+
+/// Blocks bigger than this (a very common name word, say) are skipped, the other keys still pair their businesses up.
+const MAX_BLOCK: usize = 500;
+
+/// How alike two businesses are, from 0 to 1.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DuplicateCandidate {
+    pub businesses: [String; 2],
+    pub score: f64,
+    pub name_similarity: f64,
+    /// 0 unless the ZIP codes match, then how alike the streets are.
+    pub address_similarity: f64,
+    pub same_phone: bool,
+}
+
+/// Which business to keep and which one to fold into it.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct MergeRequest {
+    pub keep: String,
+    /// Removed once its reviews and photos are moved over. Its name redirects to `keep` from then on.
+    pub merge: String,
+}
+
+/// Scores a pair of businesses.
+pub fn compare(a: &Business, b: &Business) -> DuplicateCandidate {
+    let name_similarity = similarity(&normalize_name(&a.name), &normalize_name(&b.name));
+    let address_similarity = if a.zip.five_digit() == b.zip.five_digit() {
+        similarity(&a.street_addr.to_lowercase(), &b.street_addr.to_lowercase())
+    } else {
+        0.0
+    };
+    let same_phone = a.phone_num.e164() == b.phone_num.e164();
+    let score = NAME_WEIGHT * name_similarity + ADDRESS_WEIGHT * address_similarity + PHONE_WEIGHT * f64::from(u8::from(same_phone));
+    DuplicateCandidate { businesses: [a.name.clone(), b.name.clone()], score, name_similarity, address_similarity, same_phone }
+}
+
+/// Likely duplicates among `businesses`, scoring at least `min_score`, best first.
+pub fn find(businesses: &[BusinessResponse], min_score: f64) -> Vec<DuplicateCandidate> {
+    let mut blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, business) in businesses.iter().enumerate() {
+        let business = &business.business;
+        let mut keys = vec![format!("phone:{}", business.phone_num.e164()), format!("zip:{}", business.zip.five_digit())];
+        keys.extend(normalize_name(&business.name).split(' ').filter(|word| word.len() >= 3).map(|word| format!("name:{word}")));
+        for key in keys {
+            blocks.entry(key).or_default().push(index);
+        }
+    }
+
+    let mut pairs = HashSet::new();
+    for block in blocks.values().filter(|block| block.len() <= MAX_BLOCK) {
+        for (position, first) in block.iter().enumerate() {
+            for second in &block[position + 1..] {
+                if first != second {
+                    pairs.insert((*first.min(second), *first.max(second)));
+                }
+            }
+        }
+    }
+
+    let mut candidates: Vec<DuplicateCandidate> = pairs
+        .into_iter()
+        .map(|(first, second)| compare(&businesses[first].business, &businesses[second].business))
+        .filter(|candidate| candidate.score >= min_score)
+        .collect();
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.businesses.cmp(&b.businesses)));
+    candidates
+}
+
+/// Lowercase words without punctuation or noise words, `&` read as `and`.
+fn normalize_name(name: &str) -> String {
+    name.replace('&', " and ")
+        .replace('\'', "")
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !NOISE_WORDS.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 1 minus the edit distance over the longer length, so 1 for the same text and 0 for nothing in common.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / longest as f64
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut next = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            next.push((row[j] + usize::from(a_char != b_char)).min(row[j + 1] + 1).min(next[j] + 1));
+        }
+        row = next;
+    }
+    row[b.len()]
+}
+
+/// Where the names of merged businesses point now.
+#[derive(Default)]
+pub struct Redirects {
+    targets: RwLock<BTreeMap<String, String>>,
+}
+
+impl Redirects {
+    pub fn new() -> Self {
+        Redirects::default()
+    }
+
+    /// The business `name` was merged into, if it was.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        self.targets.read().get(name).cloned()
+    }
+
+    /// Points `from` at `to`, along with anything that pointed at `from`, so there are never chains to follow.
+    pub fn add(&self, from: &str, to: &str) {
+        self.rename(from, to);
+        self.targets.write().insert(from.to_string(), to.to_string());
+    }
+
+    /// The business called `from` is called `to` now, so whatever was merged into it follows it there.
+    pub fn rename(&self, from: &str, to: &str) {
+        let mut targets = self.targets.write();
+        for target in targets.values_mut().filter(|target| *target == from) {
+            *target = to.to_string();
+        }
+        targets.remove(to);
+    }
+
+    /// There's a business called `name` again, so the name stops redirecting.
+    pub fn reuse(&self, name: &str) {
+        self.targets.write().remove(name);
+    }
+
+    /// The business called `name` was deleted, so nothing that was merged into it leads anywhere any more.
+    pub fn forget(&self, name: &str) {
+        self.targets.write().retain(|_, target| target != name);
+    }
+
+    /// Every redirect, for saving.
+    pub fn all(&self) -> BTreeMap<String, String> {
+        self.targets.read().clone()
+    }
+
+    pub fn replace(&self, targets: BTreeMap<String, String>) {
+        *self.targets.write() = targets;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn business(name: &str, street: &str, zip: &str, phone: &str) -> BusinessResponse {
+        BusinessResponse::new(
+            serde_json::from_value(serde_json::json!({
+                "name": name, "street_addr": street, "city": "Houston", "state": "TX", "zip": zip, "phone_num": phone,
+                "category": { "main_category": "Restaurants", "subcategory": "Pizza" }, "email": null, "website": null
+            }))
+            .unwrap(),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn near_identical_businesses_pair_up() {
+        let businesses = [
+            business("Joe's Pizza", "12 Main Street", "77002", "713-555-0100"),
+            business("Joes Pizza, Inc.", "12 Main St", "77002-1234", "(713) 555-0100"),
+            business("Joe's Tacos", "400 Elm St", "77056", "713-555-0199"),
+        ];
+        let candidates = find(&businesses, 0.6);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].businesses, ["Joe's Pizza".to_string(), "Joes Pizza, Inc.".to_string()]);
+        assert!(candidates[0].same_phone);
+        assert_eq!(candidates[0].address_similarity, 1.0);
+        assert!(compare(&businesses[0].business, &businesses[2].business).score < 0.6);
+    }
+
+    #[test]
+    fn redirects_never_chain() {
+        let redirects = Redirects::new();
+        redirects.add("Joes Pizza", "Joe's Pizza");
+        redirects.add("Joe's Pizza", "Joe's Famous Pizza");
+        assert_eq!(redirects.resolve("Joes Pizza").as_deref(), Some("Joe's Famous Pizza"));
+        assert_eq!(redirects.resolve("Joe's Pizza").as_deref(), Some("Joe's Famous Pizza"));
+        // Merging back the other way drops the redirect for the name that's in use again.
+        redirects.add("Joe's Famous Pizza", "Joe's Pizza");
+        assert_eq!(redirects.resolve("Joe's Pizza"), None);
+    }
+
+    #[test]
+    fn redirects_follow_the_survivor() {
+        let redirects = Redirects::new();
+        redirects.add("Joes Pizza", "Joe's Pizza");
+        redirects.add("Joes Tacos", "Joe's Tacos");
+        redirects.rename("Joe's Pizza", "Joe's Famous Pizza");
+        assert_eq!(redirects.resolve("Joes Pizza").as_deref(), Some("Joe's Famous Pizza"));
+
+        redirects.forget("Joe's Famous Pizza");
+        assert_eq!(redirects.resolve("Joes Pizza"), None);
+        assert_eq!(redirects.resolve("Joes Tacos").as_deref(), Some("Joe's Tacos"));
+
+        // Renaming a business to a merged name, or creating one with it, takes the name back.
+        redirects.add("Joes Pizza", "Joe's Tacos");
+        redirects.rename("Joe's Tacos", "Joes Tacos");
+        assert_eq!(redirects.resolve("Joes Tacos"), None);
+        assert_eq!(redirects.resolve("Joes Pizza").as_deref(), Some("Joes Tacos"));
+        redirects.reuse("Joes Pizza");
+        assert_eq!(redirects.all(), BTreeMap::new());
+    }
+}
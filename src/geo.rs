@@ -0,0 +1,220 @@
+// Where businesses are, and which ones are near a point. A business is placed at its own coordinates when it has
+// them, and at the centre of its ZIP code (from the bundled table) when it doesn't.
+//
+// The spatial index buckets businesses into one degree grid cells, so a radius search only measures the distance to
+// businesses in the handful of cells the circle overlaps. Like the other indexes it observes the store.
+use std::collections::{HashMap, HashSet};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::business::{Business, BusinessResponse};
+use crate::store::StoreObserver;
+use crate::zips;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Length of one degree of latitude, and of longitude at the equator.
+const KM_PER_DEGREE: f64 = EARTH_RADIUS_KM * std::f64::consts::PI / 180.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// True when the business has no coordinates of its own and was placed at its ZIP code's centre.
+    pub derived_from_zip: bool,
+}
+
+impl Location {
+    pub fn distance_km(&self, latitude: f64, longitude: f64) -> f64 {
+        haversine_km(self.latitude, self.longitude, latitude, longitude)
+    }
+}
+
+/// Where a business is, if it has coordinates or a ZIP code the table knows.
+pub fn locate(business: &Business) -> Option<Location> {
+    if let (Some(latitude), Some(longitude)) = (business.latitude, business.longitude) {
+        return Some(Location { latitude, longitude, derived_from_zip: false });
+    }
+    zips::lookup(business.zip.five_digit()).map(|info| Location { latitude: info.latitude, longitude: info.longitude, derived_from_zip: true })
+}
+
+/// Problems with a business's coordinates, for rejecting it before it's stored.
+pub fn coordinate_problems(business: &Business) -> Vec<String> {
+    let mut problems = Vec::new();
+    match (business.latitude, business.longitude) {
+        (Some(latitude), Some(longitude)) => {
+            if !(-90.0..=90.0).contains(&latitude) {
+                problems.push(format!("latitude {latitude} is not between -90 and 90"));
+            }
+            if !(-180.0..=180.0).contains(&longitude) {
+                problems.push(format!("longitude {longitude} is not between -180 and 180"));
+            }
+        }
+        (None, None) => {}
+        _ => problems.push("latitude and longitude have to be given together".into()),
+    }
+    problems
+}
+
+/// Parses a `lat,lng` pair, as in `?near=29.76,-95.37`.
+pub fn parse_point(point: &str) -> Option<(f64, f64)> {
+    let (latitude, longitude) = point.split_once(',')?;
+    let latitude: f64 = latitude.trim().parse().ok()?;
+    let longitude: f64 = longitude.trim().parse().ok()?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return None;
+    }
+    Some((latitude, longitude))
+}
+
+/// Great-circle distance between two points.
+pub fn haversine_km(latitude_a: f64, longitude_a: f64, latitude_b: f64, longitude_b: f64) -> f64 {
+    let (latitude_a, latitude_b) = (latitude_a.to_radians(), latitude_b.to_radians());
+    let latitude_delta = latitude_b - latitude_a;
+    let longitude_delta = (longitude_b - longitude_a).to_radians();
+    let a = (latitude_delta / 2.0).sin().powi(2) + latitude_a.cos() * latitude_b.cos() * (longitude_delta / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+type Cell = (i32, i32);
+
+fn cell(latitude: f64, longitude: f64) -> Cell {
+    (latitude.floor() as i32, longitude.floor() as i32)
+}
+
+#[derive(Default)]
+struct Grid {
+    cells: HashMap<Cell, HashSet<String>>,
+    locations: HashMap<String, Location>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Nearby {
+    pub business_name: String,
+    pub location: Location,
+    pub distance_km: f64,
+}
+
+#[derive(Default)]
+pub struct SpatialIndex {
+    grid: RwLock<Grid>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        SpatialIndex::default()
+    }
+
+    /// Every business within `radius_km` of the point, closest first.
+    pub fn within(&self, latitude: f64, longitude: f64, radius_km: f64) -> Vec<Nearby> {
+        let latitude_span = radius_km / KM_PER_DEGREE;
+        let lowest = cell(latitude - latitude_span, 0.0).0.max(-90);
+        let highest = cell(latitude + latitude_span, 0.0).0.min(89);
+        // Longitude degrees shrink towards the poles. Close enough to one that the circle could wrap all the way
+        // round, every column is searched.
+        let widest_latitude = (latitude.abs() + latitude_span).min(90.0).to_radians();
+        let longitude_span = radius_km / (KM_PER_DEGREE * widest_latitude.cos().max(f64::EPSILON));
+        let columns: Vec<i32> = if longitude_span >= 180.0 {
+            (-180..180).collect()
+        } else {
+            let (west, east) = (cell(0.0, longitude - longitude_span).1, cell(0.0, longitude + longitude_span).1);
+            // Cells past the antimeridian wrap round to the other side.
+            (west..=east).map(|column| (column + 180).rem_euclid(360) - 180).collect()
+        };
+
+        let grid = self.grid.read();
+        let mut nearby: Vec<Nearby> = (lowest..=highest)
+            .flat_map(|row| columns.iter().map(move |column| (row, *column)))
+            .filter_map(|cell| grid.cells.get(&cell))
+            .flatten()
+            .filter_map(|name| {
+                let location = grid.locations[name];
+                let distance_km = location.distance_km(latitude, longitude);
+                (distance_km <= radius_km).then(|| Nearby { business_name: name.clone(), location, distance_km })
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km).then_with(|| a.business_name.cmp(&b.business_name)));
+        nearby
+    }
+}
+
+impl StoreObserver for SpatialIndex {
+    fn business_changed(&self, name: &str, business: Option<&BusinessResponse>) {
+        let location = business.and_then(|business| locate(&business.business));
+        // Only a move puts the business in another cell.
+        if self.grid.read().locations.get(name) == location.as_ref() {
+            return;
+        }
+        let mut grid = self.grid.write();
+        if let Some(old) = grid.locations.remove(name) {
+            let old_cell = cell(old.latitude, old.longitude);
+            if let Some(names) = grid.cells.get_mut(&old_cell) {
+                names.remove(name);
+                if names.is_empty() {
+                    grid.cells.remove(&old_cell);
+                }
+            }
+        }
+        if let Some(location) = location {
+            grid.cells.entry(cell(location.latitude, location.longitude)).or_default().insert(name.to_string());
+            grid.locations.insert(name.to_string(), location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::business::Category;
+
+    fn business(name: &str, zip: &str, coordinates: Option<(f64, f64)>) -> BusinessResponse {
+        BusinessResponse::new(
+            Business {
+                name: name.into(),
+                street_addr: "1 Main St".into(),
+                city: "Houston".into(),
+                state: "Texas".into(),
+                zip: zip.parse().unwrap(),
+                phone_num: "713-555-0100".parse().unwrap(),
+                category: Category { main_category: "Restaurant".into(), subcategory: "Pizza".into() },
+                category_ids: Vec::new(),
+                email: None,
+                website: None,
+                latitude: coordinates.map(|(latitude, _)| latitude),
+                longitude: coordinates.map(|(_, longitude)| longitude),
+                hours: None,
+                attributes: Default::default(),
+            },
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn radius_search_sorts_by_distance_and_falls_back_to_zip() {
+        let index = SpatialIndex::new();
+        for business in [
+            business("Downtown", "77002", None),
+            business("Galleria", "77056", Some((29.7390, -95.4636))),
+            business("Austin", "78701", Some((30.2672, -97.7431))),
+            business("Nowhere", "99999", None),
+        ] {
+            index.business_changed(&business.business.name.clone(), Some(&business));
+        }
+
+        let nearby = index.within(29.7604, -95.3698, 50.0);
+        let names: Vec<&str> = nearby.iter().map(|nearby| nearby.business_name.as_str()).collect();
+        assert_eq!(names, ["Downtown", "Galleria"]);
+        assert!(nearby[0].location.derived_from_zip);
+        assert!((nearby[1].distance_km - 9.3).abs() < 0.5);
+        assert_eq!(index.within(29.7604, -95.3698, 300.0).len(), 3);
+    }
+
+    #[test]
+    fn searches_wrap_round_the_antimeridian() {
+        let index = SpatialIndex::new();
+        let fiji = business("Suva", "00000", Some((-18.14, 179.9)));
+        index.business_changed("Suva", Some(&fiji));
+        assert_eq!(index.within(-18.14, -179.9, 50.0).len(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+// Liveness, readiness and build information for container orchestrators.
+// Liveness only says the process is serving requests. Readiness says it should be sent traffic:
+// the store answers, the data it starts from has been loaded, and it isn't shutting down.
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::store::Store;
+
+/// How long a readiness probe waits on a shard lock before calling the store unreachable.
+const STORE_PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+pub struct Readiness {
+    seed_loaded: AtomicBool,
+    migrations_applied: AtomicBool,
+    shutting_down: AtomicBool,
+    /// Where the snapshot backend writes to, if it's in use.
+    snapshot_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    pub fn new(snapshot_path: Option<PathBuf>) -> Self {
+        Readiness {
+            seed_loaded: AtomicBool::new(false),
+            migrations_applied: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            snapshot_path,
+        }
+    }
+
+    /// Called once the seed file has been loaded, or straight away when there isn't one.
+    pub fn mark_seed_loaded(&self) {
+        self.seed_loaded.store(true, Ordering::Release);
+    }
+
+    /// Called once every file the store was loaded from has been migrated to the current snapshot format.
+    pub fn mark_migrations_applied(&self) {
+        self.migrations_applied.store(true, Ordering::Release);
+    }
+
+    /// Called when shutdown starts, so load balancers stop sending new requests while the rest drain.
+    pub fn mark_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Release);
+    }
+
+    pub fn check(&self, store: &Store) -> ReadinessReport {
+        let checks = vec![
+            self.storage_check(store),
+            Check {
+                name: "seed_loaded",
+                ok: self.seed_loaded.load(Ordering::Acquire),
+                details: None,
+            },
+            Check {
+                name: "migrations_applied",
+                ok: self.migrations_applied.load(Ordering::Acquire),
+                details: None,
+            },
+            Check {
+                name: "accepting_requests",
+                ok: !self.shutting_down.load(Ordering::Acquire),
+                details: None,
+            },
+        ];
+        ReadinessReport { ready: checks.iter().all(|check| check.ok), checks }
+    }
+
+    fn storage_check(&self, store: &Store) -> Check {
+        let failure = |details: String| Check { name: "storage", ok: false, details: Some(details) };
+        if !store.is_reachable(STORE_PROBE_TIMEOUT) {
+            return failure("The store is locked and not answering".into());
+        }
+        // The snapshot only gets written at shutdown, so make sure that's still going to work.
+        if let Some(path) = &self.snapshot_path {
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            match fs::metadata(&directory) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    return failure(format!("{} is not a directory", directory.display()))
+                }
+                Ok(metadata) if metadata.permissions().readonly() => {
+                    return failure(format!("{} is read-only", directory.display()))
+                }
+                Ok(_) => {}
+                Err(error) => return failure(format!("{}: {error}", directory.display())),
+            }
+        }
+        Check { name: "storage", ok: true, details: None }
+    }
+}
+
+/// The crate version, the commit it was built from, the cargo features it was built with, and how long it's been up.
+pub fn build_info(started_at: Instant) -> Value {
+    let features: Vec<&str> = env!("BELP_FEATURES").split(',').filter(|feature| !feature.is_empty()).collect();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("BELP_GIT_HASH"),
+        "features": features,
+        "uptime_secs": started_at.elapsed().as_secs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_once_loaded_and_not_after_shutdown_starts() {
+        let store = Store::new();
+        let readiness = Readiness::new(None);
+        assert!(!readiness.check(&store).ready);
+
+        readiness.mark_seed_loaded();
+        readiness.mark_migrations_applied();
+        assert!(readiness.check(&store).ready);
+
+        readiness.mark_shutting_down();
+        let report = readiness.check(&store);
+        assert!(!report.ready);
+        assert!(report.checks.iter().any(|check| check.name == "accepting_requests" && !check.ok));
+    }
+
+    #[test]
+    fn missing_snapshot_directory_is_not_ready() {
+        let readiness = Readiness::new(Some(PathBuf::from("/this/does/not/exist/belp.json")));
+        readiness.mark_seed_loaded();
+        readiness.mark_migrations_applied();
+        assert!(!readiness.check(&Store::new()).ready);
+    }
+}
@@ -0,0 +1,265 @@
+// Opening hours. A business has hours for each day of the week in its own time zone, plus special hours for
+// particular dates (holidays, events) that replace the weekly hours on that date.
+//
+// A range that closes at or before the time it opens runs past midnight, so `18:00`-`02:00` is a late night and
+// `00:00`-`00:00` is open around the clock. The hours after midnight belong to the day the range opened on.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Day {
+    const ALL: [Day; 7] = [Day::Monday, Day::Tuesday, Day::Wednesday, Day::Thursday, Day::Friday, Day::Saturday, Day::Sunday];
+
+    fn of(date: NaiveDate) -> Day {
+        Day::ALL[date.weekday().num_days_from_monday() as usize]
+    }
+
+    fn next(self) -> Day {
+        Day::ALL[(self as usize + 1) % 7]
+    }
+}
+
+/// One stretch of opening hours, as local `HH:MM` times. `24:00` is accepted as the end of the day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TimeRange {
+    #[serde(with = "clock_time")]
+    #[schema(value_type = String, example = "09:00")]
+    pub opens: NaiveTime,
+    #[serde(with = "clock_time")]
+    #[schema(value_type = String, example = "17:30")]
+    pub closes: NaiveTime,
+}
+
+impl TimeRange {
+    /// Minutes after the opening day's midnight. The end goes past 1440 for ranges that run overnight.
+    fn minutes(&self) -> (u32, u32) {
+        let (opens, closes) = (minute_of_day(self.opens), minute_of_day(self.closes));
+        if closes <= opens {
+            (opens, closes + MINUTES_PER_DAY)
+        } else {
+            (opens, closes)
+        }
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.opens.format("%H:%M"), self.closes.format("%H:%M"))
+    }
+}
+
+/// Hours that replace the weekly ones on one date.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SpecialHours {
+    #[schema(value_type = String, example = "2026-12-25")]
+    pub date: NaiveDate,
+    /// Left empty, the business is closed all day.
+    #[serde(default)]
+    pub ranges: Vec<TimeRange>,
+    /// What's special about the day, e.g. `Christmas Day`.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Hours {
+    /// IANA time zone the hours are in, e.g. `America/Chicago`.
+    #[schema(value_type = String, example = "America/Chicago")]
+    pub time_zone: Tz,
+    /// Days that are left out are closed.
+    #[serde(default)]
+    pub weekly: BTreeMap<Day, Vec<TimeRange>>,
+    #[serde(default)]
+    pub special: Vec<SpecialHours>,
+}
+
+/// A point in time to check the hours at, from `?open_at=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAt {
+    /// An exact moment, e.g. `2026-10-18T21:00:00Z`.
+    Instant(DateTime<Utc>),
+    /// A wall clock time without an offset, e.g. `2026-10-18T21:00`, read in each business's own time zone.
+    Local(NaiveDateTime),
+}
+
+impl OpenAt {
+    pub fn parse(input: &str) -> Option<OpenAt> {
+        let input = input.trim();
+        if let Ok(instant) = DateTime::parse_from_rfc3339(input) {
+            return Some(OpenAt::Instant(instant.with_timezone(&Utc)));
+        }
+        ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+            .map(OpenAt::Local)
+    }
+}
+
+impl Hours {
+    /// Overlapping ranges and dates with special hours twice. Empty if the hours are fine.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for day in Day::ALL {
+            let ranges = self.weekly.get(&day).map(Vec::as_slice).unwrap_or_default();
+            problems.extend(overlaps(&format!("{day:?}"), ranges));
+            // A range that runs past midnight can't run into the next day's first one either.
+            let runs_until = ranges.iter().map(|range| range.minutes().1).max().unwrap_or(0);
+            let next = self.weekly.get(&day.next()).map(Vec::as_slice).unwrap_or_default();
+            if let Some(early) = next.iter().find(|range| range.minutes().0 + MINUTES_PER_DAY < runs_until) {
+                problems.push(format!("{day:?} runs past midnight into {:?}'s {early}", day.next()));
+            }
+        }
+        let mut dates = HashSet::new();
+        for special in &self.special {
+            if !dates.insert(special.date) {
+                problems.push(format!("{} has special hours more than once", special.date));
+            }
+            problems.extend(overlaps(&special.date.to_string(), &special.ranges));
+        }
+        problems
+    }
+
+    pub fn is_open_now(&self) -> bool {
+        self.is_open(OpenAt::Instant(Utc::now()))
+    }
+
+    pub fn is_open(&self, at: OpenAt) -> bool {
+        let local = match at {
+            OpenAt::Instant(instant) => instant.with_timezone(&self.time_zone).naive_local(),
+            OpenAt::Local(local) => local,
+        };
+        let minute = minute_of_day(local.time());
+        let today = local.date();
+        let open_today = self.ranges_on(today).iter().any(|range| {
+            let (opens, closes) = range.minutes();
+            opens <= minute && minute < closes
+        });
+        let open_since_yesterday = today
+            .pred_opt()
+            .is_some_and(|yesterday| self.ranges_on(yesterday).iter().any(|range| minute + MINUTES_PER_DAY < range.minutes().1));
+        open_today || open_since_yesterday
+    }
+
+    /// The date's special hours if it has any, otherwise the weekly hours for its day.
+    fn ranges_on(&self, date: NaiveDate) -> &[TimeRange] {
+        match self.special.iter().find(|special| special.date == date) {
+            Some(special) => &special.ranges,
+            None => self.weekly.get(&Day::of(date)).map(Vec::as_slice).unwrap_or_default(),
+        }
+    }
+}
+
+fn minute_of_day(time: NaiveTime) -> u32 {
+    time.hour() * 60 + time.minute()
+}
+
+/// A problem for every range that starts before an earlier one has closed.
+fn overlaps(label: &str, ranges: &[TimeRange]) -> Vec<String> {
+    let mut sorted: Vec<&TimeRange> = ranges.iter().collect();
+    sorted.sort_by_key(|range| range.minutes());
+    let mut problems = Vec::new();
+    let mut latest: Option<&TimeRange> = None;
+    for range in sorted {
+        if let Some(previous) = latest {
+            if range.minutes().0 < previous.minutes().1 {
+                problems.push(format!("{label}: {range} overlaps {previous}"));
+            }
+        }
+        if latest.is_none_or(|previous| range.minutes().1 > previous.minutes().1) {
+            latest = Some(range);
+        }
+    }
+    problems
+}
+
+/// `HH:MM` times, with `24:00` read as midnight.
+mod clock_time {
+    use chrono::NaiveTime;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%H:%M"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        if time == "24:00" {
+            return Ok(NaiveTime::MIN);
+        }
+        NaiveTime::parse_from_str(&time, "%H:%M").map_err(|_| de::Error::custom(format!("`{time}` should be a time like `09:30`")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hours(document: serde_json::Value) -> Hours {
+        serde_json::from_value(document).unwrap()
+    }
+
+    fn local(at: &str) -> OpenAt {
+        OpenAt::parse(at).unwrap()
+    }
+
+    #[test]
+    fn late_nights_and_holidays() {
+        let bar = hours(json!({
+            "time_zone": "America/Chicago",
+            "weekly": {
+                "friday": [{ "opens": "11:00", "closes": "14:00" }, { "opens": "18:00", "closes": "02:00" }],
+                "saturday": [{ "opens": "18:00", "closes": "24:00" }]
+            },
+            "special": [{ "date": "2026-10-24", "ranges": [], "note": "Private event" }]
+        }));
+        assert!(bar.problems().is_empty(), "{:?}", bar.problems());
+
+        // 2026-10-16 is a Friday.
+        assert!(bar.is_open(local("2026-10-16T12:30")));
+        assert!(!bar.is_open(local("2026-10-16T15:00")));
+        assert!(bar.is_open(local("2026-10-17T01:30")));
+        assert!(!bar.is_open(local("2026-10-17T02:00")));
+        assert!(bar.is_open(local("2026-10-17T23:59")));
+        assert!(!bar.is_open(local("2026-10-24T19:00")));
+        // 19:30 in Chicago, during daylight saving time.
+        assert!(bar.is_open(local("2026-10-17T00:30:00Z")));
+    }
+
+    #[test]
+    fn overlapping_ranges_are_problems() {
+        let overlapping = hours(json!({
+            "time_zone": "America/New_York",
+            "weekly": {
+                "monday": [{ "opens": "09:00", "closes": "17:00" }, { "opens": "12:00", "closes": "13:00" }],
+                "tuesday": [{ "opens": "20:00", "closes": "03:00" }],
+                "wednesday": [{ "opens": "02:00", "closes": "10:00" }]
+            },
+            "special": [{ "date": "2026-12-25" }, { "date": "2026-12-25" }]
+        }));
+        assert_eq!(overlapping.problems(), [
+            "Monday: 12:00-13:00 overlaps 09:00-17:00",
+            "Tuesday runs past midnight into Wednesday's 02:00-10:00",
+            "2026-12-25 has special hours more than once",
+        ]);
+        assert!(serde_json::from_value::<Hours>(json!({ "time_zone": "Mars/Olympus_Mons" })).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+pub mod address;
+pub mod admin;
+pub mod attributes;
+pub mod autocomplete;
+pub mod business;
+pub mod claims;
+pub mod config;
+pub mod cors;
+pub mod duplicates;
+pub mod geo;
+pub mod health;
+pub mod hours;
+pub mod logging;
+pub mod metrics;
+pub mod phone;
+pub mod preconditions;
+pub mod rate_limit;
+pub mod search;
+pub mod snapshot;
+pub mod store;
+pub mod taxonomy;
+pub mod versioning;
+pub mod zips;
@@ -0,0 +1,60 @@
+// Structured logging through `tracing`. Every request gets an ID (taken from X-Request-Id if the client or a proxy
+// sent a usable one, generated otherwise) and a span carrying it, so everything logged while handling the request
+// can be tied back to it.
+use actix_web::http::header::{HeaderMap, HeaderName};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LogFormat;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming request ID that gets passed along. Anything longer is replaced, it's not worth logging.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The ID of the request being handled, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Reuses the incoming X-Request-Id when it's short and printable, otherwise makes a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let incoming = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .filter(|id| id.chars().all(|c| c.is_ascii_graphic()));
+        match incoming {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+/// Installs the global subscriber. `RUST_LOG` wins over `level` when it's set, for one-off debugging.
+pub fn init(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level.to_ascii_lowercase()));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HeaderValue;
+
+    #[test]
+    fn usable_request_ids_are_propagated() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+        assert_eq!(RequestId::from_headers(&headers), RequestId("abc-123".into()));
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("has spaces in it"));
+        assert_ne!(RequestId::from_headers(&headers).0, "has spaces in it");
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&"x".repeat(200)).unwrap());
+        assert_eq!(RequestId::from_headers(&headers).0.len(), 36);
+    }
+}
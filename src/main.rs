@@ -1,169 +1,1682 @@
 #![allow(non_snake_case)]
-mod business;
-use std::collections::HashMap;
+// Handlers bail out early with a ready-made `HttpResponse` as the error, boxing them would only add noise.
+#![allow(clippy::result_large_err)]
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Instant;
 
-use business::BusinessResponse;
+use playground_site::business::{Business, BusinessPatch, BusinessPatchError, BusinessResponse};
 use serde_json::json;
 // use reviews::Review;
 use actix_web::{
-    delete, dev::Server, get, post, put, web, App, HttpResponse, HttpServer, Responder,
+    body::MessageBody,
+    delete, dev::{Server, Service, ServiceFactory, ServiceRequest, ServiceResponse}, get,
+    http::{header::{self, HeaderValue}, Uri},
+    patch, post, put, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use tokio;
-use tokio::sync::RwLock;
 
-use crate::business::{Review, Photo};
+use playground_site::admin;
+use playground_site::attributes::{self, AttributeDefinition, AttributeFilter};
+use playground_site::autocomplete::Autocomplete;
+use playground_site::business::{
+    CoverPhoto, NewPhoto, OwnerResponse, OwnerResponseError, OwnerResponseText, PhotoCaptionUpdate, PhotoOrder, Review,
+};
+use playground_site::rate_limit::{RateLimit, RateLimiter};
+use playground_site::preconditions::{check_if_match, not_modified, not_modified_response, with_etag};
+use playground_site::claims::{
+    self, AuditEntry, ClaimConfirmation, ClaimError, ClaimEvent, ClaimRequest, ClaimSent, Claims, FileNotifier, LogNotifier,
+    Notifier, OwnerAssignment, Ownership,
+};
+use playground_site::config::{Cli, Config, NotifierBackend, StorageBackend};
+use playground_site::duplicates::{self, DuplicateCandidate, MergeRequest, Redirects};
+use playground_site::geo::{self, SpatialIndex};
+use playground_site::health::{self, Readiness};
+use playground_site::hours::OpenAt;
+use playground_site::logging::{self, RequestId, REQUEST_ID_HEADER};
+use playground_site::metrics;
+use playground_site::search::SearchIndex;
+use playground_site::snapshot;
+use playground_site::store::Store;
+use playground_site::taxonomy::{CategoryId, CategoryNode, CategoryRecord, NewCategory, Taxonomy, TaxonomyError};
+use playground_site::versioning::{self, Negotiated, API_VERSION_HEADER};
+use chrono::Utc;
+use clap::Parser;
+use serde::Deserialize;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tracing::{error, info, Instrument};
 
 // use crate::endpoints::AppError;
 
-type AtomicDB = Arc<RwLock<HashMap<String, BusinessResponse>>>;
+mod api_docs;
+
+type AtomicDB = Arc<Store>;
+
+/// The indexes kept over the store, registered as observers before anything is loaded.
+struct Indexes {
+    search: Arc<SearchIndex>,
+    autocomplete: Arc<Autocomplete>,
+    spatial: Arc<SpatialIndex>,
+}
 
 struct AppState {
     app_name: String,
     mock_database: AtomicDB,
+    /// Refuse writes to existing resources that don't send an If-Match header.
+    require_if_match: bool,
+    readiness: Arc<Readiness>,
+    search: Arc<SearchIndex>,
+    autocomplete: Arc<Autocomplete>,
+    spatial: Arc<SpatialIndex>,
+    taxonomy: Arc<Taxonomy>,
+    /// Where the names of merged businesses point now.
+    redirects: Arc<Redirects>,
+    claims: Arc<Claims>,
+    /// Bearer token for the admin endpoints, `None` turns them off.
+    admin_token: Option<String>,
+    started_at: Instant,
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    create_server()?.await?;
+    let config = match Config::load(&Cli::parse()) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("error: {error}");
+            std::process::exit(2);
+        }
+    };
+    logging::init(&config.log_level, config.log_format);
+
+    // The indexes have to be watching before anything is loaded, they only see writes made after they're added.
+    let search = Arc::new(SearchIndex::new());
+    let autocomplete = Arc::new(Autocomplete::new());
+    let spatial = Arc::new(SpatialIndex::new());
+    let mut store = Store::with_shards(config.storage_shards);
+    store.add_observer(search.clone());
+    store.add_observer(autocomplete.clone());
+    store.add_observer(spatial.clone());
+    let database: AtomicDB = Arc::new(store);
+    let snapshot_path = match config.storage_backend {
+        StorageBackend::Snapshot => config.storage_path.clone(),
+        StorageBackend::Memory => None,
+    };
+    let readiness = Arc::new(Readiness::new(snapshot_path));
+    let taxonomy = Arc::new(Taxonomy::with_defaults());
+    let redirects = Arc::new(Redirects::new());
+    let notifier: Box<dyn Notifier> = match (config.notifier, &config.notifier_outbox) {
+        (NotifierBackend::File, Some(outbox)) => Box::new(FileNotifier::new(outbox.clone())),
+        _ => Box::new(LogNotifier),
+    };
+    let claims = Arc::new(Claims::new(notifier));
+    if let Err(error) = load_data(&config, &database, &taxonomy, &redirects, &claims, &readiness) {
+        error!("{error}");
+        std::process::exit(1);
+    }
+
+    let indexes = Indexes { search, autocomplete, spatial };
+    let server = create_server(&config, database.clone(), taxonomy.clone(), redirects.clone(), claims.clone(), readiness.clone(), indexes)?;
+    let server_handle = server.handle();
+    tokio::spawn(async move {
+        let signal = shutdown_signal().await;
+        info!(signal, "No longer accepting connections, draining in-flight requests");
+        readiness.mark_shutting_down();
+        // Waits for in-flight requests, up to the shutdown timeout.
+        server_handle.stop(true).await;
+    });
+    server.await?;
+
+    // Every request has either finished or been dropped by now, so nothing can change the store while it's flushed.
+    if let (StorageBackend::Snapshot, Some(path)) = (config.storage_backend, &config.storage_path) {
+        match snapshot::save(path, &database, &taxonomy, &redirects, &claims) {
+            Ok(saved) => info!(businesses = saved, path = %path.display(), "Flushed the store"),
+            Err(error) => {
+                error!("Shutting down without saving the store: {error}");
+                std::process::exit(1);
+            }
+        }
+    }
+    info!("Shutdown complete");
     Ok(())
 }
 
-fn create_server() -> std::io::Result<Server> {
-    let server_data = web::Data::new(AppState {
-        app_name: "Belp".into(),
-        mock_database: Arc::new(RwLock::new(HashMap::new())),
-    });
-    // Shared data setup ^^^
+/// Resolves on the first SIGINT or SIGTERM, returning which one it was.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("could not listen for Ctrl-C");
+        "Ctrl-C"
+    }
+}
+
+/// Fills the store before the server starts: the snapshot first (if there is one), then the seed file.
+/// Older snapshot formats are migrated as they're read, and businesses without category IDs are linked to the tree.
+fn load_data(
+    config: &Config,
+    database: &Store,
+    taxonomy: &Taxonomy,
+    redirects: &Redirects,
+    claims: &Claims,
+    readiness: &Readiness,
+) -> Result<(), snapshot::SnapshotError> {
+    if let (StorageBackend::Snapshot, Some(path)) = (config.storage_backend, &config.storage_path) {
+        let loaded = snapshot::load(path, database, taxonomy, redirects, claims)?;
+        info!(businesses = loaded.inserted, path = %path.display(), "Loaded the snapshot{}", migration_note(loaded));
+    }
+    readiness.mark_migrations_applied();
+    if let Some(seed_file) = &config.seed_file {
+        let seeded = snapshot::seed(seed_file, database, taxonomy)?;
+        info!(businesses = seeded.inserted, path = %seed_file.display(), "Loaded the seed file{}", migration_note(seeded));
+    }
+    readiness.mark_seed_loaded();
+    Ok(())
+}
 
-    let app = move || {
-        App::new()
-            .app_data(server_data.clone()) // App data uses Arc, so I don't have to.
-            .service(index)
-            .service(add_business)
-            .service(get_businesses)
-            .service(delete_business)
-            .service(find_business)
-            .service(web::scope("/review")
-                .service(add_review)
-                .service(delete_review)
-                .service(update_review)
-                .service(show_business_reviews)
-                .service(business_user_reviews))
-            .service(web::scope("/photos")
-                .service(add_photo)
-                .service(delete_photo)
-                .service(update_photo))
+fn migration_note(report: snapshot::LoadReport) -> String {
+    if report.format_version == snapshot::FORMAT_VERSION {
+        return String::new();
+    }
+    format!(" (migrated from format version {} to {})", report.format_version, snapshot::FORMAT_VERSION)
+}
 
-    };
+fn create_server(
+    config: &Config,
+    database: AtomicDB,
+    taxonomy: Arc<Taxonomy>,
+    redirects: Arc<Redirects>,
+    claims: Arc<Claims>,
+    readiness: Arc<Readiness>,
+    indexes: Indexes,
+) -> std::io::Result<Server> {
+    let server_data = app_state(config, database, taxonomy, redirects, claims, readiness, indexes);
+    // One limiter for every worker, otherwise each worker would hand out its own allowance.
+    let rate_limiter = rate_limiter(config, &server_data);
+    let app_config = config.clone();
+    // Shared data setup ^^^
+
+    let app = move || build_app(&app_config, server_data.clone(), rate_limiter.clone());
     // App setup ^^^
 
-    Ok(HttpServer::new(app).bind(("127.0.0.1", 33333))?.run())
+    // Signals are handled in `main`, actix would otherwise treat SIGINT as a hard stop.
+    let mut server = HttpServer::new(app)
+        .max_connections(config.max_connections)
+        .shutdown_timeout(config.shutdown_timeout_secs)
+        .disable_signals();
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    info!("Listening on http://{}:{}", config.host, config.port);
+    Ok(server.bind((config.host.as_str(), config.port))?.run())
     // Server setup ^^^
 }
 
+fn app_state(
+    config: &Config,
+    database: AtomicDB,
+    taxonomy: Arc<Taxonomy>,
+    redirects: Arc<Redirects>,
+    claims: Arc<Claims>,
+    readiness: Arc<Readiness>,
+    indexes: Indexes,
+) -> web::Data<AppState> {
+    web::Data::new(AppState {
+        app_name: "Belp".into(),
+        mock_database: database,
+        require_if_match: config.require_if_match,
+        readiness,
+        search: indexes.search,
+        autocomplete: indexes.autocomplete,
+        spatial: indexes.spatial,
+        taxonomy,
+        redirects,
+        claims,
+        admin_token: config.admin_token.clone(),
+        started_at: Instant::now(),
+    })
+}
+
+/// The rate limiter. Requests with the admin token or an owner token count against the admin or the owner, wherever
+/// they come from.
+fn rate_limiter(config: &Config, state: &web::Data<AppState>) -> web::Data<RateLimiter> {
+    let state = state.clone();
+    let limiter = RateLimiter::new(config.rate_limit.clone())
+        .with_identity(move |request| admin::identity(request, state.admin_token.as_deref(), &state.claims));
+    web::Data::new(limiter)
+}
+
+/// The app every worker runs: shared data, middleware and routes.
+fn build_app(
+    config: &Config,
+    server_data: web::Data<AppState>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let max_body_bytes = config.max_body_bytes;
+    let api_config = config.api.clone();
+    App::new()
+        .app_data(server_data) // App data uses Arc, so I don't have to.
+        .app_data(rate_limiter)
+        .app_data(web::JsonConfig::default().limit(max_body_bytes))
+        .app_data(web::PayloadConfig::new(max_body_bytes))
+        // Picks the API version before routing, so unversioned paths can be sent to the right scope.
+        .wrap_fn(move |mut request, service| {
+            let accept = request.headers().get(header::ACCEPT).and_then(|accept| accept.to_str().ok());
+            let negotiated = versioning::negotiate(request.path(), accept, &api_config);
+            let unsupported = matches!(negotiated, Negotiated::Unsupported(_));
+            let (version, rewritten) = match negotiated {
+                Negotiated::Unversioned => (None, false),
+                Negotiated::Versioned(version) => (Some(version), false),
+                Negotiated::Unsupported(version) => (Some(version), false),
+                Negotiated::Rewrite { version, path } => {
+                    let path_and_query = match request.query_string() {
+                        "" => path,
+                        query => format!("{path}?{query}"),
+                    };
+                    let mut parts = request.head().uri.clone().into_parts();
+                    parts.path_and_query = path_and_query.parse().ok();
+                    if let Ok(uri) = Uri::from_parts(parts) {
+                        request.match_info_mut().get_mut().update(&uri);
+                        request.head_mut().uri = uri;
+                    }
+                    (Some(version), true)
+                }
+            };
+            let path = request.path().to_string();
+            let response = if unsupported {
+                Err(request.into_response(HttpResponse::NotAcceptable().json(json!({
+                    "error": format!("API version {} is not supported", version.unwrap_or_default()),
+                    "supported_versions": versioning::SUPPORTED_VERSIONS
+                }))))
+            } else {
+                Ok(service.call(request))
+            };
+            let api_config = api_config.clone();
+            async move {
+                let mut response = match response {
+                    Ok(response) => response.await?,
+                    Err(not_acceptable) => return Ok(not_acceptable),
+                };
+                if let Some(version) = version {
+                    let headers = response.headers_mut();
+                    headers.insert(API_VERSION_HEADER, HeaderValue::from(version));
+                    if rewritten {
+                        headers.append(header::VARY, HeaderValue::from_static("accept"));
+                    }
+                    for (name, value) in versioning::deprecation_headers(version, &path, &api_config) {
+                        headers.insert(name, value);
+                    }
+                }
+                Ok(response)
+            }
+        })
+        // Routes are labelled by their pattern, so every business shares one series instead of one each.
+        .wrap_fn(|request, service| {
+            let started = Instant::now();
+            let method = request.method().to_string();
+            let response = service.call(request);
+            async move {
+                let response = response.await?;
+                let route = response.request().match_pattern().unwrap_or_else(|| "unmatched".into());
+                metrics::observe_request(&method, &route, response.status().as_u16(), started.elapsed());
+                Ok(response)
+            }
+        })
+        // Preflights are answered here without reaching the routes (or the rate limiter).
+        .wrap(config.cors.build())
+        // Outermost, so the request ID and span cover everything else, metrics included.
+        .wrap_fn(|request, service| {
+            let request_id = RequestId::from_headers(request.headers());
+            let span = tracing::info_span!(
+                "request",
+                request_id = %request_id.0,
+                method = %request.method(),
+                path = %request.path(),
+                route = tracing::field::Empty,
+                business = tracing::field::Empty,
+                user = tracing::field::Empty,
+                status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            );
+            request.extensions_mut().insert(request_id.clone());
+            let started = Instant::now();
+            let response = service.call(request).instrument(span.clone());
+            async move {
+                let mut response = response.await?;
+                let matched = response.request().match_info();
+                if let Some(business) = matched.get("business_name") {
+                    span.record("business", business);
+                }
+                if let Some(user) = matched.get("reviewer_name").or_else(|| matched.get("user_name")) {
+                    span.record("user", user);
+                }
+                if let Some(route) = response.request().match_pattern() {
+                    span.record("route", route.as_str());
+                }
+                span.record("status", response.status().as_u16());
+                span.record("latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+                span.in_scope(|| info!("Request finished"));
+
+                if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(response)
+            }
+        })
+        .service(index)
+        .service(healthz)
+        .service(readyz)
+        .service(build_version)
+        .service(prometheus_metrics)
+        .service(api_docs::openapi_document)
+        .service(api_docs::docs)
+        .service(web::scope("/v1").configure(v1_routes))
+        .service(web::scope("/v2").configure(v2_routes))
+}
+
+/// The first version of the API, the routes as they were before versioning.
+fn v1_routes(config: &mut web::ServiceConfig) {
+    config.service(get_businesses).configure(shared_routes);
+}
+
+/// The second version. Listing businesses is paginated, everything else is still the same as v1.
+fn v2_routes(config: &mut web::ServiceConfig) {
+    config.service(list_businesses).configure(shared_routes);
+}
+
+/// Routes that haven't changed shape between versions. Each version gets its own copy over the same store.
+fn shared_routes(config: &mut web::ServiceConfig) {
+    config
+        .service(add_business)
+        .service(delete_business)
+        .service(find_business)
+        .service(update_business)
+        .service(patch_business)
+        .service(search_businesses)
+        .service(autocomplete_names)
+        .service(list_attributes)
+        .service(web::scope("/categories")
+            .service(list_categories)
+            .service(find_category)
+            .service(add_category)
+            .service(update_category)
+            .service(delete_category))
+        .service(web::scope("/duplicates")
+            .service(list_duplicates)
+            .service(merge_duplicates))
+        .service(web::scope("/claims")
+            .service(request_claim)
+            .service(confirm_claim)
+            .service(assign_owner)
+            .service(remove_owner)
+            .service(claim_audit))
+        .service(web::scope("/review")
+            .service(add_review)
+            .service(delete_review)
+            .service(update_review)
+            .service(show_business_reviews)
+            .service(business_user_reviews))
+        .service(web::scope("/responses")
+            .service(add_owner_response)
+            .service(update_owner_response)
+            .service(delete_owner_response))
+        .service(web::scope("/photos")
+            .service(add_photo)
+            .service(reorder_photos)
+            .service(set_cover_photo)
+            .service(delete_photo)
+            .service(update_photo));
+}
+
+/// Adds a new business.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    request_body = BusinessResponse,
+    responses(
+        (status = 200, description = "The business was added, wrapped in `body.payload`"),
+        (status = 409, description = "A business with that name already exists"),
+        (status = 422, description = "The business is invalid, e.g. its ZIP code is in another state"),
+    )
+)]
 #[post("/business")]
 async fn add_business(
     business_data: web::Json<BusinessResponse>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
+    let mut business_data = business_data.into_inner();
+    let _linking = resources.taxonomy.linking();
+    resources.taxonomy.link(&mut business_data.business);
+    if let Some(response) = invalid_business(&business_data.business, None, &resources.taxonomy) {
+        return Ok(response);
+    }
+    // Photo IDs, versions and owners are always handed out by the server.
+    business_data.version = 1;
+    business_data.reassign_photo_ids(None);
+    business_data.carry_nested_versions(None);
+    business_data.keep_owner_responses(None);
+    business_data.owner = None;
     let database = resources.mock_database.clone();
-    if database
-        .read()
-        .await
-        .contains_key(&business_data.business.name)
-    {
-        return Ok(HttpResponse::Conflict().body("Business already exists"));
+    if !database.insert_new(business_data.business.name.clone(), business_data.clone()) {
+        Ok(HttpResponse::Conflict().body("Business already exists"))
     } else {
-        database
-            .write()
-            .await
-            .insert(business_data.business.name.clone(), business_data.clone());
-
-        Ok(HttpResponse::Ok().json(json!({
+        resources.redirects.reuse(&business_data.business.name);
+        Ok(with_etag(HttpResponse::Ok().json(json!({
             "return_code": 200,
             "body": {
                 "payload": business_data
             }
-        })))
+        })), business_data.version))
     }
 }
 
+/// The 422 to send back for a business that can't be stored, if it can't.
+fn invalid_business(business: &Business, previous: Option<&Business>, taxonomy: &Taxonomy) -> Option<HttpResponse> {
+    let mut problems = business.problems(previous);
+    for id in taxonomy.unknown(&business.category_ids) {
+        problems.push(format!("there's no category with ID {id}"));
+    }
+    if problems.is_empty() {
+        return None;
+    }
+    Some(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "The business is invalid",
+        "problems": problems
+    })))
+}
+
+/// Lists every business.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    params(NearQuery, FilterQuery),
+    responses(
+        (status = 200, description = "Every business, or with `near` the ones within `radius_km` sorted by distance", body = [BusinessResponse]),
+        (status = 400, description = "`near`, `radius_km` or one of the filters is malformed"),
+    )
+)]
 #[get("/business")]
-async fn get_businesses(resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+async fn get_businesses(
+    near: web::Query<NearQuery>,
+    filter: web::Query<FilterQuery>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let filter = match listing_filter(&filter, &resources, "Reached the business listing endpoint") {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    match nearby_businesses(&near, &filter, &resources) {
+        Ok(Some(nearby)) => return Ok(HttpResponse::Ok().json(nearby)),
+        Ok(None) => {}
+        Err(response) => return Ok(response),
+    }
     let database = resources.mock_database.clone();
-    let database_read: Vec<BusinessResponse> = database.read().await.values().cloned().collect();
-    Ok(web::Json(database_read))
+    let mut database_read: Vec<BusinessResponse> = database.all();
+    database_read.retain(|business| filter.matches(business));
+    Ok(HttpResponse::Ok().json(database_read))
 }
 
+#[derive(Deserialize, utoipa::IntoParams)]
+struct FilterQuery {
+    /// Only businesses in this category or any category under it.
+    category: Option<CategoryId>,
+    /// `true` for only the businesses open right now, `false` for only the closed ones. Businesses without hours
+    /// are left out either way.
+    open_now: Option<bool>,
+    /// Only businesses open at this time. With an offset (`2026-10-18T21:00:00-05:00`) it's the same moment
+    /// everywhere, without one (`2026-10-18T21:00`) it's read in each business's own time zone.
+    open_at: Option<String>,
+    /// Only businesses with these attributes, comma separated: `outdoor_seating` (a yes/no attribute that's true),
+    /// `wifi:free|paid` (any of those values), `price_range:..2` (a number range, either end can be left off).
+    attributes: Option<String>,
+}
+
+/// What a business has to match to be listed.
+struct Filter {
+    /// The category asked for and everything under it.
+    categories: Option<HashSet<CategoryId>>,
+    /// When to check the hours at, and whether the business should be open or closed then.
+    open: Option<(OpenAt, bool)>,
+    attributes: AttributeFilter,
+}
+
+impl Filter {
+    fn matches(&self, business: &BusinessResponse) -> bool {
+        let business = &business.business;
+        let in_category = self
+            .categories
+            .as_ref()
+            .is_none_or(|categories| business.category_ids.iter().any(|id| categories.contains(id)));
+        let open = self.open.is_none_or(|(at, open)| business.hours.as_ref().is_some_and(|hours| hours.is_open(at) == open));
+        in_category && open && self.attributes.matches(&business.attributes)
+    }
+}
+
+/// `notes` says which endpoint it was for the 400, if the query doesn't make sense.
+fn listing_filter(query: &FilterQuery, resources: &AppState, notes: &str) -> Result<Filter, HttpResponse> {
+    let bad_request = |error: String| {
+        HttpResponse::BadRequest().json(json!({
+            "notes": notes,
+            "error": error
+        }))
+    };
+    let categories = match query.category {
+        Some(id) => match resources.taxonomy.subtree(id) {
+            Some(subtree) => Some(subtree),
+            None => return Err(bad_request(format!("There's no category with ID {id}"))),
+        },
+        None => None,
+    };
+    let open = match (query.open_now, &query.open_at) {
+        (Some(_), Some(_)) => return Err(bad_request("`open_now` and `open_at` can't be used together".into())),
+        (Some(open), None) => Some((OpenAt::Instant(Utc::now()), open)),
+        (None, Some(open_at)) => match OpenAt::parse(open_at) {
+            Some(at) => Some((at, true)),
+            None => return Err(bad_request("`open_at` should be a time like `2026-10-18T21:00` or `2026-10-18T21:00:00-05:00`".into())),
+        },
+        (None, None) => None,
+    };
+    let attributes = match query.attributes.as_deref().map(AttributeFilter::parse) {
+        Some(Ok(attributes)) => attributes,
+        Some(Err(error)) => return Err(bad_request(error.to_string())),
+        None => AttributeFilter::default(),
+    };
+    Ok(Filter { categories, open, attributes })
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+struct NearQuery {
+    /// Only businesses near this point, given as `lat,lng`, closest first.
+    near: Option<String>,
+    /// How far from `near` to look, 10 km by default and at most 1000.
+    radius_km: Option<f64>,
+}
+
+/// The businesses around `near`, closest first and each with its distance, or `None` if no point was asked for.
+/// Businesses without coordinates are placed at the centre of their ZIP code.
+fn nearby_businesses(
+    query: &NearQuery,
+    filter: &Filter,
+    resources: &AppState,
+) -> Result<Option<Vec<serde_json::Value>>, HttpResponse> {
+    let near = match &query.near {
+        Some(near) => near,
+        None => return Ok(None),
+    };
+    let (latitude, longitude) = match geo::parse_point(near) {
+        Some(point) => point,
+        None => {
+            return Err(HttpResponse::BadRequest().json(json!({
+                "notes": "Reached the business listing endpoint",
+                "error": "`near` should be `lat,lng`, e.g. `29.76,-95.37`"
+            })))
+        }
+    };
+    let radius_km = query.radius_km.unwrap_or(10.0);
+    if !(radius_km > 0.0 && radius_km <= 1000.0) {
+        return Err(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the business listing endpoint",
+            "error": "`radius_km` should be more than 0 and at most 1000"
+        })));
+    }
+    let nearby = resources
+        .spatial
+        .within(latitude, longitude, radius_km)
+        .into_iter()
+        .filter_map(|nearby| {
+            let business = resources.mock_database.read(&nearby.business_name, BusinessResponse::clone)?;
+            if !filter.matches(&business) {
+                return None;
+            }
+            Some(json!({
+                "distance_km": nearby.distance_km,
+                "location": nearby.location,
+                "business": business
+            }))
+        })
+        .collect();
+    Ok(Some(nearby))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+struct PageQuery {
+    /// Starts at 1.
+    page: Option<usize>,
+    /// At most 100.
+    per_page: Option<usize>,
+}
+
+/// Which page of a listing to send back, from the `page` and `per_page` in the query.
+struct Pagination {
+    page: usize,
+    per_page: usize,
+}
+
+impl Pagination {
+    /// Pages start at 1 and hold 20 results unless asked otherwise, never more than 100.
+    fn new(page: Option<usize>, per_page: Option<usize>) -> Self {
+        Pagination { page: page.unwrap_or(1).max(1), per_page: per_page.unwrap_or(20).clamp(1, 100) }
+    }
+
+    /// How many results come before the page. A page too far out to count to is just past the end.
+    fn offset(&self) -> usize {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+}
+
+/// Lists businesses a page at a time, sorted by name.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v2",
+    params(PageQuery, NearQuery, FilterQuery),
+    responses(
+        (status = 200, description = "One page of businesses, with the total count. With `near`, only the ones within `radius_km`, closest first"),
+        (status = 400, description = "`near`, `radius_km` or one of the filters is malformed"),
+    )
+)]
+#[get("/business")]
+async fn list_businesses(
+    query: web::Query<PageQuery>,
+    near: web::Query<NearQuery>,
+    filter: web::Query<FilterQuery>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let pagination = Pagination::new(query.page, query.per_page);
+    let filter = match listing_filter(&filter, &resources, "Reached the business listing endpoint") {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    match nearby_businesses(&near, &filter, &resources) {
+        Ok(Some(nearby)) => {
+            let total = nearby.len();
+            let page_of_businesses: Vec<serde_json::Value> =
+                nearby.into_iter().skip(pagination.offset()).take(pagination.per_page).collect();
+            return Ok(HttpResponse::Ok().json(json!({
+                "businesses": page_of_businesses,
+                "page": pagination.page,
+                "per_page": pagination.per_page,
+                "total": total
+            })));
+        }
+        Ok(None) => {}
+        Err(response) => return Ok(response),
+    }
+    let mut businesses = resources.mock_database.all();
+    businesses.retain(|business| filter.matches(business));
+    businesses.sort_by(|a, b| a.business.name.cmp(&b.business.name));
+    let total = businesses.len();
+    let page_of_businesses: Vec<BusinessResponse> =
+        businesses.into_iter().skip(pagination.offset()).take(pagination.per_page).collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "businesses": page_of_businesses,
+        "page": pagination.page,
+        "per_page": pagination.per_page,
+        "total": total
+    })))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+struct SearchQuery {
+    /// Words to look for in business names, categories, cities and reviews, or a phone number in any format.
+    q: String,
+    /// Starts at 1.
+    page: Option<usize>,
+    /// At most 100.
+    per_page: Option<usize>,
+}
+
+/// Full-text search over businesses and their reviews, best matches first.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    params(SearchQuery, FilterQuery),
+    responses(
+        (status = 200, description = "One page of matches, each with the business and highlighted snippets of what matched"),
+        (status = 400, description = "The query is empty, or one of the filters is malformed"),
+    )
+)]
+#[get("/search")]
+async fn search_businesses(
+    query: web::Query<SearchQuery>,
+    filter: web::Query<FilterQuery>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if query.q.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the search endpoint",
+            "error": "The query `q` is empty"
+        })));
+    }
+    let filter = match listing_filter(&filter, &resources, "Reached the search endpoint") {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let pagination = Pagination::new(query.page, query.per_page);
+    let keep = |name: &str| resources.mock_database.read(name, |business| filter.matches(business)).unwrap_or(false);
+    let results = resources.search.search_where(&query.q, pagination.offset(), pagination.per_page, keep);
+    // The index only knows names, the businesses themselves come from the store. One could have been removed since
+    // the search ran, in which case it's left out of the page.
+    let matches: Vec<serde_json::Value> = results
+        .hits
+        .into_iter()
+        .filter_map(|hit| {
+            let business = resources.mock_database.read(&hit.business_name, BusinessResponse::clone)?;
+            Some(json!({
+                "business_name": hit.business_name,
+                "score": hit.score,
+                "highlights": hit.highlights,
+                "business": business
+            }))
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "query": query.q,
+        "results": matches,
+        "page": pagination.page,
+        "per_page": pagination.per_page,
+        "total": results.total
+    })))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+struct AutocompleteQuery {
+    /// What's been typed so far.
+    q: String,
+    /// At most 50.
+    limit: Option<usize>,
+}
+
+/// Suggests business and category names for a partly typed, possibly misspelled, name.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    params(AutocompleteQuery),
+    responses(
+        (status = 200, description = "The closest names, best first, with how many edits away from the query each is"),
+        (status = 400, description = "The query is empty"),
+    )
+)]
+#[get("/autocomplete")]
+async fn autocomplete_names(query: web::Query<AutocompleteQuery>, resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    if query.q.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the autocomplete endpoint",
+            "error": "The query `q` is empty"
+        })));
+    }
+    let limit = query.limit.unwrap_or(10).clamp(1, 50);
+    Ok(HttpResponse::Ok().json(json!({
+        "query": query.q,
+        "suggestions": resources.autocomplete.suggest(&query.q, limit)
+    })))
+}
+
+/// The attributes businesses can have, with the values each one takes.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "Every attribute, for `attributes` on a business and the `attributes` filter", body = [AttributeDefinition]),
+    )
+)]
+#[get("/attributes")]
+async fn list_attributes() -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(attributes::definitions()))
+}
+
+/// The category tree, top level categories first.
+#[utoipa::path(
+    tag = "categories",
+    context_path = "/v1/categories",
+    responses(
+        (status = 200, description = "Every top level category with everything under it", body = [CategoryNode]),
+    )
+)]
+#[get("")]
+async fn list_categories(resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(resources.taxonomy.roots()))
+}
+
+/// Fetches a category with everything under it.
+#[utoipa::path(
+    tag = "categories",
+    context_path = "/v1/categories",
+    responses(
+        (status = 200, description = "The category, its path from the top and its subcategories", body = CategoryNode),
+        (status = 404, description = "Category not found"),
+    )
+)]
+#[get("/{category_id}")]
+async fn find_category(category_id: web::Path<CategoryId>, resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    match resources.taxonomy.get(*category_id) {
+        Some(category) => Ok(HttpResponse::Ok().json(category)),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the category info endpoint",
+            "error": "Category not found"
+        }))),
+    }
+}
+
+/// Adds a category, at the top level or under another one. Admins only.
+#[utoipa::path(
+    tag = "categories",
+    context_path = "/v1/categories",
+    request_body = NewCategory,
+    responses(
+        (status = 201, description = "The category was added", body = CategoryRecord),
+        (status = 400, description = "The name is empty"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "The parent category doesn't exist"),
+        (status = 409, description = "The parent already has a category with that name"),
+    ),
+    security(("admin_token" = []))
+)]
+#[post("")]
+async fn add_category(
+    request: HttpRequest,
+    category: web::Json<NewCategory>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    match resources.taxonomy.create(category.into_inner()) {
+        Ok(category) => Ok(HttpResponse::Created().json(category)),
+        Err(error) => Ok(taxonomy_error(error)),
+    }
+}
+
+/// Renames a category and/or moves it under another parent, along with its subcategories. Admins only.
+#[utoipa::path(
+    tag = "categories",
+    context_path = "/v1/categories",
+    request_body = NewCategory,
+    responses(
+        (status = 200, description = "The category was updated", body = CategoryRecord),
+        (status = 400, description = "The name is empty"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "The category or the new parent doesn't exist"),
+        (status = 409, description = "The name is taken, or the category would end up under itself"),
+    ),
+    security(("admin_token" = []))
+)]
+#[put("/{category_id}")]
+async fn update_category(
+    request: HttpRequest,
+    category_id: web::Path<CategoryId>,
+    category: web::Json<NewCategory>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    match resources.taxonomy.update(*category_id, category.into_inner()) {
+        Ok(category) => Ok(HttpResponse::Ok().json(category)),
+        Err(error) => Ok(taxonomy_error(error)),
+    }
+}
+
+/// Removes a category. Only categories without subcategories, that no business is in, can be removed. Admins only.
+#[utoipa::path(
+    tag = "categories",
+    context_path = "/v1/categories",
+    responses(
+        (status = 200, description = "The category that was removed", body = CategoryRecord),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "Category not found"),
+        (status = 409, description = "The category still has subcategories or businesses"),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/{category_id}")]
+async fn delete_category(
+    request: HttpRequest,
+    category_id: web::Path<CategoryId>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    let category_id = category_id.into_inner();
+    let _deleting = resources.taxonomy.deleting();
+    let businesses: Vec<String> = resources
+        .mock_database
+        .all()
+        .into_iter()
+        .filter(|business| business.business.category_ids.contains(&category_id))
+        .map(|business| business.business.name)
+        .collect();
+    if !businesses.is_empty() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": format!("{} businesses are still in category {category_id}", businesses.len()),
+            "businesses": businesses
+        })));
+    }
+    match resources.taxonomy.delete(category_id) {
+        Ok(category) => Ok(HttpResponse::Ok().json(category)),
+        Err(error) => Ok(taxonomy_error(error)),
+    }
+}
+
+fn taxonomy_error(error: TaxonomyError) -> HttpResponse {
+    let body = json!({ "error": error.to_string() });
+    match error {
+        TaxonomyError::NotFound(_) => HttpResponse::NotFound().json(body),
+        TaxonomyError::EmptyName => HttpResponse::BadRequest().json(body),
+        TaxonomyError::DuplicateName(_) | TaxonomyError::Cycle(_) | TaxonomyError::HasChildren(_) | TaxonomyError::DuplicateId(_) => {
+            HttpResponse::Conflict().json(body)
+        }
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+struct DuplicatesQuery {
+    /// How alike two businesses have to be, from 0 to 1, 0.6 by default.
+    min_score: Option<f64>,
+    /// At most this many pairs, 50 by default and at most 500.
+    limit: Option<usize>,
+}
+
+/// Pairs of businesses that are likely the same one added twice, most alike first. Admins only.
+#[utoipa::path(
+    tag = "moderation",
+    context_path = "/v1/duplicates",
+    params(DuplicatesQuery),
+    responses(
+        (status = 200, description = "The likely duplicates, with how alike their names, addresses and phone numbers are", body = [DuplicateCandidate]),
+        (status = 400, description = "`min_score` isn't between 0 and 1"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("")]
+async fn list_duplicates(
+    request: HttpRequest,
+    query: web::Query<DuplicatesQuery>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    let min_score = query.min_score.unwrap_or(0.6);
+    if !(0.0..=1.0).contains(&min_score) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the duplicates endpoint",
+            "error": "min_score must be between 0 and 1"
+        })));
+    }
+    let mut candidates = duplicates::find(&resources.mock_database.all(), min_score);
+    let total = candidates.len();
+    candidates.truncate(query.limit.unwrap_or(50).clamp(1, 500));
+    Ok(HttpResponse::Ok().json(json!({
+        "duplicates": candidates,
+        "total": total
+    })))
+}
+
+/// Merges a duplicate into the business to keep: its reviews and photos are moved over, it's removed, and its name
+/// redirects to the surviving business from then on. Admins only.
+#[utoipa::path(
+    tag = "moderation",
+    context_path = "/v1/duplicates",
+    request_body = MergeRequest,
+    responses(
+        (status = 200, description = "The surviving business, in `business`, and what was moved over, in `merged`"),
+        (status = 400, description = "`keep` and `merge` are the same business"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "One of the businesses doesn't exist"),
+    ),
+    security(("admin_token" = []))
+)]
+#[post("/merge")]
+async fn merge_duplicates(
+    request: HttpRequest,
+    merge: web::Json<MergeRequest>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    let MergeRequest { keep, merge } = merge.into_inner();
+    if keep == merge {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the merge endpoint",
+            "error": "A business can't be merged into itself"
+        })));
+    }
+    // The survivor picks up the duplicate's categories.
+    let _linking = resources.taxonomy.linking();
+    let merged = resources.mock_database.entry_pair(&keep, &merge, |survivor, duplicate| {
+        let missing = match (survivor.is_some(), duplicate.is_some()) {
+            (true, true) => None,
+            (false, _) => Some(&keep),
+            (true, false) => Some(&merge),
+        };
+        if let Some(missing) = missing {
+            return Err(format!("There's no business called {missing}"));
+        }
+        let survivor = survivor.as_mut().expect("checked above");
+        let summary = survivor.absorb(duplicate.take().expect("checked above"));
+        Ok((summary, survivor.clone()))
+    });
+    match merged {
+        Err(error) => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the merge endpoint",
+            "error": error
+        }))),
+        Ok((summary, business)) => {
+            resources.redirects.add(&merge, &keep);
+            // The duplicate's codes went to its own contact details and its owner doesn't own the survivor, so only its
+            // history comes along.
+            resources.claims.cancel(&merge);
+            resources.claims.revoke_owner_token(&merge);
+            resources.claims.rename(&merge, &keep);
+            Ok(HttpResponse::Ok().json(json!({
+                "merged": summary,
+                "business": business
+            })))
+        }
+    }
+}
+
+/// Removes a business, along with its reviews and photos.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "The business that was removed", body = BusinessResponse),
+        (status = 403, description = "The business has an owner, and this isn't them or an admin"),
+        (status = 404, description = "Business not found"),
+        (status = 412, description = "If-Match doesn't match the current version"),
+        (status = 428, description = "If-Match is required but wasn't sent"),
+    )
+)]
 #[delete("/business/{business_name}")]
 async fn delete_business(
+    request: HttpRequest,
     resources: web::Data<AppState>,
     business_name: web::Path<String>,
 ) -> std::io::Result<impl Responder> {
     let database = resources.mock_database.clone();
-    let removed_business = database.write().await.remove(&business_name.to_string());
+    let removed_business = database.entry(&business_name, |slot| {
+        if let Some(business) = slot {
+            if let Some(response) = check_if_match(&request, Some(business.version), resources.require_if_match) {
+                return Err(response);
+            }
+            // Otherwise anyone could delete an owned business, add it again with their own contact details and claim it.
+            if business.owner.is_some() {
+                if let Some(response) = owner_refused(&request, &resources, business) {
+                    return Err(response);
+                }
+            }
+        }
+        Ok(slot.take())
+    });
     match removed_business {
-        Some(business) => Ok(HttpResponse::Ok().json(business)),
-        None => Ok(HttpResponse::NotFound().json(json!({
+        Err(response) => Ok(response),
+        Ok(Some(business)) => {
+            // Codes already sent were for this business, not for whatever gets the name next.
+            let cancelled = resources.claims.cancel(&business_name);
+            resources.claims.revoke_owner_token(&business_name);
+            resources.claims.record(&business_name, ClaimEvent::BusinessDeleted { owner: business.owner.clone(), cancelled });
+            resources.redirects.forget(&business_name);
+            Ok(HttpResponse::Ok().json(business))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
             "notes": "Reached the deletion endpoint",
             "error": "Business not found"
         }))),
     }
 }
 
+/// Fetches a business with its reviews and photos.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    responses(
+        (status = 200, description = "The business", body = BusinessResponse),
+        (status = 301, description = "The business was merged into another one, which `Location` points at"),
+        (status = 304, description = "If-None-Match matches the current version"),
+        (status = 404, description = "Business not found"),
+    )
+)]
 #[get("/business/{business_name}")]
 async fn find_business(
+    request: HttpRequest,
     business_name: web::Path<String>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let database = resources.mock_database.clone();
-    let searched_business = database
-        .read()
-        .await
-        .get(&business_name.to_string())
-        .cloned();
+    let searched_business = database.read(&business_name, |business| business.clone());
 
     match searched_business {
-        Some(business) => Ok(HttpResponse::Ok().json(business)),
-        None => Ok(HttpResponse::NotFound().json(json!({
-            "notes": "Reached the business info specification endpoint",
-            "error": "Business not found"
-        }))),
+        Some(business) if not_modified(&request, business.version) => Ok(not_modified_response(business.version)),
+        Some(business) => Ok(with_etag(HttpResponse::Ok().json(&business), business.version)),
+        None => match resources.redirects.resolve(&business_name) {
+            Some(survivor) => Ok(merged_into(&request, &survivor)),
+            None => Ok(HttpResponse::NotFound().json(json!({
+                "notes": "Reached the business info specification endpoint",
+                "error": "Business not found"
+            }))),
+        },
     }
 }
 
+/// A permanent redirect from a merged business to the one it was merged into, next to it under the same path.
+fn merged_into(request: &HttpRequest, survivor: &str) -> HttpResponse {
+    let base = request.path().rsplit_once('/').map_or("", |(base, _)| base);
+    let location = format!("{base}/{}", utf8_percent_encode(survivor, NON_ALPHANUMERIC));
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, location))
+        .json(json!({
+            "notes": "Reached the business info specification endpoint",
+            "merged_into": survivor
+        }))
+}
+
+/// Replaces a business, or creates it if there isn't one with that name. A different name in the body renames it.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    request_body = BusinessResponse,
+    responses(
+        (status = 200, description = "The business was replaced or created"),
+        (status = 403, description = "The contact details changed without the owner's token or an admin token"),
+        (status = 409, description = "The body renames the business to a name that's taken"),
+        (status = 412, description = "If-Match doesn't match the current version"),
+        (status = 422, description = "The business is invalid, e.g. its ZIP code is in another state"),
+        (status = 428, description = "If-Match is required but wasn't sent"),
+    )
+)]
 #[put("/business/{business_name}")]
 async fn update_business(
+    request: HttpRequest,
     business_name: web::Path<String>,
     business_data: web::Json<BusinessResponse>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
+    let mut business_data = business_data.into_inner();
+    let _linking = resources.taxonomy.linking();
+    resources.taxonomy.link(&mut business_data.business);
+    // Businesses are keyed by name, so a different name in the body moves the entry (as long as the new name is free).
+    let new_name = business_data.business.name.clone();
     let database = resources.mock_database.clone();
-    // Might be a good idea to make this read to check if it exists instead of potentially taking up a writing spot in the queue.
-    let updated_business = database
-        .write()
-        .await
-        .insert(business_name.to_string(), business_data.clone());
-    
+    let mut replace = |slot: &mut Option<BusinessResponse>| {
+        let previous = slot.as_ref().map(|business| &business.business);
+        if let Some(response) = invalid_business(&business_data.business, previous, &resources.taxonomy) {
+            return Err(response);
+        }
+        let current_version = slot.as_ref().map(|business| business.version);
+        if let Some(response) = check_if_match(&request, current_version, resources.require_if_match) {
+            return Err(response);
+        }
+        if let Some(response) = slot.as_ref().and_then(|current| contact_change_refused(&request, &resources, current, &business_data.business)) {
+            return Err(response);
+        }
+        business_data.version = current_version.map_or(1, |version| version + 1);
+        business_data.reassign_photo_ids(slot.as_ref());
+        business_data.carry_nested_versions(slot.as_ref());
+        // Ownership only changes through a claim, so it carries over from the business being replaced, and so do the
+        // owner's replies to its reviews.
+        business_data.owner = slot.as_ref().and_then(|business| business.owner.clone());
+        business_data.keep_owner_responses(slot.as_ref());
+        Ok(slot.replace(business_data.clone()))
+    };
+    let updated_business = if new_name == *business_name {
+        database.entry(&business_name, replace)
+    } else {
+        database.entry_pair(&business_name, &new_name, |from, to| {
+            if to.is_some() {
+                return Err(HttpResponse::Conflict().json(json!({
+                    "error": "A business with that name already exists",
+                    "name": new_name
+                })));
+            }
+            let previous = replace(from)?;
+            *to = from.take();
+            Ok(previous)
+        })
+    };
+    if let Ok(previous) = &updated_business {
+        if previous.is_some() && new_name != *business_name {
+            resources.claims.rename(&business_name, &new_name);
+            resources.redirects.rename(&business_name, &new_name);
+        }
+        // Created or renamed, there's a business by the new name now, so the name no longer redirects.
+        resources.redirects.reuse(&new_name);
+    }
+
     match updated_business {
-        Some(business) => Ok(HttpResponse::Ok().json(json!({
+        Err(response) => Ok(response),
+        Ok(Some(business)) => Ok(with_etag(HttpResponse::Ok().json(json!({
             "success": true,
             "body": "Replaced an old business!",
             "previous_business": business,
             "new_business": business_data
-        }))),
-        None => Ok(HttpResponse::Ok().json(json!({ // Returns None if the key didn't exist. It still added a new one.
+        })), business_data.version)),
+        Ok(None) => Ok(with_etag(HttpResponse::Ok().json(json!({ // Returns None if the key didn't exist. It still added a new one.
             "success": true,
             "body": "Created a new business!",
             "created_business": business_data
-        })))
+        })), business_data.version))
+    }
+}
+
+/// Contact details are where claim codes go, so only the owner or an admin can change them. Otherwise anyone could
+/// point an unowned business' contact details at themselves and claim it. Returns the response to send instead, if the
+/// change isn't allowed.
+fn contact_change_refused(request: &HttpRequest, resources: &AppState, current: &BusinessResponse, new: &Business) -> Option<HttpResponse> {
+    if current.business.email == new.email && current.business.phone_num == new.phone_num {
+        return None;
     }
+    owner_refused(request, resources, current)
 }
+
+/// Turns away anyone but the owner of `business`, with their owner token, or an admin. Without an owner only an admin
+/// gets through. Returns the response to send instead, if the request isn't from either.
+fn owner_refused(request: &HttpRequest, resources: &AppState, business: &BusinessResponse) -> Option<HttpResponse> {
+    let owner_token = business.owner.as_deref().and_then(|owner| resources.claims.owner_token(&business.business.name, owner));
+    admin::authorize_owner(request, resources.admin_token.as_deref(), owner_token.as_deref())
+}
+
+/// Partially updates the business fields, leaving reviews and photos alone.
+/// Accepts either a JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902), picked by the content type.
+#[utoipa::path(
+    tag = "businesses",
+    context_path = "/v1",
+    request_body(
+        description = "A JSON Merge Patch (`application/merge-patch+json`) or JSON Patch (`application/json-patch+json`) against the business fields",
+        content = Object,
+        content_type = "application/merge-patch+json",
+    ),
+    responses(
+        (status = 200, description = "The patched business", body = BusinessResponse),
+        (status = 400, description = "Malformed patch document, or it changes `is_open_now`"),
+        (status = 403, description = "The contact details changed without the owner's token or an admin token"),
+        (status = 404, description = "Business not found"),
+        (status = 409, description = "A JSON Patch test failed, or the new name is taken"),
+        (status = 412, description = "If-Match doesn't match the current version"),
+        (status = 415, description = "Unsupported patch format"),
+        (status = 422, description = "The patch doesn't apply or produces an invalid business"),
+    )
+)]
+#[patch("/business/{business_name}")]
+async fn patch_business(
+    business_name: web::Path<String>,
+    request: HttpRequest,
+    body: web::Bytes,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let business_name = business_name.into_inner();
+    let patch = match request.content_type() {
+        "application/merge-patch+json" => serde_json::from_slice(&body).map(BusinessPatch::Merge),
+        "application/json-patch+json" => serde_json::from_slice(&body).map(BusinessPatch::Json),
+        _ => {
+            return Ok(HttpResponse::UnsupportedMediaType().json(json!({
+                "error": "Unsupported patch format",
+                "supported": ["application/merge-patch+json", "application/json-patch+json"]
+            })))
+        }
+    };
+    let patch = match patch {
+        Ok(patch) => patch,
+        Err(error) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": "Malformed patch document",
+                "details": error.to_string()
+            })))
+        }
+    };
+
+    // Businesses are keyed by name, so a rename moves the entry (as long as the new name is free). The patch is applied
+    // to the business as it is once it's locked, and since that decides the new name, the name is worked out from a
+    // first look. If the business changes in between in a way that gives it another name, it's worked out again.
+    let database = resources.mock_database.clone();
+    let _linking = resources.taxonomy.linking();
+    loop {
+        let new_name = match database.read(&business_name, |business| business.business.apply_patch(&patch).map(|patched| patched.name)) {
+            Some(new_name) => new_name.unwrap_or_else(|_| business_name.clone()),
+            None => {
+                return Ok(HttpResponse::NotFound().json(json!({
+                    "notes": "Reached the business patch endpoint",
+                    "error": "Business not found"
+                })))
+            }
+        };
+        // `Err(None)` means the patch renames the business to something else now, so the wrong entries are locked.
+        let apply = |business: &mut BusinessResponse| {
+            if let Some(response) = check_if_match(&request, Some(business.version), resources.require_if_match) {
+                return Err(Some(response));
+            }
+            let mut patched_business = match business.business.apply_patch(&patch) {
+                Ok(patched_business) => patched_business,
+                Err(BusinessPatchError::Apply(error)) if matches!(error.kind, json_patch::PatchErrorKind::TestFailed) => {
+                    return Err(Some(HttpResponse::Conflict().json(json!({ "error": error.to_string() }))))
+                }
+                Err(error @ BusinessPatchError::ReadOnlyField(_)) => {
+                    return Err(Some(HttpResponse::BadRequest().json(json!({ "error": error.to_string() }))))
+                }
+                Err(error) => return Err(Some(HttpResponse::UnprocessableEntity().json(json!({ "error": error.to_string() })))),
+            };
+            if patched_business.name != new_name {
+                return Err(None);
+            }
+            resources.taxonomy.link(&mut patched_business);
+            if let Some(response) = invalid_business(&patched_business, Some(&business.business), &resources.taxonomy) {
+                return Err(Some(response));
+            }
+            if let Some(response) = contact_change_refused(&request, &resources, business, &patched_business) {
+                return Err(Some(response));
+            }
+            business.business = patched_business;
+            business.touch();
+            Ok(business.clone())
+        };
+
+        let updated = if new_name == business_name {
+            database.update(&business_name, apply)
+        } else {
+            database.entry_pair(&business_name, &new_name, |from, to| {
+                let business = from.as_mut()?;
+                if to.is_some() {
+                    return Some(Err(Some(HttpResponse::Conflict().json(json!({
+                        "error": "A business with that name already exists",
+                        "name": new_name
+                    })))));
+                }
+                let updated = apply(business);
+                if updated.is_ok() {
+                    *to = from.take();
+                }
+                Some(updated)
+            })
+        };
+
+        return match updated {
+            Some(Ok(updated)) => {
+                if new_name != business_name {
+                    resources.claims.rename(&business_name, &new_name);
+                    resources.redirects.rename(&business_name, &new_name);
+                    resources.redirects.reuse(&new_name);
+                }
+                Ok(with_etag(HttpResponse::Ok().json(&updated), updated.version))
+            }
+            Some(Err(Some(response))) => Ok(response),
+            Some(Err(None)) => continue,
+            None => Ok(HttpResponse::NotFound().json(json!({
+                "notes": "Reached the business patch endpoint",
+                "error": "Business not found"
+            }))),
+        };
+    }
+}
+
+// --- Claims below ---
+
+/// Starts claiming a business: a verification code is sent to the email address or phone number the business has on
+/// record, to be sent back to the confirmation endpoint. Asking again sends a new code.
+#[utoipa::path(
+    tag = "claims",
+    context_path = "/v1/claims",
+    request_body = ClaimRequest,
+    responses(
+        (status = 202, description = "The code was sent, `sent_to` says where", body = ClaimSent),
+        (status = 404, description = "Business not found"),
+        (status = 409, description = "The business already has an owner"),
+        (status = 422, description = "The business has no contact details on that channel"),
+        (status = 429, description = "Rate limited, or the user sent too many wrong codes for this business lately, see Retry-After"),
+        (status = 502, description = "The code couldn't be delivered"),
+    )
+)]
+#[post("/{user_name}/{business_name}", wrap = "RateLimit::route(\"request_claim\")")]
+async fn request_claim(
+    params: web::Path<(String, String)>,
+    claim: web::Json<ClaimRequest>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let (user_name, business_name) = params.into_inner();
+    let business = resources.mock_database.read(&business_name, |business| (business.business.clone(), business.owner.clone()));
+    match business {
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the claim endpoint",
+            "error": "Business not found"
+        }))),
+        Some((_, Some(owner))) => Ok(HttpResponse::Conflict().json(json!({
+            "notes": "Reached the claim endpoint",
+            "error": format!("{business_name} is already owned by {owner}")
+        }))),
+        Some((business, None)) => match resources.claims.request(&business, &user_name, claim.channel) {
+            Ok(sent) => Ok(HttpResponse::Accepted().json(sent)),
+            Err(error) => Ok(claim_error(error)),
+        },
+    }
+}
+
+/// Finishes claiming a business with the code that was sent, making the user its owner.
+#[utoipa::path(
+    tag = "claims",
+    context_path = "/v1/claims",
+    request_body = ClaimConfirmation,
+    responses(
+        (status = 200, description = "The user owns the business now, and `owner_token` is how they act as its owner", body = Ownership),
+        (status = 404, description = "Business not found, or there's no claim by this user waiting for a code"),
+        (status = 409, description = "Someone else became the owner in the meantime"),
+        (status = 410, description = "The code expired, too many wrong codes were sent, or the business' contact details changed since, the claim has to be started again"),
+        (status = 422, description = "The code is wrong"),
+    )
+)]
+#[post("/{user_name}/{business_name}/confirm")]
+async fn confirm_claim(
+    params: web::Path<(String, String)>,
+    confirmation: web::Json<ClaimConfirmation>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let (user_name, business_name) = params.into_inner();
+    let destination = match resources.claims.confirm(&business_name, &user_name, &confirmation.code) {
+        Ok(destination) => destination,
+        Err(error) => return Ok(claim_error(error)),
+    };
+    let claimed = resources.mock_database.entry(&business_name, |slot| match slot {
+        None => Err(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the claim confirmation endpoint",
+            "error": "Business not found"
+        }))),
+        Some(business) => match &business.owner {
+            Some(owner) => Err(HttpResponse::Conflict().json(json!({
+                "notes": "Reached the claim confirmation endpoint",
+                "error": format!("{business_name} is already owned by {owner}")
+            }))),
+            // The code only shows the user can read what the business had on record when it was sent.
+            None if claims::contact(&business.business, destination.channel).as_ref() != Some(&destination.to) => {
+                let error = ClaimError::ContactChanged(destination.channel);
+                resources.claims.record(&business_name, ClaimEvent::Rejected { user: user_name.clone(), reason: error.to_string() });
+                Err(claim_error(error))
+            }
+            None => {
+                business.owner = Some(user_name.clone());
+                business.touch();
+                Ok(business.clone())
+            }
+        },
+    });
+    match claimed {
+        Err(response) => Ok(response),
+        Ok(business) => {
+            resources.claims.cancel(&business_name);
+            let owner_token = resources.claims.issue_owner_token(&business_name, &user_name);
+            resources.claims.record(&business_name, ClaimEvent::Verified { user: user_name });
+            let version = business.version;
+            Ok(with_etag(HttpResponse::Ok().json(Ownership { business, owner_token }), version))
+        }
+    }
+}
+
+/// Makes a user the owner of a business without a claim, replacing any owner it had. Admins only.
+#[utoipa::path(
+    tag = "claims",
+    context_path = "/v1/claims",
+    request_body = OwnerAssignment,
+    responses(
+        (status = 200, description = "The user owns the business now, `owner_token` is for handing over to them", body = Ownership),
+        (status = 400, description = "The user name is empty"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "Business not found"),
+    ),
+    security(("admin_token" = []))
+)]
+#[put("/{business_name}/owner")]
+async fn assign_owner(
+    request: HttpRequest,
+    business_name: web::Path<String>,
+    assignment: web::Json<OwnerAssignment>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    let user = assignment.into_inner().user.trim().to_string();
+    if user.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "notes": "Reached the owner assignment endpoint",
+            "error": "The user name can't be empty"
+        })));
+    }
+    let assigned = resources.mock_database.update(&business_name, |business| {
+        let previous = business.owner.replace(user.clone());
+        business.touch();
+        (previous, business.clone())
+    });
+    match assigned {
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner assignment endpoint",
+            "error": "Business not found"
+        }))),
+        Some((previous, business)) => {
+            resources.claims.cancel(&business_name);
+            let owner_token = resources.claims.issue_owner_token(&business_name, &user);
+            resources.claims.record(&business_name, ClaimEvent::OwnerAssigned { user, previous });
+            let version = business.version;
+            Ok(with_etag(HttpResponse::Ok().json(Ownership { business, owner_token }), version))
+        }
+    }
+}
+
+/// Takes ownership of a business away, so it can be claimed again. Admins only.
+#[utoipa::path(
+    tag = "claims",
+    context_path = "/v1/claims",
+    responses(
+        (status = 200, description = "The business no longer has an owner", body = BusinessResponse),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+        (status = 404, description = "Business not found, or it has no owner"),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/{business_name}/owner")]
+async fn remove_owner(
+    request: HttpRequest,
+    business_name: web::Path<String>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    let removed = resources.mock_database.update(&business_name, |business| {
+        let previous = business.owner.take();
+        if previous.is_some() {
+            business.touch();
+        }
+        (previous, business.clone())
+    });
+    match removed {
+        Some((Some(previous), business)) => {
+            resources.claims.revoke_owner_token(&business_name);
+            resources.claims.record(&business_name, ClaimEvent::OwnerRemoved { previous });
+            Ok(with_etag(HttpResponse::Ok().json(&business), business.version))
+        }
+        Some((None, _)) => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner removal endpoint",
+            "error": "The business has no owner"
+        }))),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner removal endpoint",
+            "error": "Business not found"
+        }))),
+    }
+}
+
+/// Everything that happened to claims on a business, oldest first. Admins only.
+#[utoipa::path(
+    tag = "claims",
+    context_path = "/v1/claims",
+    responses(
+        (status = 200, description = "The audit trail, empty if nothing happened yet", body = [AuditEntry]),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 403, description = "Admin endpoints are disabled"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/{business_name}/audit")]
+async fn claim_audit(
+    request: HttpRequest,
+    business_name: web::Path<String>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    if let Some(response) = admin::authorize(&request, resources.admin_token.as_deref()) {
+        return Ok(response);
+    }
+    Ok(HttpResponse::Ok().json(resources.claims.history(&business_name)))
+}
+
+fn claim_error(error: ClaimError) -> HttpResponse {
+    let body = json!({ "error": error.to_string() });
+    match error {
+        ClaimError::NoContact(_) | ClaimError::WrongCode { .. } => HttpResponse::UnprocessableEntity().json(body),
+        ClaimError::NotRequested => HttpResponse::NotFound().json(body),
+        ClaimError::Expired | ClaimError::TooManyAttempts | ClaimError::ContactChanged(_) => HttpResponse::Gone().json(body),
+        ClaimError::LockedOut { until } => {
+            let retry_after_secs = (until - Utc::now()).num_seconds().max(1);
+            HttpResponse::TooManyRequests().insert_header((header::RETRY_AFTER, retry_after_secs)).json(body)
+        }
+        ClaimError::Delivery(_) => HttpResponse::BadGateway().json(body),
+    }
+}
+
 // --- Reviews below ---
 
 /// Add a new review to a business. If the content is the exact same, make two seperate reviews.
-#[post("/{reviewer_name}/{business_name}")]
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/review",
+    request_body = Review,
+    responses(
+        (status = 200, description = "The review was added"),
+        (status = 404, description = "Business not found"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    )
+)]
+#[post("/{reviewer_name}/{business_name}", wrap = "RateLimit::route(\"add_review\")")]
 async fn add_review(
     params: web::Path<(String, String)>,
     review_data: web::Json<Review>,
@@ -176,82 +1689,131 @@ async fn add_review(
         reviewer_name = "Anonymous".to_string();
     }
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
+    // If the business exists, then retrieve the review list (if it exists).
+    // If it does then add the sent review into the list, if it doesn't, then create the list.
+    // I could probably make it so that the review list always exists, either way works.
+    let response = database.update(&business_name, |business| business.add_business_review(reviewer_name, review_data.into_inner()));
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.add_business_review(reviewer_name, review_data.clone()));
+    if let Some(response) = response {
+        Ok(response)
     } else {
-        return Ok(HttpResponse::NotFound().json(json!({
+        Ok(HttpResponse::NotFound().json(json!({
             "notes": "Reached the review addition endpoint",
             "error": "Business not found"
-        })));
+        })))
     }
 }
 
-#[delete("/{reviewer_name}/{business_name}")]
+/// Deletes a user's review of a business.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/review",
+    responses(
+        (status = 200, description = "The review was deleted"),
+        (status = 412, description = "If-Match doesn't match the review's current version"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    )
+)]
+#[delete("/{reviewer_name}/{business_name}", wrap = "RateLimit::route(\"delete_review\")")]
 async fn delete_review(
+    request: HttpRequest,
     params: web::Path<(String, String)>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let (reviewer_name, business_name) = params.into_inner();
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
+    let response = database.update(&business_name, |business| {
+        if let Some(response) = check_if_match(&request, business.review_version(&reviewer_name), resources.require_if_match) {
+            return response;
+        }
+        business.delete_business_review(reviewer_name.clone())
+    });
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.delete_business_review(reviewer_name.clone()));
+    if let Some(response) = response {
+        Ok(response)
     }else {
-        return Ok(HttpResponse::Ok().body(format!("Deleted {reviewer_name}'s review from {business_name}")));
+        Ok(HttpResponse::Ok().body(format!("Deleted {reviewer_name}'s review from {business_name}")))
     }
 }
 
-#[put("/{reviewer_name}/{business_name}")]
+/// Replaces a user's review of a business.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/review",
+    request_body = Review,
+    responses(
+        (status = 200, description = "The review was updated"),
+        (status = 412, description = "If-Match doesn't match the review's current version"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    )
+)]
+#[put("/{reviewer_name}/{business_name}", wrap = "RateLimit::route(\"update_review\")")]
 async fn update_review(
+    request: HttpRequest,
     params: web::Path<(String, String)>,
     review_data: web::Json<Review>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let (reviewer_name, business_name) = params.into_inner();
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
-    // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
+    let response = database.update(&business_name, |business| {
         // If the business exists, then retrieve the review list (if it exists).
         // If it does then update the sent review into the list, if it doesn't, then exit.
-        return Ok(business.update_business_review(reviewer_name.clone(), review_data.into_inner()));
+        if let Some(response) = check_if_match(&request, business.review_version(&reviewer_name), resources.require_if_match) {
+            return response;
+        }
+        let response = business.update_business_review(reviewer_name.clone(), review_data.into_inner());
+        match business.review_version(&reviewer_name) {
+            Some(version) => with_etag(response, version),
+            None => response,
+        }
+    });
+    // Check to see if the business exists, if not then return an error.
+    if let Some(response) = response {
+        Ok(response)
     }else {
-        return Ok(HttpResponse::Ok().body(format!("Updated {reviewer_name}'s review from {business_name}")));
+        Ok(HttpResponse::Ok().body(format!("Updated {reviewer_name}'s review from {business_name}")))
     }
 }
 
+/// Lists the reviews of a business.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/review",
+    responses(
+        (status = 200, description = "The business' reviews"),
+        (status = 304, description = "If-None-Match matches the business' current version"),
+    )
+)]
 #[get("/{business_name}")]
 async fn show_business_reviews(
+    request: HttpRequest,
     params: web::Path<String>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let business_name = params.into_inner();
     let database = resources.mock_database.clone();
-    let database_read = database.read().await;
-    let business_data = database_read.get(&business_name);
+    let response = database.read(&business_name, |business| {
+        // The review list changes whenever the business version does, so it shares the business' ETag.
+        if not_modified(&request, business.version) {
+            return not_modified_response(business.version);
+        }
+        with_etag(business.get_business_reviews(), business.version)
+    });
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.get_business_reviews());
+    if let Some(response) = response {
+        Ok(response)
     }else {
-        return Ok(HttpResponse::Ok().body(format!("Showing reviews from {business_name}")));
+        Ok(HttpResponse::Ok().body(format!("Showing reviews from {business_name}")))
     }
 }
 
+/// Lists the reviews of a business, from a user's point of view.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/review",
+    responses((status = 200, description = "The business' reviews"))
+)]
 #[get("/{reviewer_name}/{business_name}")]
 async fn business_user_reviews(
     params: web::Path<(String, String)>,
@@ -259,78 +1821,440 @@ async fn business_user_reviews(
 ) -> std::io::Result<impl Responder> {
     let (reviewer_name, business_name) = params.into_inner();
     let database = resources.mock_database.clone();
-    let database_read = database.read().await;
-    let business_data = database_read.get(&business_name);
+    let response = database.read(&business_name, |business| business.get_business_reviews());
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.get_business_reviews());
+    if let Some(response) = response {
+        Ok(response)
     }else {
-        return Ok(HttpResponse::Ok().body(format!("Showing {reviewer_name}'s reviews from {business_name}")));
+        Ok(HttpResponse::Ok().body(format!("Showing {reviewer_name}'s reviews from {business_name}")))
     }
 }
+
+/// Replies to a review as the owner of the business. A review gets one reply, which can be edited or deleted after.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/responses",
+    request_body = OwnerResponseText,
+    responses(
+        (status = 201, description = "The reply was added, it's shown with the review from now on", body = OwnerResponse),
+        (status = 403, description = "Neither the owner's token nor an admin token was sent"),
+        (status = 404, description = "Business or review not found"),
+        (status = 409, description = "The review already has a reply, or there are several reviews under the reviewer's name"),
+        (status = 422, description = "The reply is empty or too long"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    ),
+    security(("owner_token" = []), ("admin_token" = []))
+)]
+#[post("/{business_name}/{reviewer_name}", wrap = "RateLimit::route(\"add_owner_response\")")]
+async fn add_owner_response(
+    request: HttpRequest,
+    params: web::Path<(String, String)>,
+    response_data: web::Json<OwnerResponseText>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let (business_name, reviewer_name) = params.into_inner();
+    let response = resources.mock_database.update(&business_name, |business| {
+        if let Some(response) = owner_refused(&request, &resources, business) {
+            return response;
+        }
+        let author = reply_author(business);
+        match business.add_owner_response(&author, &reviewer_name, &response_data.text) {
+            Ok(added) => with_etag(HttpResponse::Created().json(json!({
+                "message": "Response added.",
+                "reviewer": reviewer_name,
+                "owner_response": added
+            })), added.version),
+            Err(error) => owner_response_error(error),
+        }
+    });
+    match response {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner response endpoint",
+            "error": "Business not found"
+        }))),
+    }
+}
+
+/// Changes the owner's reply to a review.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/responses",
+    request_body = OwnerResponseText,
+    responses(
+        (status = 200, description = "The reply was changed", body = OwnerResponse),
+        (status = 403, description = "Neither the owner's token nor an admin token was sent"),
+        (status = 404, description = "Business, review or reply not found"),
+        (status = 409, description = "There are several reviews under the reviewer's name"),
+        (status = 412, description = "If-Match doesn't match the reply's current version"),
+        (status = 422, description = "The reply is empty or too long"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    ),
+    security(("owner_token" = []), ("admin_token" = []))
+)]
+#[put("/{business_name}/{reviewer_name}", wrap = "RateLimit::route(\"update_owner_response\")")]
+async fn update_owner_response(
+    request: HttpRequest,
+    params: web::Path<(String, String)>,
+    response_data: web::Json<OwnerResponseText>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let (business_name, reviewer_name) = params.into_inner();
+    let response = resources.mock_database.update(&business_name, |business| {
+        if let Some(response) = owner_refused(&request, &resources, business) {
+            return response;
+        }
+        let current_version = match business.owner_response_version(&reviewer_name) {
+            Ok(version) => version,
+            Err(error) => return owner_response_error(error),
+        };
+        if let Some(response) = check_if_match(&request, current_version, resources.require_if_match) {
+            return response;
+        }
+        let author = reply_author(business);
+        match business.update_owner_response(&author, &reviewer_name, &response_data.text) {
+            Ok(updated) => with_etag(HttpResponse::Ok().json(json!({
+                "message": "Response updated.",
+                "reviewer": reviewer_name,
+                "owner_response": updated
+            })), updated.version),
+            Err(error) => owner_response_error(error),
+        }
+    });
+    match response {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner response endpoint",
+            "error": "Business not found"
+        }))),
+    }
+}
+
+/// Deletes the owner's reply to a review.
+#[utoipa::path(
+    tag = "reviews",
+    context_path = "/v1/responses",
+    responses(
+        (status = 200, description = "The reply was deleted"),
+        (status = 403, description = "Neither the owner's token nor an admin token was sent"),
+        (status = 404, description = "Business, review or reply not found"),
+        (status = 409, description = "There are several reviews under the reviewer's name"),
+        (status = 412, description = "If-Match doesn't match the reply's current version"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    ),
+    security(("owner_token" = []), ("admin_token" = []))
+)]
+#[delete("/{business_name}/{reviewer_name}", wrap = "RateLimit::route(\"delete_owner_response\")")]
+async fn delete_owner_response(
+    request: HttpRequest,
+    params: web::Path<(String, String)>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let (business_name, reviewer_name) = params.into_inner();
+    let response = resources.mock_database.update(&business_name, |business| {
+        if let Some(response) = owner_refused(&request, &resources, business) {
+            return response;
+        }
+        let current_version = match business.owner_response_version(&reviewer_name) {
+            Ok(version) => version,
+            Err(error) => return owner_response_error(error),
+        };
+        if let Some(response) = check_if_match(&request, current_version, resources.require_if_match) {
+            return response;
+        }
+        match business.delete_owner_response(&reviewer_name) {
+            Ok(deleted) => HttpResponse::Ok().json(json!({
+                "message": "Response deleted.",
+                "reviewer": reviewer_name,
+                "deleted_response": deleted
+            })),
+            Err(error) => owner_response_error(error),
+        }
+    });
+    match response {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the owner response endpoint",
+            "error": "Business not found"
+        }))),
+    }
+}
+
+/// Who a reply is signed by: the owner, even when an admin steps in for them.
+fn reply_author(business: &BusinessResponse) -> String {
+    business.owner.clone().unwrap_or_else(|| "admin".into())
+}
+
+fn owner_response_error(error: OwnerResponseError) -> HttpResponse {
+    let body = json!({ "error": error.to_string() });
+    match error {
+        OwnerResponseError::NoReview(_) | OwnerResponseError::NoResponse => HttpResponse::NotFound().json(body),
+        OwnerResponseError::AlreadyResponded | OwnerResponseError::Ambiguous(_) => HttpResponse::Conflict().json(body),
+        OwnerResponseError::Empty | OwnerResponseError::TooLong => HttpResponse::UnprocessableEntity().json(body),
+    }
+}
+
 // --- Photos API below ---
 
-#[post("/photos/{user_name}/{business_name}")]
+/// Uploads a photo of a business. The server hands out the photo ID.
+#[utoipa::path(
+    tag = "photos",
+    context_path = "/v1/photos",
+    request_body = NewPhoto,
+    responses(
+        (status = 201, description = "The photo was added, with its ID in `added_photo`"),
+        (status = 404, description = "Business not found"),
+        (status = 429, description = "Rate limited, see Retry-After"),
+    )
+)]
+#[post("/{user_name}/{business_name}", wrap = "RateLimit::route(\"add_photo\")")]
 async fn add_photo(
     params: web::Path<(String, String)>,
-    photo_data: web::Json<Photo>,
+    photo_data: web::Json<NewPhoto>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let (user_name, business_name) = params.into_inner();
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
+    // The photo gets its ID from the business and goes to the end of the display order.
+    let response = database.update(&business_name, |business| {
+        let response = business.add_business_photo(user_name, photo_data.into_inner());
+        // The new photo is the one that took the last ID.
+        match business.photo_version(business.next_photo_id - 1) {
+            Some(version) => with_etag(response, version),
+            None => response,
+        }
+    });
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.add_business_photo(user_name.clone(), photo_data.clone()));
-    }else {
-        return Ok(HttpResponse::Ok().body(format!("Added a photo to {business_name}")));
+    if let Some(response) = response {
+        Ok(response)
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the photo addition endpoint",
+            "error": "Business not found"
+        })))
+    }
+}
+
+/// Once a business has an owner, only they or an admin decide how its photos are laid out. Returns the response to send
+/// instead, if the change isn't allowed.
+fn photo_layout_refused(request: &HttpRequest, resources: &AppState, business: &BusinessResponse) -> Option<HttpResponse> {
+    business.owner.as_ref()?;
+    owner_refused(request, resources, business)
+}
+
+/// Sets the display order of a business' photos.
+#[utoipa::path(
+    tag = "photos",
+    context_path = "/v1/photos",
+    request_body = PhotoOrder,
+    responses(
+        (status = 200, description = "The photos in their new order"),
+        (status = 400, description = "The order isn't every photo ID exactly once"),
+        (status = 403, description = "The business has an owner, and this isn't them or an admin"),
+        (status = 404, description = "Business not found"),
+        (status = 412, description = "If-Match doesn't match the business' current version"),
+    ),
+    security(("owner_token" = []), ("admin_token" = []))
+)]
+#[put("/{business_name}/order", wrap = "RateLimit::route(\"reorder_photos\")")]
+async fn reorder_photos(
+    request: HttpRequest,
+    business_name: web::Path<String>,
+    order: web::Json<PhotoOrder>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let database = resources.mock_database.clone();
+    let response = database.update(&business_name, |business| {
+        if let Some(response) = photo_layout_refused(&request, &resources, business) {
+            return response;
+        }
+        if let Some(response) = check_if_match(&request, Some(business.version), resources.require_if_match) {
+            return response;
+        }
+        let response = business.reorder_business_photos(order.into_inner());
+        with_etag(response, business.version)
+    });
+    if let Some(response) = response {
+        Ok(response)
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the photo reordering endpoint",
+            "error": "Business not found"
+        })))
+    }
+}
+
+/// Picks (or clears) the cover photo of a business.
+#[utoipa::path(
+    tag = "photos",
+    context_path = "/v1/photos",
+    request_body = CoverPhoto,
+    responses(
+        (status = 200, description = "The cover photo was set"),
+        (status = 403, description = "The business has an owner, and this isn't them or an admin"),
+        (status = 404, description = "Business or photo not found"),
+        (status = 412, description = "If-Match doesn't match the business' current version"),
+    ),
+    security(("owner_token" = []), ("admin_token" = []))
+)]
+#[put("/{business_name}/cover", wrap = "RateLimit::route(\"set_cover_photo\")")]
+async fn set_cover_photo(
+    request: HttpRequest,
+    business_name: web::Path<String>,
+    cover: web::Json<CoverPhoto>,
+    resources: web::Data<AppState>,
+) -> std::io::Result<impl Responder> {
+    let database = resources.mock_database.clone();
+    let response = database.update(&business_name, |business| {
+        if let Some(response) = photo_layout_refused(&request, &resources, business) {
+            return response;
+        }
+        if let Some(response) = check_if_match(&request, Some(business.version), resources.require_if_match) {
+            return response;
+        }
+        let response = business.set_cover_photo(cover.into_inner());
+        with_etag(response, business.version)
+    });
+    if let Some(response) = response {
+        Ok(response)
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the cover photo endpoint",
+            "error": "Business not found"
+        })))
     }
 }
 
-#[delete("/s{user_name}/{business_name}/{photo_id}")]
+/// Deletes one of the user's photos.
+#[utoipa::path(
+    tag = "photos",
+    context_path = "/v1/photos",
+    responses(
+        (status = 200, description = "The photo was deleted"),
+        (status = 403, description = "The photo belongs to someone else"),
+        (status = 404, description = "Business or photo not found"),
+        (status = 412, description = "If-Match doesn't match the photo's current version"),
+    )
+)]
+#[delete("/{user_name}/{business_name}/{photo_id}", wrap = "RateLimit::route(\"delete_photo\")")]
 async fn delete_photo(
+    request: HttpRequest,
     params: web::Path<(String, String, usize)>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
     let (user_name, business_name, photo_id) = params.into_inner();
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
+    let response = database.update(&business_name, |business| {
+        // Only the photo with this ID gets removed, and only if it belongs to the user.
+        if let Some(response) = check_if_match(&request, business.photo_version(photo_id), resources.require_if_match) {
+            return response;
+        }
+        business.delete_business_photo(user_name, photo_id)
+    });
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then add the sent review into the list, if it doesn't, then create the list.
-        // I could probably make it so that the review list always exists, either way works.
-        return Ok(business.delete_business_photo(user_name.clone(), photo_id.clone()));
-    }else {
-        return Ok(HttpResponse::Ok().body(format!("Deleted a photo from {business_name}")));
+    if let Some(response) = response {
+        Ok(response)
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the photo deletion endpoint",
+            "error": "Business not found"
+        })))
     }
 }
 
-#[put("/{user_name}/{business_name}")]
+/// Changes the caption of one of the user's photos.
+#[utoipa::path(
+    tag = "photos",
+    context_path = "/v1/photos",
+    request_body = PhotoCaptionUpdate,
+    responses(
+        (status = 200, description = "The caption was updated"),
+        (status = 403, description = "The photo belongs to someone else"),
+        (status = 404, description = "Business or photo not found"),
+        (status = 412, description = "If-Match doesn't match the photo's current version"),
+    )
+)]
+#[put("/{user_name}/{business_name}/{photo_id}", wrap = "RateLimit::route(\"update_photo\")")]
 async fn update_photo(
-    params: web::Path<(String, String)>,
-    photo_data: web::Json<Photo>,
+    request: HttpRequest,
+    params: web::Path<(String, String, usize)>,
+    caption_data: web::Json<PhotoCaptionUpdate>,
     resources: web::Data<AppState>,
 ) -> std::io::Result<impl Responder> {
-    let (user_name, business_name) = params.into_inner();
+    let (user_name, business_name, photo_id) = params.into_inner();
     let database = resources.mock_database.clone();
-    let mut database_read = database.write().await;
-    let business_data = database_read.get_mut(&business_name);
+    let response = database.update(&business_name, |business| {
+        // Photos can only have their caption changed, everything else stays as it was uploaded.
+        if let Some(response) = check_if_match(&request, business.photo_version(photo_id), resources.require_if_match) {
+            return response;
+        }
+        let response = business.update_business_photo(user_name, photo_id, caption_data.into_inner());
+        match business.photo_version(photo_id) {
+            Some(version) => with_etag(response, version),
+            None => response,
+        }
+    });
     // Check to see if the business exists, if not then return an error.
-    if let Some(business) = business_data {
-        // If the business exists, then retrieve the review list (if it exists).
-        // If it does then update the sent review into the list, if it doesn't, then exit.
-        return Ok(business.update_business_photo(user_name.clone(), photo_data.clone()));
-    }else {
-        return Ok(HttpResponse::Ok().body(format!("Updated a photo from {business_name}")));
+    if let Some(response) = response {
+        Ok(response)
+    } else {
+        Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the photo update endpoint",
+            "error": "Business not found"
+        })))
+    }
+}
+
+// --- Diagnostics below ---
+
+/// Liveness: if this answers at all, the process is up.
+#[utoipa::path(tag = "operations", responses((status = 200, description = "The server is up")))]
+#[get("/healthz")]
+async fn healthz() -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(json!({ "status": "ok" })))
+}
+
+/// Readiness: whether this instance should be sent traffic. Answers 503 with the failing checks otherwise.
+#[utoipa::path(
+    tag = "operations",
+    responses(
+        (status = 200, description = "Ready, with every check"),
+        (status = 503, description = "Not ready, with the failing checks"),
+    )
+)]
+#[get("/readyz")]
+async fn readyz(resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    let report = resources.readiness.check(&resources.mock_database);
+    if report.ready {
+        Ok(HttpResponse::Ok().json(report))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(report))
+    }
+}
+
+/// The crate version, git commit, enabled features and uptime.
+#[utoipa::path(tag = "operations", responses((status = 200, description = "Build information")))]
+#[get("/version")]
+async fn build_version(resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(health::build_info(resources.started_at)))
+}
+
+/// Prometheus metrics in the text exposition format.
+#[utoipa::path(
+    tag = "operations",
+    responses(
+        (status = 200, description = "The metrics", content_type = "text/plain"),
+        (status = 404, description = "Built without the metrics feature"),
+    )
+)]
+#[get("/metrics")]
+async fn prometheus_metrics(resources: web::Data<AppState>) -> std::io::Result<impl Responder> {
+    match metrics::render(&resources.mock_database) {
+        Some(rendered) => Ok(HttpResponse::Ok().content_type(metrics::CONTENT_TYPE).body(rendered)),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "notes": "Reached the metrics endpoint",
+            "error": "This server was built without the metrics feature"
+        }))),
     }
 }
 
@@ -339,3 +2263,869 @@ async fn index(data: web::Data<AppState>) -> String {
     let app_name = &data.app_name;
     format!("Hello! Welcome to {app_name}!")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use parking_lot::Mutex;
+    use playground_site::claims::{Message, NotifyError};
+    use playground_site::rate_limit::Limit;
+    use serde_json::Value;
+
+    const ADMIN_TOKEN: &str = "aaaaaaaaaaaaaaaaaaaa";
+
+    /// Keeps every message the claims send, so tests can read the codes.
+    #[derive(Clone, Default)]
+    struct Outbox(Arc<Mutex<Vec<Message>>>);
+
+    impl Outbox {
+        /// The code in the last message sent to `to`.
+        fn last_code(&self, to: &str) -> String {
+            let messages = self.0.lock();
+            let message = messages.iter().rev().find(|message| message.to == to).expect("a message was sent");
+            let code = message.body.split("The code is ").nth(1).expect("the message has a code");
+            code.chars().take_while(char::is_ascii_digit).collect()
+        }
+    }
+
+    impl Notifier for Outbox {
+        fn send(&self, message: &Message) -> Result<(), NotifyError> {
+            self.0.lock().push(message.clone());
+            Ok(())
+        }
+    }
+
+    /// Everything `create_server` sets up, over an empty store that's ready for traffic.
+    struct TestServer {
+        config: Config,
+        state: web::Data<AppState>,
+        rate_limiter: web::Data<RateLimiter>,
+        outbox: Outbox,
+    }
+
+    impl TestServer {
+        fn new() -> Self {
+            TestServer::with_config(Config { admin_token: Some(ADMIN_TOKEN.into()), ..Config::default() })
+        }
+
+        fn with_config(config: Config) -> Self {
+            let indexes = Indexes {
+                search: Arc::new(SearchIndex::new()),
+                autocomplete: Arc::new(Autocomplete::new()),
+                spatial: Arc::new(SpatialIndex::new()),
+            };
+            let mut store = Store::with_shards(4);
+            store.add_observer(indexes.search.clone());
+            store.add_observer(indexes.autocomplete.clone());
+            store.add_observer(indexes.spatial.clone());
+            let readiness = Arc::new(Readiness::new(None));
+            readiness.mark_migrations_applied();
+            readiness.mark_seed_loaded();
+            let outbox = Outbox::default();
+            let claims = Arc::new(Claims::new(Box::new(outbox.clone())));
+            let taxonomy = Arc::new(Taxonomy::with_defaults());
+            let state = app_state(&config, Arc::new(store), taxonomy, Arc::new(Redirects::new()), claims, readiness, indexes);
+            let rate_limiter = rate_limiter(&config, &state);
+            TestServer { config, state, rate_limiter, outbox }
+        }
+
+        fn app(
+            &self,
+        ) -> App<
+            impl ServiceFactory<
+                ServiceRequest,
+                Config = (),
+                Response = ServiceResponse<impl MessageBody>,
+                Error = actix_web::Error,
+                InitError = (),
+            >,
+        > {
+            build_app(&self.config, self.state.clone(), self.rate_limiter.clone())
+        }
+    }
+
+    fn header<B>(response: &ServiceResponse<B>, name: impl AsRef<str>) -> Option<String> {
+        response.headers().get(name.as_ref()).and_then(|value| value.to_str().ok()).map(str::to_string)
+    }
+
+    /// A valid business for the request bodies, with one review by alice.
+    fn business_json(name: &str) -> Value {
+        json!({
+            "business": {
+                "name": name,
+                "street_addr": "1 Main St",
+                "city": "Houston",
+                "state": "Texas",
+                "zip": 77001,
+                "phone_num": 7135550100u64,
+                "category": { "main_category": "Restaurant", "subcategory": "Pizza" },
+                "email": "owner@example.com",
+                "website": null
+            },
+            "reviews": [["alice", { "rating": 4, "dollar_signs": 2, "review": "Good crust" }]],
+            "photos": []
+        })
+    }
+
+    /// The `Authorization` header for a bearer token.
+    fn bearer(token: &str) -> (header::HeaderName, String) {
+        (header::AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    /// An admin making `user` the owner of `business`.
+    fn assign_owner_request(business: &str, user: &str) -> TestRequest {
+        TestRequest::put()
+            .uri(&format!("/v1/claims/{business}/owner"))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+            .set_json(json!({ "user": user }))
+    }
+
+    #[actix_web::test]
+    async fn health_readiness_and_version() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+
+        let response = test::call_service(&app, TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let version: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/version").to_request()).await;
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+
+        server.state.readiness.mark_shutting_down();
+        let response = test::call_service(&app, TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let report: Value = test::read_body_json(response).await;
+        assert_eq!(report["ready"], false);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[actix_web::test]
+    async fn metrics_label_requests_by_route() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let response = test::call_service(&app, TestRequest::get().uri("/v1/business/Nowhere").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = test::call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), metrics::CONTENT_TYPE);
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains(r#"belp_http_requests_total{method="GET",route="/v1/business/{business_name}",status="404"}"#));
+        assert!(body.contains("belp_store_items"));
+    }
+
+    #[actix_web::test]
+    async fn request_ids_are_passed_back_or_made_up() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+
+        let request = TestRequest::get().uri("/healthz").insert_header((REQUEST_ID_HEADER, "trace-42")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(header(&response, REQUEST_ID_HEADER).as_deref(), Some("trace-42"));
+
+        // Even responses that never reach a handler carry one.
+        let request = TestRequest::get().uri("/v1/nowhere").insert_header((REQUEST_ID_HEADER, "has spaces")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let generated = header(&response, REQUEST_ID_HEADER).unwrap();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn rate_limits_count_per_address_whatever_the_user_name() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let review = json!({ "rating": 4, "dollar_signs": 2, "review": "Fine" });
+        let review_from = |reviewer: &str, address: &str| {
+            TestRequest::post()
+                .uri(&format!("/v1/review/{reviewer}/Nowhere"))
+                .peer_addr(address.parse().unwrap())
+                .set_json(&review)
+                .to_request()
+        };
+
+        for attempt in 0..5 {
+            let response = test::call_service(&app, review_from(&format!("user{attempt}"), "10.0.0.1:4000")).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert_eq!(header(&response, "x-ratelimit-limit").as_deref(), Some("5"));
+            assert_eq!(header(&response, "x-ratelimit-remaining"), Some((4 - attempt).to_string()));
+        }
+        let response = test::call_service(&app, review_from("someone-else", "10.0.0.1:4001")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(header(&response, header::RETRY_AFTER).is_some());
+
+        let response = test::call_service(&app, review_from("someone-else", "10.0.0.2:4000")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn rate_limits_count_per_admin_or_owner_wherever_they_connect_from() {
+        let mut config = Config { admin_token: Some(ADMIN_TOKEN.into()), ..Config::default() };
+        config.rate_limit.routes.insert("add_review".into(), Limit { burst: 2, per_minute: 1 });
+        let server = TestServer::with_config(config);
+        let app = test::init_service(server.app()).await;
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json("Pizza Place")).to_request()).await;
+        let ownership: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Place", "olivia").to_request()).await;
+        let olivia = ownership["owner_token"].as_str().unwrap();
+        let review_from = |token: Option<&str>, address: &str| {
+            let mut request = TestRequest::post()
+                .uri("/v1/review/someone/Nowhere")
+                .peer_addr(address.parse().unwrap())
+                .set_json(json!({ "rating": 4, "dollar_signs": 2, "review": "Fine" }));
+            if let Some(token) = token {
+                request = request.insert_header(bearer(token));
+            }
+            request.to_request()
+        };
+
+        for token in [ADMIN_TOKEN, olivia] {
+            for address in ["10.0.0.1:4000", "10.0.0.2:4000"] {
+                assert_eq!(test::call_service(&app, review_from(Some(token), address)).await.status(), StatusCode::NOT_FOUND);
+            }
+            let response = test::call_service(&app, review_from(Some(token), "10.0.0.3:4000")).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        // A token that isn't anyone's counts against the address, like no token at all. Neither address was used up by
+        // the admin or the owner.
+        for address in ["10.0.0.1:4000", "10.0.0.3:4000"] {
+            assert_eq!(test::call_service(&app, review_from(Some("made up"), address)).await.status(), StatusCode::NOT_FOUND);
+            assert_eq!(test::call_service(&app, review_from(None, address)).await.status(), StatusCode::NOT_FOUND);
+            let response = test::call_service(&app, review_from(None, address)).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+
+    #[actix_web::test]
+    async fn client_versions_are_ignored_and_new_photos_get_an_etag() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let mut business = business_json("Pizza Place");
+        business["version"] = json!(41);
+        business["reviews"][0][1]["version"] = json!(99);
+        business["photos"] = json!([{ "user_name": "bob", "photo_id": 7, "photo_url": "https://example.com/a.jpg", "photo_caption": null, "version": 42 }]);
+
+        let response = test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"1\""));
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["reviews"][0][1]["version"], 1);
+        assert_eq!(stored["photos"][0]["version"], 1);
+        assert_eq!(stored["photos"][0]["photo_id"], 0);
+
+        // Replacing it moves the nested versions on, whatever the body says, so tags from before the replace are stale.
+        let request = TestRequest::put().uri("/v1/business/Pizza%20Place").insert_header((header::IF_MATCH, "\"1\"")).set_json(&business).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"2\""));
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["reviews"][0][1]["version"], 2);
+        assert_eq!(stored["photos"][0]["version"], 2);
+        let review = json!({ "rating": 1, "dollar_signs": 2, "review": "Stale" });
+        let request = TestRequest::put()
+            .uri("/v1/review/alice/Pizza%20Place")
+            .insert_header((header::IF_MATCH, "\"1\""))
+            .set_json(&review)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"2\""));
+
+        let request = TestRequest::put().uri("/v1/business/Pizza%20Place").insert_header((header::IF_MATCH, "\"1\"")).set_json(&business).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"2\""));
+
+        let photo = json!({ "photo_url": "https://example.com/b.jpg", "photo_caption": "Booths" });
+        let response = test::call_service(&app, TestRequest::post().uri("/v1/photos/bob/Pizza%20Place").set_json(&photo).to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"1\""));
+        let caption = json!({ "photo_caption": "The booths" });
+        for expected in [StatusCode::OK, StatusCode::PRECONDITION_FAILED] {
+            let request = TestRequest::put()
+                .uri("/v1/photos/bob/Pizza%20Place/2")
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .set_json(&caption)
+                .to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn patches_apply_to_the_business_as_it_is_when_written() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json("Pizza Place")).to_request()).await;
+        let patch = |if_match: Option<&str>, body: &str| {
+            let mut request = TestRequest::patch()
+                .uri("/v1/business/Pizza%20Place")
+                .insert_header((header::CONTENT_TYPE, "application/json-patch+json"))
+                .set_payload(body.to_string());
+            if let Some(if_match) = if_match {
+                request = request.insert_header((header::IF_MATCH, if_match));
+            }
+            request.to_request()
+        };
+
+        // Other writes in between don't matter without If-Match, they do with a stale one.
+        let review = json!({ "rating": 5, "dollar_signs": 1, "review": null });
+        test::call_service(&app, TestRequest::post().uri("/v1/review/bob/Pizza%20Place").set_json(&review).to_request()).await;
+        let website = r#"[{"op": "add", "path": "/website", "value": "https://pizza.example.com"}]"#;
+        let response = test::call_service(&app, patch(Some("\"1\""), website)).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = test::call_service(&app, patch(None, website)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::ETAG).as_deref(), Some("\"3\""));
+
+        // Tests in a JSON Patch see the current business.
+        let test_website = r#"[{"op": "test", "path": "/website", "value": "https://pizza.example.com"}, {"op": "replace", "path": "/city", "value": "Austin"}]"#;
+        let patched: Value = test::call_and_read_body_json(&app, patch(Some("\"3\""), test_website)).await;
+        assert_eq!(patched["business"]["city"], "Austin");
+        let test_website = r#"[{"op": "test", "path": "/website", "value": "https://elsewhere.example.com"}]"#;
+        assert_eq!(test::call_service(&app, patch(None, test_website)).await.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn cors_answers_preflights_and_exposes_headers() {
+        let mut config = Config { admin_token: Some(ADMIN_TOKEN.into()), ..Config::default() };
+        config.cors.allowed_origins = vec!["https://belp.example.com".into()];
+        let server = TestServer::with_config(config);
+        let app = test::init_service(server.app()).await;
+
+        // Answered by the middleware, so it doesn't use up the route's rate limit.
+        let preflight = TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/v1/review/alice/Nowhere")
+            .insert_header((header::ORIGIN, "https://belp.example.com"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+            .to_request();
+        let response = test::call_service(&app, preflight).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN).as_deref(), Some("https://belp.example.com"));
+        assert!(header(&response, "x-ratelimit-remaining").is_none());
+
+        // Admin and owner requests carry their token, and usually a version to check.
+        let preflight = TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/v1/business/Pizza%20Place")
+            .insert_header((header::ORIGIN, "https://belp.example.com"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization, if-match"))
+            .to_request();
+        let response = test::call_service(&app, preflight).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let allowed = header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap();
+        assert!(allowed.contains("authorization") && allowed.contains("if-match"), "{allowed}");
+
+        let request = TestRequest::get().uri("/healthz").insert_header((header::ORIGIN, "https://belp.example.com")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN).as_deref(), Some("https://belp.example.com"));
+        assert!(header(&response, header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap().contains("etag"));
+
+        let request = TestRequest::get().uri("/healthz").insert_header((header::ORIGIN, "https://evil.example.com")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn the_openapi_document_and_docs_page_are_served() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+
+        let response = test::call_service(&app, TestRequest::get().uri("/openapi.json").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::CONTENT_TYPE).as_deref(), Some("application/json"));
+        let document: Value = test::read_body_json(response).await;
+        assert!(document["paths"]["/v1/claims/{user_name}/{business_name}"]["post"].is_object());
+        assert!(document["paths"]["/v1/responses/{business_name}/{reviewer_name}"]["put"].is_object());
+
+        let response = test::call_service(&app, TestRequest::get().uri("/docs").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = test::read_body(response).await;
+        let page = String::from_utf8_lossy(&page);
+        assert!(page.contains("openapi.json"));
+        assert!(!page.contains("/latest/"), "Redoc should be pinned to a release");
+    }
+
+    #[actix_web::test]
+    async fn unversioned_paths_are_routed_by_the_accept_header() {
+        let mut config = Config { admin_token: Some(ADMIN_TOKEN.into()), ..Config::default() };
+        let deprecation = versioning::Deprecation { since: "2026-01-01".into(), sunset: Some("2027-01-01".into()) };
+        config.api.deprecations.insert(1, deprecation);
+        let server = TestServer::with_config(config);
+        let app = test::init_service(server.app()).await;
+
+        // The default version, v1, which answers with a plain list and points at v2.
+        let response = test::call_service(&app, TestRequest::get().uri("/business").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, API_VERSION_HEADER).as_deref(), Some("1"));
+        assert!(header(&response, header::VARY).unwrap().starts_with("accept"));
+        assert_eq!(header(&response, header::LINK).as_deref(), Some("</v2/business>; rel=\"successor-version\""));
+        assert!(header(&response, "sunset").is_some());
+        let body: Value = test::read_body_json(response).await;
+        assert!(body.is_array());
+
+        let request = TestRequest::get().uri("/business").insert_header((header::ACCEPT, "application/vnd.belp.v2+json")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(header(&response, API_VERSION_HEADER).as_deref(), Some("2"));
+        assert!(header(&response, "deprecation").is_none());
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["page"], 1);
+
+        let request = TestRequest::get().uri("/business").insert_header((header::ACCEPT, "application/vnd.belp.v9+json")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        let response = test::call_service(&app, TestRequest::get().uri("/v9/business").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[actix_web::test]
+    async fn businesses_from_before_the_address_checks_stay_editable() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        // Like a lot of the seed data: a Texas business with an Ohio ZIP code.
+        let mut business = business_json("Pizza Place");
+        business["business"]["zip"] = json!(43945);
+        let business: BusinessResponse = serde_json::from_value(business).unwrap();
+        server.state.mock_database.insert_new("Pizza Place".into(), business);
+        let patch = |body: Value| {
+            TestRequest::patch()
+                .uri("/v1/business/Pizza%20Place")
+                .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+                .set_payload(body.to_string())
+                .to_request()
+        };
+
+        let response = test::call_service(&app, patch(json!({ "website": "https://pizza.example.com" }))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, patch(json!({ "street_addr": "2 Main St" }))).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["problems"], json!(["ZIP code 43945 is in OH, not TX"]));
+    }
+
+    #[actix_web::test]
+    async fn pages_past_the_end_are_empty_however_far_out() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json("Pizza Place")).to_request()).await;
+
+        for uri in [
+            "/v2/business?page=18446744073709551615&per_page=100",
+            "/v2/business?page=18446744073709551615&per_page=100&near=29.76,-95.37",
+        ] {
+            let response = test::call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            let body: Value = test::read_body_json(response).await;
+            assert_eq!(body["businesses"], json!([]), "{uri}");
+            assert_eq!(body["total"], 1, "{uri}");
+        }
+        let uri = "/v1/search?q=pizza&page=18446744073709551615&per_page=100";
+        let response = test::call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["results"], json!([]));
+        assert_eq!(body["total"], 1);
+    }
+
+    #[actix_web::test]
+    async fn categories_in_use_arent_deleted() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let as_admin = |request: TestRequest| request.insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")));
+        let request = as_admin(TestRequest::post().uri("/v1/categories")).set_json(json!({ "name": "Deep Dish" })).to_request();
+        let category: Value = test::call_and_read_body_json(&app, request).await;
+        let mut business = business_json("Pizza Place");
+        business["business"]["category_ids"] = json!([category["id"]]);
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+
+        let delete_request = || as_admin(TestRequest::delete().uri(&format!("/v1/categories/{}", category["id"]))).to_request();
+        let response = test::call_service(&app, delete_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["businesses"], json!(["Pizza Place"]));
+
+        test::call_service(&app, TestRequest::delete().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(test::call_service(&app, delete_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn merged_names_follow_the_business_they_were_merged_into() {
+        let server = TestServer::with_config(Config { admin_token: Some(ADMIN_TOKEN.into()), ..Config::default() });
+        let app = test::init_service(server.app()).await;
+        let create = |name: &str| TestRequest::post().uri("/v1/business").set_json(business_json(name)).to_request();
+        let merge = |keep: &str, merge: &str| {
+            TestRequest::post()
+                .uri("/v1/duplicates/merge")
+                .insert_header(bearer(ADMIN_TOKEN))
+                .set_json(json!({ "keep": keep, "merge": merge }))
+                .to_request()
+        };
+        let find = |name: &str| TestRequest::get().uri(&format!("/v1/business/{name}")).to_request();
+        for name in ["Pizza Place", "Pizza Palace"] {
+            test::call_service(&app, create(name)).await;
+        }
+        assert_eq!(test::call_service(&app, merge("Pizza Place", "Pizza Palace")).await.status(), StatusCode::OK);
+
+        // Renaming the survivor, either way, takes the redirect along.
+        let rename = TestRequest::patch()
+            .uri("/v1/business/Pizza%20Place")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(r#"{"name": "Pizza Plaza"}"#);
+        assert_eq!(test::call_service(&app, rename.to_request()).await.status(), StatusCode::OK);
+        let response = test::call_service(&app, find("Pizza%20Palace")).await;
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/v1/business/Pizza%20Plaza");
+        let rename = TestRequest::put().uri("/v1/business/Pizza%20Plaza").set_json(business_json("Pizza Parlor"));
+        assert_eq!(test::call_service(&app, rename.to_request()).await.status(), StatusCode::OK);
+        let response = test::call_service(&app, find("Pizza%20Palace")).await;
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/v1/business/Pizza%20Parlor");
+
+        // Deleting it leaves nothing to redirect to.
+        let delete = TestRequest::delete().uri("/v1/business/Pizza%20Parlor").to_request();
+        assert_eq!(test::call_service(&app, delete).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, find("Pizza%20Palace")).await.status(), StatusCode::NOT_FOUND);
+
+        // A new business with a merged name takes the name back, and keeps it once it's gone again.
+        for name in ["Taco Stand", "Taco Stop"] {
+            test::call_service(&app, create(name)).await;
+        }
+        assert_eq!(test::call_service(&app, merge("Taco Stand", "Taco Stop")).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, create("Taco Stop")).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, find("Taco%20Stop")).await.status(), StatusCode::OK);
+        let delete = TestRequest::delete().uri("/v1/business/Taco%20Stop").to_request();
+        assert_eq!(test::call_service(&app, delete).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, find("Taco%20Stop")).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn merging_an_owned_duplicate_doesnt_hand_over_the_survivor() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        for name in ["Pizza Place", "Pizza Palace"] {
+            test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json(name)).to_request()).await;
+        }
+        let owned: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Palace", "olivia").to_request()).await;
+        let olivia = owned["owner_token"].as_str().unwrap();
+        let merge = TestRequest::post()
+            .uri("/v1/duplicates/merge")
+            .insert_header(bearer(ADMIN_TOKEN))
+            .set_json(json!({ "keep": "Pizza Place", "merge": "Pizza Palace" }))
+            .to_request();
+        let merged: Value = test::call_and_read_body_json(&app, merge).await;
+        assert_eq!(merged["business"]["owner"], Value::Null);
+
+        // Owning the duplicate doesn't count for the survivor, which is still up for a claim.
+        let new_email = TestRequest::patch()
+            .uri("/v1/business/Pizza%20Place")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .insert_header(bearer(olivia))
+            .set_payload(r#"{"email": "olivia@example.com"}"#)
+            .to_request();
+        assert_eq!(test::call_service(&app, new_email).await.status(), StatusCode::FORBIDDEN);
+        let claim = TestRequest::post().uri("/v1/claims/olivia/Pizza%20Place").set_json(json!({})).to_request();
+        assert_eq!(test::call_service(&app, claim).await.status(), StatusCode::ACCEPTED);
+    }
+
+    #[actix_web::test]
+    async fn contact_details_only_change_with_the_owner_or_an_admin() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let business = business_json("Pizza Place");
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        let new_email = |token: Option<&str>| {
+            let mut request = TestRequest::patch()
+                .uri("/v1/business/Pizza%20Place")
+                .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+                .set_payload(r#"{"email": "mallory@example.com"}"#);
+            if let Some(token) = token {
+                request = request.insert_header(bearer(token));
+            }
+            request.to_request()
+        };
+
+        // Not even before anyone claims it, or pointing the email at yourself and claiming it would be a takeover, by
+        // either kind of write.
+        assert_eq!(test::call_service(&app, new_email(None)).await.status(), StatusCode::FORBIDDEN);
+        let mut replacement = business.clone();
+        replacement["business"]["email"] = json!("mallory@example.com");
+        let response = test::call_service(&app, TestRequest::put().uri("/v1/business/Pizza%20Place").set_json(&replacement).to_request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let claim = |user: &str| TestRequest::post().uri(&format!("/v1/claims/{user}/Pizza%20Place")).set_json(json!({})).to_request();
+        assert_eq!(test::call_service(&app, claim("mallory")).await.status(), StatusCode::ACCEPTED);
+        assert!(server.outbox.0.lock().iter().all(|message| message.to == "owner@example.com"));
+        // Everything else can still be edited.
+        let response = test::call_service(&app, TestRequest::put().uri("/v1/business/Pizza%20Place").set_json(&business).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(test::call_service(&app, claim("olivia")).await.status(), StatusCode::ACCEPTED);
+        let code = server.outbox.last_code("owner@example.com");
+        let confirm = TestRequest::post().uri("/v1/claims/olivia/Pizza%20Place/confirm").set_json(json!({ "code": code })).to_request();
+        let claimed: Value = test::call_and_read_body_json(&app, confirm).await;
+        assert_eq!(claimed["business"]["owner"], "olivia");
+
+        assert_eq!(test::call_service(&app, new_email(None)).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, new_email(Some("olivia"))).await.status(), StatusCode::FORBIDDEN);
+        let olivia = claimed["owner_token"].as_str().unwrap();
+        assert_eq!(test::call_service(&app, new_email(Some(olivia))).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, new_email(Some(ADMIN_TOKEN))).await.status(), StatusCode::OK);
+
+        let audit = TestRequest::get()
+            .uri("/v1/claims/Pizza%20Place/audit")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+            .to_request();
+        let audit: Value = test::call_and_read_body_json(&app, audit).await;
+        let events: Vec<&str> = audit.as_array().unwrap().iter().map(|entry| entry["event"].as_str().unwrap()).collect();
+        assert_eq!(events, ["requested", "requested", "verified"]);
+    }
+
+    #[actix_web::test]
+    async fn owned_businesses_are_only_deleted_by_the_owner_or_an_admin() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let delete = |token: Option<&str>| {
+            let mut request = TestRequest::delete().uri("/v1/business/Pizza%20Place");
+            if let Some(token) = token {
+                request = request.insert_header(bearer(token));
+            }
+            request.to_request()
+        };
+        let own = || async {
+            test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json("Pizza Place")).to_request()).await;
+            let owned: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Place", "olivia").to_request()).await;
+            owned["owner_token"].as_str().unwrap().to_string()
+        };
+
+        // Otherwise it could be added again under the same name, with someone else's contact details, and claimed.
+        let olivia = own().await;
+        assert_eq!(test::call_service(&app, delete(None)).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, delete(Some("olivia"))).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, delete(Some(&olivia))).await.status(), StatusCode::OK);
+        own().await;
+        assert_eq!(test::call_service(&app, delete(Some(ADMIN_TOKEN))).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn codes_only_claim_the_business_they_were_sent_for() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let claim = || TestRequest::post().uri("/v1/claims/mallory/Pizza%20Place").set_json(json!({})).to_request();
+        let confirm = |code: &str| {
+            TestRequest::post().uri("/v1/claims/mallory/Pizza%20Place/confirm").set_json(json!({ "code": code })).to_request()
+        };
+        let mut business = business_json("Pizza Place");
+        business["business"]["email"] = json!("mallory@example.com");
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        assert_eq!(test::call_service(&app, claim()).await.status(), StatusCode::ACCEPTED);
+        let code = server.outbox.last_code("mallory@example.com");
+
+        // Someone else's business under the same name doesn't inherit the claim.
+        test::call_service(&app, TestRequest::delete().uri("/v1/business/Pizza%20Place").to_request()).await;
+        let recreated = business_json("Pizza Place");
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&recreated).to_request()).await;
+        assert_eq!(test::call_service(&app, confirm(&code)).await.status(), StatusCode::NOT_FOUND);
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["owner"], Value::Null);
+
+        // Nor does a code outlast an admin changing where codes go.
+        assert_eq!(test::call_service(&app, claim()).await.status(), StatusCode::ACCEPTED);
+        let code = server.outbox.last_code("owner@example.com");
+        business["business"]["email"] = json!("olivia@example.com");
+        let request = TestRequest::put()
+            .uri("/v1/business/Pizza%20Place")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+            .set_json(&business)
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, confirm(&code)).await.status(), StatusCode::GONE);
+
+        let audit = TestRequest::get()
+            .uri("/v1/claims/Pizza%20Place/audit")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+            .to_request();
+        let audit: Value = test::call_and_read_body_json(&app, audit).await;
+        let events: Vec<&str> = audit.as_array().unwrap().iter().map(|entry| entry["event"].as_str().unwrap()).collect();
+        assert_eq!(events, ["requested", "business_deleted", "requested", "rejected"]);
+        assert_eq!(audit[1]["cancelled"], json!(["mallory"]));
+    }
+
+    #[actix_web::test]
+    async fn claims_follow_a_renamed_business() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let audit = |business: &str| {
+            TestRequest::get()
+                .uri(&format!("/v1/claims/{business}/audit"))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+                .to_request()
+        };
+        for name in ["Pizza Place", "Taken"] {
+            test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(business_json(name)).to_request()).await;
+        }
+        let request = TestRequest::post().uri("/v1/claims/olivia/Pizza%20Place").set_json(json!({})).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::ACCEPTED);
+
+        let rename = TestRequest::patch()
+            .uri("/v1/business/Pizza%20Place")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(r#"{"name": "Pizza Palace"}"#)
+            .to_request();
+        assert_eq!(test::call_service(&app, rename).await.status(), StatusCode::OK);
+        let code = server.outbox.last_code("owner@example.com");
+        let confirm = TestRequest::post().uri("/v1/claims/olivia/Pizza%20Palace/confirm").set_json(json!({ "code": code })).to_request();
+        let claimed: Value = test::call_and_read_body_json(&app, confirm).await;
+        let olivia = claimed["owner_token"].as_str().unwrap();
+
+        let renamed = business_json("Pizza Plaza");
+        let response = test::call_service(&app, TestRequest::put().uri("/v1/business/Pizza%20Palace").set_json(&renamed).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Plaza").to_request()).await;
+        assert_eq!(stored["owner"], "olivia");
+        let reply = TestRequest::post()
+            .uri("/v1/responses/Pizza%20Plaza/alice")
+            .insert_header(bearer(olivia))
+            .set_json(json!({ "text": "Thanks!" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, reply).await.status(), StatusCode::CREATED);
+        let history: Value = test::call_and_read_body_json(&app, audit("Pizza%20Plaza")).await;
+        assert_eq!(history.as_array().unwrap().len(), 2);
+        for old_name in ["Pizza%20Place", "Pizza%20Palace"] {
+            let history: Value = test::call_and_read_body_json(&app, audit(old_name)).await;
+            assert_eq!(history, json!([]));
+        }
+
+        let response = test::call_service(&app, TestRequest::put().uri("/v1/business/Pizza%20Plaza").set_json(business_json("Taken")).to_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn owner_responses_cant_be_written_through_the_business() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let forged = json!({ "owner": "mallory", "text": "Forged", "responded_at": "2026-10-18T21:00:00Z", "edited_at": null, "version": 1 });
+        let mut business = business_json("Pizza Place");
+        business["reviews"][0][1]["owner_response"] = forged.clone();
+
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["reviews"][0][1]["owner_response"], Value::Null);
+
+        let ownership: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Place", "olivia").to_request()).await;
+        let reply = json!({ "text": "Thanks!" });
+        let request = TestRequest::post()
+            .uri("/v1/responses/Pizza%20Place/alice")
+            .insert_header(bearer(ownership["owner_token"].as_str().unwrap()))
+            .set_json(&reply)
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::CREATED);
+
+        // A replacement can't change the reply alice got, or make one up for bob.
+        business["reviews"] = json!([
+            ["alice", { "rating": 5, "dollar_signs": 2, "review": "Even better", "owner_response": forged }],
+            ["bob", { "rating": 3, "dollar_signs": 2, "review": "Okay", "owner_response": forged }],
+        ]);
+        let response = test::call_service(&app, TestRequest::put().uri("/v1/business/Pizza%20Place").set_json(&business).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["reviews"][0][1]["review"], "Even better");
+        assert_eq!(stored["reviews"][0][1]["owner_response"]["text"], "Thanks!");
+        assert_eq!(stored["reviews"][1][1]["owner_response"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn only_the_owner_replies_and_only_to_one_review() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let mut business = business_json("Pizza Place");
+        business["reviews"] = json!([
+            ["Anonymous", { "rating": 1, "dollar_signs": 2, "review": "Cold" }],
+            ["Anonymous", { "rating": 5, "dollar_signs": 2, "review": "Hot" }],
+            ["alice", { "rating": 4, "dollar_signs": 2, "review": "Good crust" }],
+        ]);
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        let reply = json!({ "text": "Thanks!" });
+        let reply_to = |token: &str, reviewer: &str| {
+            TestRequest::post()
+                .uri(&format!("/v1/responses/Pizza%20Place/{reviewer}"))
+                .insert_header(bearer(token))
+                .set_json(&reply)
+                .to_request()
+        };
+
+        // Nobody owns it yet, and once someone does only their token will do. Their name is public, so it's no proof.
+        assert_eq!(test::call_service(&app, reply_to("olivia", "alice")).await.status(), StatusCode::FORBIDDEN);
+        let ownership: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Place", "olivia").to_request()).await;
+        let olivia = ownership["owner_token"].as_str().unwrap();
+        assert_eq!(test::call_service(&app, reply_to("olivia", "alice")).await.status(), StatusCode::FORBIDDEN);
+        let anonymous = TestRequest::post().uri("/v1/responses/Pizza%20Place/alice").set_json(&reply).to_request();
+        assert_eq!(test::call_service(&app, anonymous).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, reply_to(olivia, "alice")).await.status(), StatusCode::CREATED);
+
+        // Someone else can't change or delete it, but an admin can step in.
+        let edit = |token: &str| {
+            TestRequest::put()
+                .uri("/v1/responses/Pizza%20Place/alice")
+                .insert_header(bearer(token))
+                .set_json(json!({ "text": "Never again" }))
+        };
+        assert_eq!(test::call_service(&app, edit("mallory").to_request()).await.status(), StatusCode::FORBIDDEN);
+        let remove = |token: &str| TestRequest::delete().uri("/v1/responses/Pizza%20Place/alice").insert_header(bearer(token));
+        assert_eq!(test::call_service(&app, remove("mallory").to_request()).await.status(), StatusCode::FORBIDDEN);
+        let as_admin = TestRequest::put()
+            .uri("/v1/responses/Pizza%20Place/alice")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}")))
+            .set_json(json!({ "text": "Sorry about that" }));
+        let edited: Value = test::call_and_read_body_json(&app, as_admin.to_request()).await;
+        assert_eq!(edited["owner_response"]["owner"], "olivia");
+
+        assert_eq!(test::call_service(&app, reply_to(olivia, "Anonymous")).await.status(), StatusCode::CONFLICT);
+        let request = TestRequest::delete()
+            .uri("/v1/responses/Pizza%20Place/Anonymous")
+            .insert_header(bearer(olivia))
+            .insert_header((header::IF_MATCH, "\"1\""))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::CONFLICT);
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["reviews"][0][1]["owner_response"], Value::Null);
+        assert_eq!(stored["reviews"][1][1]["owner_response"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn only_the_owner_lays_out_an_owned_business_photos() {
+        let server = TestServer::new();
+        let app = test::init_service(server.app()).await;
+        let mut business = business_json("Pizza Place");
+        business["photos"] = json!([
+            { "user_name": "bob", "photo_id": 0, "photo_url": "https://example.com/a.jpg", "photo_caption": null },
+            { "user_name": "bob", "photo_id": 1, "photo_url": "https://example.com/b.jpg", "photo_caption": null },
+        ]);
+        test::call_service(&app, TestRequest::post().uri("/v1/business").set_json(&business).to_request()).await;
+        let reorder = |token: Option<&str>, photo_ids: [usize; 2]| {
+            let mut request = TestRequest::put().uri("/v1/photos/Pizza%20Place/order").set_json(json!({ "photo_ids": photo_ids }));
+            if let Some(token) = token {
+                request = request.insert_header(bearer(token));
+            }
+            request.to_request()
+        };
+        let cover = || TestRequest::put().uri("/v1/photos/Pizza%20Place/cover").set_json(json!({ "photo_id": 1 }));
+
+        // Anyone can while nobody owns it.
+        assert_eq!(test::call_service(&app, reorder(None, [1, 0])).await.status(), StatusCode::OK);
+        let ownership: Value = test::call_and_read_body_json(&app, assign_owner_request("Pizza%20Place", "olivia").to_request()).await;
+        let olivia = ownership["owner_token"].as_str().unwrap();
+
+        assert_eq!(test::call_service(&app, reorder(None, [0, 1])).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, reorder(Some("mallory"), [0, 1])).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, cover().insert_header(bearer("mallory")).to_request()).await.status(), StatusCode::FORBIDDEN);
+        let stored: Value = test::call_and_read_body_json(&app, TestRequest::get().uri("/v1/business/Pizza%20Place").to_request()).await;
+        assert_eq!(stored["photos"][0]["photo_id"], 1);
+        assert_eq!(stored["cover_photo_id"], Value::Null);
+
+        assert_eq!(test::call_service(&app, reorder(Some("olivia"), [0, 1])).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, reorder(Some(olivia), [0, 1])).await.status(), StatusCode::OK);
+        let as_admin = cover().insert_header(bearer(ADMIN_TOKEN));
+        assert_eq!(test::call_service(&app, as_admin.to_request()).await.status(), StatusCode::OK);
+    }
+}
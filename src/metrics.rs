@@ -0,0 +1,132 @@
+// Prometheus metrics, served on /metrics in the text exposition format.
+// Without the `metrics` feature everything here is a no-op, so callers don't need their own cfgs.
+use std::time::Duration;
+
+use crate::store::Store;
+
+/// Which kind of shard lock was waited on.
+#[derive(Debug, Clone, Copy)]
+pub enum LockMode {
+    Read,
+    Write,
+}
+
+#[cfg(feature = "metrics")]
+mod registry {
+    use std::sync::OnceLock;
+
+    use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+
+    pub struct Metrics {
+        pub registry: Registry,
+        pub requests: IntCounterVec,
+        pub request_duration: HistogramVec,
+        pub store_items: IntGaugeVec,
+        pub lock_wait: HistogramVec,
+    }
+
+    pub fn metrics() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(|| {
+            let registry = Registry::new_custom(Some("belp".into()), None).expect("valid metrics prefix");
+            let requests = IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled, by route and status code"),
+                &["method", "route", "status"],
+            )
+            .expect("valid metric");
+            let request_duration = HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "Time spent handling HTTP requests"),
+                &["method", "route", "status"],
+            )
+            .expect("valid metric");
+            let store_items = IntGaugeVec::new(
+                Opts::new("store_items", "Businesses, reviews and photos currently in the store"),
+                &["kind"],
+            )
+            .expect("valid metric");
+            // Uncontended locks are taken in well under a microsecond, so the buckets start low.
+            let lock_wait = HistogramVec::new(
+                HistogramOpts::new("store_lock_wait_seconds", "Time spent waiting for a store shard lock")
+                    .buckets(prometheus::exponential_buckets(1e-6, 4.0, 10).expect("valid buckets")),
+                &["mode"],
+            )
+            .expect("valid metric");
+
+            registry.register(Box::new(requests.clone())).expect("metric registered once");
+            registry.register(Box::new(request_duration.clone())).expect("metric registered once");
+            registry.register(Box::new(store_items.clone())).expect("metric registered once");
+            registry.register(Box::new(lock_wait.clone())).expect("metric registered once");
+            Metrics { registry, requests, request_duration, store_items, lock_wait }
+        })
+    }
+
+    pub fn encode(metrics: &Metrics) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&metrics.registry.gather(), &mut buffer).expect("metrics encode as text");
+        String::from_utf8(buffer).expect("the text format is UTF-8")
+    }
+}
+
+/// The content type of `render`'s output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counts a finished request. `route` is the matched route pattern, not the raw path, so the label stays bounded.
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        let metrics = registry::metrics();
+        metrics.requests.with_label_values(&labels).inc();
+        metrics.request_duration.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (method, route, status, elapsed);
+}
+
+pub fn observe_lock_wait(mode: LockMode, waited: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let mode = match mode {
+            LockMode::Read => "read",
+            LockMode::Write => "write",
+        };
+        registry::metrics().lock_wait.with_label_values(&[mode]).observe(waited.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (mode, waited);
+}
+
+/// Every metric in the Prometheus text format, with the store gauges refreshed first.
+/// Returns `None` when the server was built without the `metrics` feature.
+pub fn render(store: &Store) -> Option<String> {
+    #[cfg(feature = "metrics")]
+    {
+        let counts = store.counts();
+        let metrics = registry::metrics();
+        metrics.store_items.with_label_values(&["businesses"]).set(counts.businesses as i64);
+        metrics.store_items.with_label_values(&["reviews"]).set(counts.reviews as i64);
+        metrics.store_items.with_label_values(&["photos"]).set(counts.photos as i64);
+        Some(registry::encode(metrics))
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = store;
+        None
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_labelled_by_route_and_status() {
+        observe_request("GET", "/business/{business_name}", 404, Duration::from_millis(3));
+        let rendered = render(&Store::new()).unwrap();
+        assert!(rendered.contains(
+            r#"belp_http_requests_total{method="GET",route="/business/{business_name}",status="404"}"#
+        ));
+        assert!(rendered.contains(r#"belp_store_items{kind="businesses"} 0"#));
+    }
+}
@@ -0,0 +1,114 @@
+// Conditional request handling. Businesses, reviews and photos all carry a version number,
+// which is handed out as a strong ETag and checked against If-Match / If-None-Match.
+use actix_web::{
+    http::header::{self, EntityTag, Header, IfMatch, IfNoneMatch},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+
+pub fn etag(version: u64) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+/// Adds the ETag for `version` to a response that was already built.
+pub fn with_etag(mut response: HttpResponse, version: u64) -> HttpResponse {
+    if let Ok(value) = header::HeaderValue::from_str(&etag(version).to_string()) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+/// Checks the If-Match header of a write against the current version of the resource (`None` if it doesn't exist yet).
+/// When `required` is set, writes to existing resources without an If-Match header are turned away with a 428.
+/// Returns the response to send instead of doing the write, if the precondition doesn't hold.
+pub fn check_if_match(request: &HttpRequest, current: Option<u64>, required: bool) -> Option<HttpResponse> {
+    let if_match = match IfMatch::parse(request) {
+        Ok(if_match) => if_match,
+        Err(_) => {
+            return Some(HttpResponse::BadRequest().json(json!({
+                "error": "Malformed If-Match header"
+            })))
+        }
+    };
+
+    let matches = match (&if_match, current) {
+        (IfMatch::Items(tags), Some(_)) if tags.is_empty() => {
+            if required {
+                return Some(HttpResponse::PreconditionRequired().json(json!({
+                    "error": "This request needs an If-Match header with the resource's current ETag"
+                })));
+            }
+            true
+        }
+        (IfMatch::Items(tags), None) => tags.is_empty(),
+        (IfMatch::Any, current) => current.is_some(),
+        (IfMatch::Items(tags), Some(version)) => tags.iter().any(|tag| tag.strong_eq(&etag(version))),
+    };
+
+    if matches {
+        None
+    } else {
+        Some(precondition_failed(current))
+    }
+}
+
+/// Whether a GET can be answered with a 304 because the client already has the current version.
+pub fn not_modified(request: &HttpRequest, version: u64) -> bool {
+    match IfNoneMatch::parse(request) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag(version))),
+        Err(_) => false,
+    }
+}
+
+pub fn not_modified_response(version: u64) -> HttpResponse {
+    with_etag(HttpResponse::NotModified().finish(), version)
+}
+
+fn precondition_failed(current: Option<u64>) -> HttpResponse {
+    let response = HttpResponse::PreconditionFailed().json(json!({
+        "error": "The resource has changed since it was fetched",
+        "current_version": current,
+    }));
+    match current {
+        Some(version) => with_etag(response, version),
+        None => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    #[test]
+    fn if_match_only_accepts_the_current_version() {
+        let stale = TestRequest::default().insert_header((header::IF_MATCH, "\"1\"")).to_http_request();
+        let status = check_if_match(&stale, Some(2), false).map(|response| response.status());
+        assert_eq!(status, Some(StatusCode::PRECONDITION_FAILED));
+
+        let current = TestRequest::default().insert_header((header::IF_MATCH, "\"2\"")).to_http_request();
+        assert!(check_if_match(&current, Some(2), false).is_none());
+
+        let any = TestRequest::default().insert_header((header::IF_MATCH, "*")).to_http_request();
+        assert!(check_if_match(&any, Some(2), false).is_none());
+        assert!(check_if_match(&any, None, false).is_some());
+    }
+
+    #[test]
+    fn missing_if_match_is_only_rejected_when_required() {
+        let request = TestRequest::default().to_http_request();
+        assert!(check_if_match(&request, Some(1), false).is_none());
+        let status = check_if_match(&request, Some(1), true).map(|response| response.status());
+        assert_eq!(status, Some(StatusCode::PRECONDITION_REQUIRED));
+        // Creating something new never needs an If-Match.
+        assert!(check_if_match(&request, None, true).is_none());
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        let request = TestRequest::default().insert_header((header::IF_NONE_MATCH, "W/\"3\"")).to_http_request();
+        assert!(not_modified(&request, 3));
+        assert!(!not_modified(&request, 4));
+    }
+}
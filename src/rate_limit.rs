@@ -0,0 +1,374 @@
+// Token bucket rate limiting. Every (route, client) pair gets a bucket that holds up to `burst` tokens and refills
+// at `per_minute`. A request takes a token, and when the bucket is empty it's turned away with a 429.
+//
+// Routes opt in by wrapping themselves with `RateLimit::route("name")`, so the limits are looked up by a stable
+// route name rather than by path. Requests that prove who they're from (with a token the app checks, see
+// `RateLimiter::with_identity`) count against that identity wherever they come from. Everything else counts against
+// the client's IP address, never against a user named in the path: nothing checks those names, so keying on them
+// would let anyone spend someone else's allowance. Behind a reverse proxy every request comes from the proxy, so
+// proxies listed in `trusted_proxies` are looked past to the address they put in X-Forwarded-For.
+use std::collections::{BTreeMap, HashMap};
+use std::future::{ready, Future, Ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::time::Instant;
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, RETRY_AFTER, X_FORWARDED_FOR},
+    web, Error, HttpRequest, HttpResponse,
+};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Once there are this many buckets, full ones (clients that have been quiet for a while) are dropped, then the least
+/// recently used ones until there's room again.
+const MAX_BUCKETS: usize = 10_000;
+
+const LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const REMAINING_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const RESET_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    /// How many requests can be made back to back.
+    pub burst: u32,
+    /// How fast the allowance comes back.
+    pub per_minute: u32,
+}
+
+impl Limit {
+    fn refill_per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+
+    fn seconds_to_refill(&self, tokens: f64) -> u64 {
+        (tokens / self.refill_per_second()).ceil() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Used by every rate limited route that doesn't have its own entry in `routes`.
+    pub default: Limit,
+    pub routes: BTreeMap<String, Limit>,
+    /// Reverse proxies whose X-Forwarded-For is believed. Empty by default, since anyone can send the header.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        // Writing reviews and uploading photos are the easy ones to spam, so they get much less room by default.
+        // Claiming a business sends a message to whoever's on record for it, so that gets even less.
+        let routes = BTreeMap::from([
+            ("add_review".to_string(), Limit { burst: 5, per_minute: 10 }),
+            ("add_photo".to_string(), Limit { burst: 10, per_minute: 20 }),
+            ("request_claim".to_string(), Limit { burst: 3, per_minute: 2 }),
+        ]);
+        RateLimitConfig {
+            enabled: true,
+            default: Limit { burst: 60, per_minute: 120 },
+            routes,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// The outcome of taking a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset_secs: u64,
+    /// Seconds until the next request would be allowed, when this one wasn't.
+    pub retry_after_secs: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Works out who a request is from, if it proves it.
+type Identify = Box<dyn Fn(&HttpRequest) -> Option<String> + Send + Sync>;
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    identify: Option<Identify>,
+    buckets: Mutex<HashMap<(&'static str, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter { config, identify: None, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts requests that `identify` recognizes against the identity it returns instead of their IP address. It
+    /// must only return an identity the request has proven, e.g. with a token it carries.
+    pub fn with_identity(mut self, identify: impl Fn(&HttpRequest) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identify = Some(Box::new(identify));
+        self
+    }
+
+    pub fn limit_for(&self, route: &str) -> Limit {
+        self.config.routes.get(route).copied().unwrap_or(self.config.default)
+    }
+
+    /// Takes a token from `client`'s bucket for `route`, if there is one.
+    pub fn check(&self, route: &'static str, client: &str, now: Instant) -> Decision {
+        let limit = self.limit_for(route);
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_BUCKETS {
+            self.evict(&mut buckets, now, MAX_BUCKETS - 1);
+        }
+
+        let bucket = buckets
+            .entry((route, client.to_string()))
+            .or_insert(Bucket { tokens: limit.burst as f64, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_second()).min(limit.burst as f64);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let tokens = bucket.tokens;
+        Decision {
+            allowed,
+            limit: limit.burst,
+            remaining: tokens.floor() as u32,
+            reset_secs: limit.seconds_to_refill(limit.burst as f64 - tokens),
+            retry_after_secs: if allowed { 0 } else { limit.seconds_to_refill(1.0 - tokens).max(1) },
+        }
+    }
+
+    /// The bucket key `request` counts against: its identity if it has one, its address otherwise.
+    pub fn client(&self, request: &HttpRequest) -> String {
+        if let Some(identity) = self.identify.as_ref().and_then(|identify| identify(request)) {
+            return format!("id:{identity}");
+        }
+        match self.client_ip(request) {
+            Some(address) => format!("ip:{address}"),
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// The address `request` counts against. That's the peer, unless the peer is a trusted proxy, in which case it's
+    /// the nearest address in X-Forwarded-For that isn't one. Entries further left than that were written by the
+    /// client, so they're never believed.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let mut client = request.peer_addr()?.ip();
+        let forwarded: Vec<&str> = request
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in forwarded.iter().rev() {
+            if !self.config.trusted_proxies.contains(&client) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(address) => client = address,
+                Err(_) => break,
+            }
+        }
+        Some(client)
+    }
+
+    /// Shrinks the map to at most `keep` buckets. Full buckets go first since dropping them changes nothing, then the
+    /// ones that have been used least recently.
+    fn evict(&self, buckets: &mut HashMap<(&'static str, String), Bucket>, now: Instant, keep: usize) {
+        buckets.retain(|(route, _), bucket| {
+            let limit = self.limit_for(route);
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * limit.refill_per_second() < limit.burst as f64
+        });
+        if buckets.len() <= keep {
+            return;
+        }
+        // Evicting a tenth at a time keeps this from running on every request once the map is full.
+        let keep = keep - keep / 10;
+        let mut last_used: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+        let evicted = buckets.len() - keep;
+        let (_, &mut cutoff, _) = last_used.select_nth_unstable(evicted - 1);
+        let mut to_evict = evicted;
+        buckets.retain(|_, bucket| {
+            if to_evict > 0 && bucket.updated <= cutoff {
+                to_evict -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+/// Middleware that rate limits one route. Does nothing unless an enabled `RateLimiter` was added to the app data.
+pub struct RateLimit {
+    route: &'static str,
+}
+
+impl RateLimit {
+    pub fn route(route: &'static str) -> Self {
+        RateLimit { route }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service, route: self.route }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    route: &'static str,
+}
+
+type ResponseFuture<B> = Pin<Box<dyn Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>>>;
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<B>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let limiter = match request.app_data::<web::Data<RateLimiter>>() {
+            Some(limiter) if limiter.config.enabled => limiter.clone(),
+            _ => {
+                let response = self.service.call(request);
+                return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+            }
+        };
+
+        let client = limiter.client(request.request());
+        let decision = limiter.check(self.route, &client, Instant::now());
+        if !decision.allowed {
+            tracing::warn!(route = self.route, client, "Rate limited");
+            let mut response = HttpResponse::TooManyRequests().json(json!({
+                "error": "Too many requests, slow down",
+                "retry_after_secs": decision.retry_after_secs
+            }));
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+            add_headers(response.headers_mut(), &decision);
+            return Box::pin(ready(Ok(request.into_response(response).map_into_right_body())));
+        }
+
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            add_headers(response.headers_mut(), &decision);
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+fn add_headers(headers: &mut actix_web::http::header::HeaderMap, decision: &Decision) {
+    headers.insert(LIMIT_HEADER, HeaderValue::from(decision.limit));
+    headers.insert(REMAINING_HEADER, HeaderValue::from(decision.remaining));
+    headers.insert(RESET_HEADER, HeaderValue::from(decision.reset_secs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_empty_and_refill() {
+        let mut config = RateLimitConfig::default();
+        config.routes.insert("add_review".into(), Limit { burst: 2, per_minute: 60 });
+        let limiter = RateLimiter::new(config);
+        let start = Instant::now();
+
+        assert!(limiter.check("add_review", "user:alice", start).allowed);
+        assert!(limiter.check("add_review", "user:alice", start).allowed);
+        let refused = limiter.check("add_review", "user:alice", start);
+        assert!(!refused.allowed);
+        assert_eq!(refused.retry_after_secs, 1);
+        // Someone else has their own bucket.
+        assert!(limiter.check("add_review", "user:bob", start).allowed);
+
+        assert!(limiter.check("add_review", "user:alice", start + Duration::from_secs(1)).allowed);
+    }
+
+    #[test]
+    fn forwarded_addresses_are_only_believed_from_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let config = RateLimitConfig { trusted_proxies: vec![proxy], ..RateLimitConfig::default() };
+        let limiter = RateLimiter::new(config);
+        let request = |peer: &str, forwarded: &str| {
+            actix_web::test::TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((X_FORWARDED_FOR, forwarded))
+                .to_http_request()
+        };
+        let client_ip = |peer: &str, forwarded: &str| limiter.client_ip(&request(peer, forwarded)).unwrap().to_string();
+
+        assert_eq!(client_ip("203.0.113.9:4000", "198.51.100.1"), "203.0.113.9");
+        assert_eq!(client_ip("10.0.0.1:4000", "198.51.100.1"), "198.51.100.1");
+        // Whatever the client put in front of the real address is ignored.
+        assert_eq!(client_ip("10.0.0.1:4000", "192.0.2.7, 198.51.100.1"), "198.51.100.1");
+        assert_eq!(client_ip("10.0.0.1:4000", "198.51.100.1, 10.0.0.1"), "198.51.100.1");
+        assert_eq!(client_ip("10.0.0.1:4000", "not an address"), "10.0.0.1");
+    }
+
+    #[test]
+    fn proven_identities_count_wherever_they_come_from() {
+        let limiter = RateLimiter::new(RateLimitConfig::default())
+            .with_identity(|request| request.headers().contains_key("x-test-admin").then(|| "admin".to_string()));
+        let request = |peer: &str, admin: bool| {
+            let mut request = actix_web::test::TestRequest::default().peer_addr(peer.parse().unwrap());
+            if admin {
+                request = request.insert_header(("x-test-admin", "yes"));
+            }
+            request.to_http_request()
+        };
+
+        assert_eq!(limiter.client(&request("203.0.113.9:4000", true)), "id:admin");
+        assert_eq!(limiter.client(&request("198.51.100.1:4000", true)), "id:admin");
+        assert_eq!(limiter.client(&request("203.0.113.9:4000", false)), "ip:203.0.113.9");
+    }
+
+    #[test]
+    fn the_least_recently_used_buckets_are_evicted() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let start = Instant::now();
+        for client in 0..MAX_BUCKETS {
+            limiter.check("add_review", &format!("ip:{client}"), start + Duration::from_millis(client as u64));
+        }
+        assert_eq!(limiter.buckets.lock().len(), MAX_BUCKETS);
+
+        let later = start + Duration::from_millis(MAX_BUCKETS as u64);
+        assert!(limiter.check("add_review", "ip:newcomer", later).allowed);
+        assert!(limiter.buckets.lock().len() < MAX_BUCKETS);
+        // The most recently used client kept its (partly spent) bucket, the oldest ones were dropped.
+        let buckets = limiter.buckets.lock();
+        assert!(buckets.contains_key(&("add_review", format!("ip:{}", MAX_BUCKETS - 1))));
+        assert!(!buckets.contains_key(&("add_review", "ip:0".to_string())));
+    }
+}
@@ -0,0 +1,416 @@
+// Full-text search over businesses and their reviews. An inverted index maps stemmed terms to the businesses
+// that contain them, and results are ranked with BM25. The index observes the store, so every write is searchable
+// as soon as it's made.
+//
+// Fields are weighted by how much a match in them says about the business: a term in the name counts for more than
+// the same term in a review. Each business is one document, with the weighted term counts of all its fields.
+use std::collections::{HashMap, HashSet};
+
+use parking_lot::RwLock;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+
+use crate::business::BusinessResponse;
+use crate::phone::PhoneNumber;
+use crate::store::StoreObserver;
+
+/// BM25 term frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document length normalization.
+const B: f64 = 0.75;
+
+/// Reviews longer than this many words are cut down to the part around the first match.
+const SNIPPET_WORDS: usize = 24;
+
+/// Words too common to be worth indexing. "near" is here so "pizza near Houston" searches for pizza and Houston.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it", "near", "of", "on", "or", "the",
+    "to", "was", "with",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Name,
+    Category,
+    Subcategory,
+    City,
+    Phone,
+    Review,
+}
+
+impl Field {
+    fn weight(self) -> f64 {
+        match self {
+            Field::Name | Field::Phone => 3.0,
+            Field::Category | Field::Subcategory | Field::City => 2.0,
+            Field::Review => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Highlight {
+    pub field: Field,
+    /// The field's text, HTML escaped, with the matching words wrapped in `<mark>`.
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub business_name: String,
+    pub score: f64,
+    pub highlights: Vec<Highlight>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    /// How many businesses matched, across every page.
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+struct Document {
+    fields: Vec<(Field, String)>,
+    /// Weighted count of every term in the document.
+    terms: HashMap<String, f64>,
+    length: f64,
+}
+
+#[derive(Default)]
+struct Index {
+    documents: HashMap<String, Document>,
+    /// Term -> business name -> weighted count.
+    postings: HashMap<String, HashMap<String, f64>>,
+    total_length: f64,
+}
+
+impl Index {
+    fn remove(&mut self, name: &str) {
+        let document = match self.documents.remove(name) {
+            Some(document) => document,
+            None => return,
+        };
+        self.total_length -= document.length;
+        for term in document.terms.keys() {
+            if let Some(posting) = self.postings.get_mut(term) {
+                posting.remove(name);
+                if posting.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, name: &str, document: Document) {
+        self.total_length += document.length;
+        for (term, count) in &document.terms {
+            self.postings.entry(term.clone()).or_default().insert(name.to_string(), *count);
+        }
+        self.documents.insert(name.to_string(), document);
+    }
+}
+
+pub struct SearchIndex {
+    index: RwLock<Index>,
+    stemmer: Stemmer,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        SearchIndex::new()
+    }
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        SearchIndex { index: RwLock::new(Index::default()), stemmer: Stemmer::create(Algorithm::English) }
+    }
+
+    /// The index term for a word, or `None` for stop words.
+    fn term(&self, word: &str) -> Option<String> {
+        let word = word.to_lowercase();
+        if STOP_WORDS.contains(&word.as_str()) {
+            return None;
+        }
+        Some(self.stemmer.stem(&word).into_owned())
+    }
+
+    fn terms(&self, text: &str) -> Vec<String> {
+        words(text).filter_map(|(start, end)| self.term(&text[start..end])).collect()
+    }
+
+    /// The terms to look for. A query that's a phone number in any format also looks for the number as it's
+    /// indexed, so "(713) 598-0157" finds a business stored as +17135980157.
+    fn query_terms(&self, query: &str) -> HashSet<String> {
+        let mut terms: HashSet<String> = self.terms(query).into_iter().collect();
+        if let Ok(phone) = PhoneNumber::parse(query) {
+            terms.extend(self.terms(&phone.e164()));
+        }
+        terms
+    }
+
+    /// The text of every field that's indexed, in the order highlights are shown.
+    fn fields(business: &BusinessResponse) -> Vec<(Field, String)> {
+        let details = &business.business;
+        let mut fields = vec![
+            (Field::Name, details.name.clone()),
+            (Field::Category, details.category.main_category.clone()),
+            (Field::Subcategory, details.category.subcategory.clone()),
+            (Field::City, details.city.clone()),
+            (Field::Phone, details.phone_num.e164()),
+        ];
+        if let Some(reviews) = &business.reviews {
+            fields.extend(reviews.iter().filter_map(|(_, review)| review.review.clone()).map(|text| (Field::Review, text)));
+        }
+        fields
+    }
+
+    fn document(&self, fields: Vec<(Field, String)>) -> Document {
+        let mut terms = HashMap::new();
+        let mut length = 0.0;
+        for (field, text) in &fields {
+            for term in self.terms(text) {
+                *terms.entry(term).or_insert(0.0) += field.weight();
+                length += field.weight();
+            }
+        }
+        Document { fields, terms, length }
+    }
+
+    /// Businesses matching any of the words in `query`, best first. `offset` and `limit` pick the page.
+    pub fn search(&self, query: &str, offset: usize, limit: usize) -> SearchResults {
+        self.search_where(query, offset, limit, |_| true)
+    }
+
+    /// Like `search`, but only the businesses `keep` accepts are counted and paged. `keep` is called with the
+    /// index unlocked, so it's free to look the business up in the store.
+    pub fn search_where(&self, query: &str, offset: usize, limit: usize, keep: impl Fn(&str) -> bool) -> SearchResults {
+        let query_terms = self.query_terms(query);
+        let ranked: Vec<(String, f64)> = self.rank(&query_terms).into_iter().filter(|(name, _)| keep(name)).collect();
+        let total = ranked.len();
+        let index = self.index.read();
+        // A business could have been removed while the index was unlocked, it just gets no highlights then.
+        let hits = ranked
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(name, score)| SearchHit {
+                highlights: index
+                    .documents
+                    .get(&name)
+                    .map(|document| self.highlights(document, &query_terms))
+                    .unwrap_or_default(),
+                business_name: name,
+                score,
+            })
+            .collect();
+        SearchResults { total, hits }
+    }
+
+    /// Every business with any of the terms and its BM25 score, best first.
+    fn rank(&self, query_terms: &HashSet<String>) -> Vec<(String, f64)> {
+        let index = self.index.read();
+        let document_count = index.documents.len() as f64;
+        let average_length = if index.documents.is_empty() { 1.0 } else { index.total_length / document_count };
+
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        for term in query_terms {
+            let posting = match index.postings.get(term) {
+                Some(posting) => posting,
+                None => continue,
+            };
+            let frequency = posting.len() as f64;
+            let idf = (1.0 + (document_count - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (name, count) in posting {
+                let length = index.documents[name].length;
+                let normalization = K1 * (1.0 - B + B * length / average_length);
+                *scores.entry(name.as_str()).or_insert(0.0) += idf * count * (K1 + 1.0) / (count + normalization);
+            }
+        }
+
+        let mut ranked: Vec<(String, f64)> = scores.into_iter().map(|(name, score)| (name.to_string(), score)).collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked
+    }
+
+    fn highlights(&self, document: &Document, query_terms: &HashSet<String>) -> Vec<Highlight> {
+        document
+            .fields
+            .iter()
+            .filter_map(|(field, text)| {
+                let spans: Vec<(usize, usize)> = words(text).collect();
+                let matches: Vec<bool> = spans
+                    .iter()
+                    .map(|&(start, end)| self.term(&text[start..end]).is_some_and(|term| query_terms.contains(&term)))
+                    .collect();
+                let first_match = matches.iter().position(|matched| *matched)?;
+                Some(Highlight { field: *field, snippet: snippet(text, &spans, &matches, first_match) })
+            })
+            .collect()
+    }
+}
+
+impl StoreObserver for SearchIndex {
+    fn business_changed(&self, name: &str, business: Option<&BusinessResponse>) {
+        // Most writes (photos, versions, owners) don't touch anything that's indexed, those are skipped.
+        let fields = business.map(SearchIndex::fields);
+        if self.index.read().documents.get(name).map(|document| &document.fields) == fields.as_ref() {
+            return;
+        }
+        // Build the document before taking the lock, searches only wait for the swap.
+        let document = fields.map(|fields| self.document(fields));
+        let mut index = self.index.write();
+        index.remove(name);
+        if let Some(document) = document {
+            index.insert(name, document);
+        }
+    }
+}
+
+/// Byte ranges of the words (runs of letters and digits) in `text`.
+fn words(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        while chars.next_if(|(_, c)| !c.is_alphanumeric()).is_some() {}
+        let (start, first) = chars.next()?;
+        let mut end = start + first.len_utf8();
+        while let Some((index, c)) = chars.next_if(|(_, c)| c.is_alphanumeric()) {
+            end = index + c.len_utf8();
+        }
+        Some((start, end))
+    })
+}
+
+/// The text around the first match, escaped, with every matching word marked.
+fn snippet(text: &str, spans: &[(usize, usize)], matches: &[bool], first_match: usize) -> String {
+    let (from, to) = if spans.len() <= SNIPPET_WORDS {
+        (0, spans.len())
+    } else {
+        let from = first_match.saturating_sub(SNIPPET_WORDS / 3);
+        (from, (from + SNIPPET_WORDS).min(spans.len()))
+    };
+
+    let mut snippet = String::new();
+    let mut position = if from == 0 { 0 } else { spans[from].0 };
+    if from > 0 {
+        snippet.push('…');
+    }
+    for index in from..to {
+        let (start, end) = spans[index];
+        snippet.push_str(&escape(&text[position..start]));
+        if matches[index] {
+            snippet.push_str("<mark>");
+            snippet.push_str(&escape(&text[start..end]));
+            snippet.push_str("</mark>");
+        } else {
+            snippet.push_str(&escape(&text[start..end]));
+        }
+        position = end;
+    }
+    if to == spans.len() {
+        snippet.push_str(&escape(&text[position..]));
+    } else {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::business::{Business, Category, Review, UserReviews};
+
+    fn business(name: &str, subcategory: &str, city: &str, review: Option<&str>) -> BusinessResponse {
+        let mut business = BusinessResponse::new(
+            Business {
+                name: name.into(),
+                street_addr: "1 Main St".into(),
+                city: city.into(),
+                state: "Texas".into(),
+                zip: "77002".parse().unwrap(),
+                phone_num: "713-555-0100".parse().unwrap(),
+                category: Category { main_category: "Restaurant".into(), subcategory: subcategory.into() },
+                category_ids: Vec::new(),
+                email: None,
+                website: None,
+                latitude: None,
+                longitude: None,
+                hours: None,
+                attributes: Default::default(),
+            },
+            Some(serde_json::from_value::<UserReviews>(serde_json::json!([])).unwrap()),
+            None,
+        );
+        if let Some(text) = review {
+            let review = Review { rating: 5, dollar_signs: 2, review: Some(text.into()), version: 0, owner_response: None };
+            business.add_business_review("alice".into(), review);
+        }
+        business
+    }
+
+    fn index(businesses: &[BusinessResponse]) -> SearchIndex {
+        let index = SearchIndex::new();
+        for business in businesses {
+            index.business_changed(&business.business.name, Some(business));
+        }
+        index
+    }
+
+    #[test]
+    fn stemmed_matches_rank_by_field_and_city() {
+        let index = index(&[
+            business("Luigi's", "Pizza", "Houston", None),
+            business("Burger Barn", "Burgers", "Houston", Some("They also sell pizzas")),
+            business("Slice", "Pizza", "Austin", None),
+        ]);
+        let results = index.search("pizza near Houston", 0, 10);
+        assert_eq!(results.total, 3);
+        assert_eq!(results.hits[0].business_name, "Luigi's");
+
+        let review = results.hits.iter().find(|hit| hit.business_name == "Burger Barn").unwrap();
+        let snippet = &review.highlights.iter().find(|highlight| highlight.field == Field::Review).unwrap().snippet;
+        assert_eq!(snippet, "They also sell <mark>pizzas</mark>");
+    }
+
+    #[test]
+    fn phone_numbers_match_in_any_format() {
+        let index = index(&[business("Luigi's", "Pizza", "Houston", None)]);
+        let results = index.search("(713) 555-0100", 0, 10);
+        assert_eq!(results.total, 1);
+        let phone = results.hits[0].highlights.iter().find(|highlight| highlight.field == Field::Phone).unwrap();
+        assert_eq!(phone.snippet, "+<mark>17135550100</mark>");
+    }
+
+    #[test]
+    fn removed_businesses_stop_matching() {
+        let index = index(&[business("Luigi's", "Pizza", "Houston", None)]);
+        index.business_changed("Luigi's", None);
+        assert_eq!(index.search("pizza", 0, 10).total, 0);
+    }
+
+    #[test]
+    fn snippets_are_escaped_and_trimmed() {
+        let text = format!("{} <b>pizza</b> {}", "word ".repeat(30), "more ".repeat(30));
+        let index = index(&[business("Luigi's", "Pizza", "Houston", Some(&text))]);
+        let results = index.search("pizza", 0, 10);
+        let review = results.hits[0].highlights.iter().find(|highlight| highlight.field == Field::Review).unwrap();
+        assert!(review.snippet.starts_with('…') && review.snippet.ends_with('…'));
+        assert!(review.snippet.contains("&lt;b&gt;<mark>pizza</mark>&lt;/b&gt;"));
+    }
+}
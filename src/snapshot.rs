@@ -0,0 +1,336 @@
+// Loading and saving the store as JSON. Used for the seed file and for the snapshot storage backend.
+//
+// Snapshots carry a format version. Older files (including a bare array of businesses like `MOCK_DATA.json`,
+// which counts as version 0) are migrated step by step to the current format when they're read.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::business::BusinessResponse;
+use crate::claims::{AuditEntry, Claims, OwnerToken};
+use crate::duplicates::Redirects;
+use crate::phone::PhoneNumber;
+use crate::store::Store;
+use crate::taxonomy::{CategoryRecord, Taxonomy, TaxonomyError};
+
+/// Bumped whenever the snapshot layout changes, together with a new entry in `MIGRATIONS`.
+pub const FORMAT_VERSION: u64 = 7;
+
+/// `MIGRATIONS[n]` turns a version `n` document into a version `n + 1` one.
+const MIGRATIONS: &[fn(Value) -> Value] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+];
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("could not access {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{path} is not a valid list of businesses: {source}")]
+    Parse { path: PathBuf, source: serde_json::Error },
+    #[error("{path} has snapshot format version {found}, this build only understands up to {FORMAT_VERSION}")]
+    UnsupportedVersion { path: PathBuf, found: u64 },
+    #[error("{path} has an invalid category tree: {source}")]
+    Categories { path: PathBuf, source: TaxonomyError },
+}
+
+#[derive(Serialize)]
+struct SnapshotFile<'a> {
+    format_version: u64,
+    categories: &'a [CategoryRecord],
+    /// Names of merged businesses and the business each was merged into.
+    redirects: &'a BTreeMap<String, String>,
+    /// Everything that happened to business claims.
+    claim_audit: &'a [AuditEntry],
+    /// The token each business' owner acts on it with, by business.
+    owner_tokens: &'a BTreeMap<String, OwnerToken>,
+    businesses: &'a [BusinessResponse],
+}
+
+/// What was read from a snapshot or seed file.
+pub struct Loaded {
+    pub businesses: Vec<BusinessResponse>,
+    /// The category tree, if the file has one. Files from before the tree, and seed files, usually don't.
+    pub categories: Option<Vec<CategoryRecord>>,
+    pub redirects: BTreeMap<String, String>,
+    pub claim_audit: Vec<AuditEntry>,
+    pub owner_tokens: BTreeMap<String, OwnerToken>,
+    /// The format version the file was in, before migrating.
+    pub format_version: u64,
+}
+
+/// What loading a file into the store did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadReport {
+    pub inserted: usize,
+    pub format_version: u64,
+}
+
+/// Reads businesses in any known snapshot format, migrating them to the current one.
+pub fn read_businesses(path: &Path) -> Result<Loaded, SnapshotError> {
+    let parse_error = |source| SnapshotError::Parse { path: path.to_path_buf(), source };
+    let contents = fs::read(path).map_err(|source| SnapshotError::Io { path: path.to_path_buf(), source })?;
+    let mut document: Value = serde_json::from_slice(&contents).map_err(parse_error)?;
+
+    let format_version = match &document {
+        Value::Array(_) => 0,
+        _ => document["format_version"].as_u64().unwrap_or(0),
+    };
+    if format_version > FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion { path: path.to_path_buf(), found: format_version });
+    }
+    for migration in &MIGRATIONS[format_version as usize..] {
+        document = migration(document);
+    }
+
+    let businesses = serde_json::from_value(document["businesses"].take()).map_err(parse_error)?;
+    let categories = serde_json::from_value(document["categories"].take()).map_err(parse_error)?;
+    let redirects = serde_json::from_value(document["redirects"].take()).map_err(parse_error)?;
+    let claim_audit = serde_json::from_value(document["claim_audit"].take()).map_err(parse_error)?;
+    let owner_tokens = serde_json::from_value(document["owner_tokens"].take()).map_err(parse_error)?;
+    Ok(Loaded { businesses, categories, redirects, claim_audit, owner_tokens, format_version })
+}
+
+/// Loads a snapshot into the store, its redirects into `redirects`, its claim audit trail and owner tokens into
+/// `claims`, and its category tree into `taxonomy` when it has one. A missing snapshot just means this is the first
+/// start, so it's not an error.
+pub fn load(
+    path: &Path,
+    store: &Store,
+    taxonomy: &Taxonomy,
+    redirects: &Redirects,
+    claims: &Claims,
+) -> Result<LoadReport, SnapshotError> {
+    if !path.exists() {
+        return Ok(LoadReport { inserted: 0, format_version: FORMAT_VERSION });
+    }
+    let loaded = read_businesses(path)?;
+    if let Some(categories) = loaded.categories {
+        taxonomy
+            .replace(categories)
+            .map_err(|source| SnapshotError::Categories { path: path.to_path_buf(), source })?;
+    }
+    redirects.replace(loaded.redirects);
+    claims.replace_audit(loaded.claim_audit);
+    claims.replace_owner_tokens(loaded.owner_tokens);
+    let inserted = insert_all(store, taxonomy, loaded.businesses);
+    Ok(LoadReport { inserted, format_version: loaded.format_version })
+}
+
+/// Loads a seed file into the store. Businesses that already exist (e.g. from a snapshot) are left alone.
+/// The category tree is never taken from a seed file, its businesses are linked to the tree that's already there.
+pub fn seed(path: &Path, store: &Store, taxonomy: &Taxonomy) -> Result<LoadReport, SnapshotError> {
+    let loaded = read_businesses(path)?;
+    let inserted = insert_all(store, taxonomy, loaded.businesses);
+    Ok(LoadReport { inserted, format_version: loaded.format_version })
+}
+
+/// Writes the whole store to `path` and makes sure it's on disk before returning.
+/// The snapshot is written next to the old one and renamed over it, so a crash halfway through never leaves a torn file.
+/// Returns how many businesses were written.
+pub fn save(
+    path: &Path,
+    store: &Store,
+    taxonomy: &Taxonomy,
+    redirects: &Redirects,
+    claims: &Claims,
+) -> Result<usize, SnapshotError> {
+    let io_error = |source| SnapshotError::Io { path: path.to_path_buf(), source };
+    let businesses = store.all();
+    let categories = taxonomy.records();
+    let redirects = redirects.all();
+    let claim_audit = claims.audit();
+    let owner_tokens = claims.owner_tokens();
+    let snapshot = SnapshotFile {
+        format_version: FORMAT_VERSION,
+        categories: &categories,
+        redirects: &redirects,
+        claim_audit: &claim_audit,
+        owner_tokens: &owner_tokens,
+        businesses: &businesses,
+    };
+    let contents = serde_json::to_vec(&snapshot).map_err(|error| io_error(error.into()))?;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let mut file = File::create(&temp_path).map_err(io_error)?;
+    file.write_all(&contents).map_err(io_error)?;
+    file.sync_all().map_err(io_error)?;
+    fs::rename(&temp_path, path).map_err(io_error)?;
+
+    // The rename itself only survives a crash once the directory entry is synced too.
+    #[cfg(unix)]
+    {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory).and_then(|directory| directory.sync_all()).map_err(io_error)?;
+    }
+    Ok(businesses.len())
+}
+
+fn insert_all(store: &Store, taxonomy: &Taxonomy, businesses: Vec<BusinessResponse>) -> usize {
+    businesses
+        .into_iter()
+        .map(|mut business| {
+            taxonomy.link(&mut business.business);
+            store.insert_new(business.business.name.clone(), business)
+        })
+        .filter(|inserted| *inserted)
+        .count()
+}
+
+/// Version 0 was a bare array of businesses, from before photo IDs were allocated by the server.
+/// Wraps it in a versioned document and makes sure new photo IDs can't collide with the ones already there.
+fn migrate_v0_to_v1(document: Value) -> Value {
+    let mut businesses = match document {
+        Value::Array(businesses) => businesses,
+        mut document => match document["businesses"].take() {
+            Value::Array(businesses) => businesses,
+            _ => Vec::new(),
+        },
+    };
+    for business in &mut businesses {
+        if business.get("next_photo_id").is_some() {
+            continue;
+        }
+        let next_photo_id = business["photos"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|photo| photo["photo_id"].as_u64())
+            .map(|photo_id| photo_id + 1)
+            .max()
+            .unwrap_or(0);
+        if let Some(business) = business.as_object_mut() {
+            business.insert("next_photo_id".into(), json!(next_photo_id));
+        }
+    }
+    json!({ "format_version": 1, "businesses": businesses })
+}
+
+/// Version 1 stored phone numbers as JSON numbers. They're strings in E.164 from version 2 on.
+fn migrate_v1_to_v2(mut document: Value) -> Value {
+    if let Some(businesses) = document["businesses"].as_array_mut() {
+        for business in businesses {
+            let phone_num = &mut business["business"]["phone_num"];
+            if let Some(number) = phone_num.as_u64() {
+                // Anything that doesn't parse is left as it was, so loading fails on it with a useful error.
+                if let Ok(phone) = PhoneNumber::parse(&number.to_string()) {
+                    *phone_num = json!(phone);
+                }
+            }
+        }
+    }
+    document["format_version"] = json!(2);
+    document
+}
+
+/// Version 2 stored ZIP codes as JSON numbers, which dropped leading zeros. They're strings from version 3 on.
+fn migrate_v2_to_v3(mut document: Value) -> Value {
+    if let Some(businesses) = document["businesses"].as_array_mut() {
+        for business in businesses {
+            let zip = &mut business["business"]["zip"];
+            if let Some(number) = zip.as_u64().filter(|number| *number <= 99_999) {
+                *zip = json!(format!("{number:05}"));
+            }
+        }
+    }
+    document["format_version"] = json!(3);
+    document
+}
+
+/// Version 4 added the category tree. Older snapshots start from the bundled one.
+fn migrate_v3_to_v4(mut document: Value) -> Value {
+    document["categories"] = Value::Null;
+    document["format_version"] = json!(4);
+    document
+}
+
+/// Version 5 added redirects from merged businesses. There weren't any merges before.
+fn migrate_v4_to_v5(mut document: Value) -> Value {
+    document["redirects"] = json!({});
+    document["format_version"] = json!(5);
+    document
+}
+
+/// Version 6 added the audit trail of business claims, which started out empty.
+fn migrate_v5_to_v6(mut document: Value) -> Value {
+    document["claim_audit"] = json!([]);
+    document["format_version"] = json!(6);
+    document
+}
+
+/// Version 7 added owner tokens. Owners from before have none, so only an admin can act for them until an admin makes
+/// them the owner again, which hands them one.
+fn migrate_v6_to_v7(mut document: Value) -> Value {
+    document["owner_tokens"] = json!({});
+    document["format_version"] = json!(7);
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Claims {
+        Claims::new(Box::new(crate::claims::LogNotifier))
+    }
+
+    #[test]
+    fn bare_arrays_are_migrated() {
+        let photo = json!({ "user_name": "alice", "photo_id": 4, "photo_url": "https://example.com/4.png", "photo_caption": null });
+        let migrated = migrate_v0_to_v1(json!([{ "business": {}, "photos": [photo] }, { "business": {}, "photos": null }]));
+        assert_eq!(migrated["format_version"], 1);
+        assert_eq!(migrated["businesses"][0]["next_photo_id"], 5);
+        assert_eq!(migrated["businesses"][1]["next_photo_id"], 0);
+    }
+
+    #[test]
+    fn numeric_phone_numbers_become_e164_strings() {
+        let migrated = migrate_v1_to_v2(json!({ "format_version": 1, "businesses": [{ "business": { "phone_num": 7135980157u64 } }] }));
+        assert_eq!(migrated["format_version"], 2);
+        assert_eq!(migrated["businesses"][0]["business"]["phone_num"], "+17135980157");
+    }
+
+    #[test]
+    fn numeric_zip_codes_get_their_leading_zeros_back() {
+        let migrated = migrate_v2_to_v3(json!({ "format_version": 2, "businesses": [{ "business": { "zip": 2108 } }] }));
+        assert_eq!(migrated["format_version"], 3);
+        assert_eq!(migrated["businesses"][0]["business"]["zip"], "02108");
+    }
+
+    #[test]
+    fn the_category_tree_redirects_and_owner_tokens_are_saved_with_the_businesses() {
+        let path = std::env::temp_dir().join(format!("belp-snapshot-categories-{}.json", std::process::id()));
+        let taxonomy = Taxonomy::with_defaults();
+        let added = taxonomy.create(crate::taxonomy::NewCategory { name: "Food Trucks".into(), parent_id: None }).unwrap();
+        let redirects = Redirects::new();
+        redirects.add("Joes Pizza", "Joe's Pizza");
+        let claims = claims();
+        let owner_token = claims.issue_owner_token("Joe's Pizza", "joe");
+        save(&path, &Store::with_shards(1), &taxonomy, &redirects, &claims).unwrap();
+
+        let restored = Taxonomy::with_defaults();
+        assert!(restored.get(added.id).is_none());
+        let restored_redirects = Redirects::new();
+        let restored_claims = self::claims();
+        load(&path, &Store::with_shards(1), &restored, &restored_redirects, &restored_claims).unwrap();
+        assert_eq!(restored.records(), taxonomy.records());
+        assert_eq!(restored_redirects.all(), redirects.all());
+        assert_eq!(restored_claims.owner_token("Joe's Pizza", "joe"), Some(owner_token));
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,319 @@
+// The business store. Instead of one lock over every business, the map is split into shards that each have
+// their own lock, so writes to businesses in different shards don't wait on each other.
+// Locks are never held across an await, everything that touches a business runs in a closure under the shard lock.
+// Every lock goes through `read_shard` / `write_shard`, which report how long it took to get to the metrics.
+// Indexes over the store (search, ...) register as observers and hear about every write once the shard lock is released,
+// so a slow index never holds up the store. Deliveries for a shard go one at a time and always carry the business as
+// it is when they're made, so an index that skips an intermediate state still ends up with the latest one.
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::business::BusinessResponse;
+use crate::metrics::{self, LockMode};
+
+pub const DEFAULT_SHARDS: usize = 64;
+
+type Shard = RwLock<HashMap<String, BusinessResponse>>;
+
+/// How much is in the store, for the metrics gauges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreCounts {
+    pub businesses: usize,
+    pub reviews: usize,
+    pub photos: usize,
+}
+
+/// Something that keeps derived data in step with the store.
+pub trait StoreObserver: Send + Sync {
+    /// Called after every write with the current state of the business, or `None` if it's gone. No store lock is held,
+    /// but calls for businesses in the same shard are serialized, so it must not write to the store.
+    fn business_changed(&self, name: &str, business: Option<&BusinessResponse>);
+}
+
+pub struct Store {
+    shards: Box<[Shard]>,
+    /// One per shard, held while its changes are delivered to the observers so they arrive in order.
+    deliveries: Box<[Mutex<()>]>,
+    hasher: RandomState,
+    observers: Vec<Arc<dyn StoreObserver>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    pub fn with_shards(shard_count: usize) -> Self {
+        let shards = (0..shard_count.max(1)).map(|_| RwLock::new(HashMap::new())).collect();
+        let deliveries = (0..shard_count.max(1)).map(|_| Mutex::new(())).collect();
+        Store { shards, deliveries, hasher: RandomState::new(), observers: Vec::new() }
+    }
+
+    /// Registers an observer. Only businesses written after this are reported, so add observers before loading data.
+    pub fn add_observer(&mut self, observer: Arc<dyn StoreObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Tells the observers about a write to `name`. Must be called after the shard lock is released.
+    fn notify(&self, name: &str) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let _delivering = self.deliveries[self.shard_index(name)].lock();
+        // Read under the delivery lock, so a later write to the same business is never overtaken by this one.
+        let business = self.read(name, BusinessResponse::clone);
+        for observer in &self.observers {
+            observer.business_changed(name, business.as_ref());
+        }
+    }
+
+    fn shard_index(&self, name: &str) -> usize {
+        (self.hasher.hash_one(name) as usize) % self.shards.len()
+    }
+
+    fn shard(&self, name: &str) -> &Shard {
+        &self.shards[self.shard_index(name)]
+    }
+
+    fn read_shard(shard: &Shard) -> RwLockReadGuard<'_, HashMap<String, BusinessResponse>> {
+        let started = Instant::now();
+        let guard = shard.read();
+        metrics::observe_lock_wait(LockMode::Read, started.elapsed());
+        guard
+    }
+
+    fn write_shard(shard: &Shard) -> RwLockWriteGuard<'_, HashMap<String, BusinessResponse>> {
+        let started = Instant::now();
+        let guard = shard.write();
+        metrics::observe_lock_wait(LockMode::Write, started.elapsed());
+        guard
+    }
+
+    /// Runs `f` against a business under a shared lock. Returns `None` if there's no business with that name.
+    pub fn read<R>(&self, name: &str, f: impl FnOnce(&BusinessResponse) -> R) -> Option<R> {
+        Self::read_shard(self.shard(name)).get(name).map(f)
+    }
+
+    /// Runs `f` against a business under an exclusive lock. Returns `None` if there's no business with that name.
+    pub fn update<R>(&self, name: &str, f: impl FnOnce(&mut BusinessResponse) -> R) -> Option<R> {
+        let result = f(Self::write_shard(self.shard(name)).get_mut(name)?);
+        self.notify(name);
+        Some(result)
+    }
+
+    /// Gives `f` the slot for `name` whether or not it's taken, so it can check and then insert, replace or remove in one step.
+    pub fn entry<R>(&self, name: &str, f: impl FnOnce(&mut Option<BusinessResponse>) -> R) -> R {
+        let mut shard = Self::write_shard(self.shard(name));
+        let mut slot = shard.remove(name);
+        let existed = slot.is_some();
+        let result = f(&mut slot);
+        let changed = existed || slot.is_some();
+        put_back(&mut shard, name, slot);
+        drop(shard);
+        if changed {
+            self.notify(name);
+        }
+        result
+    }
+
+    /// Like `entry`, but for two different names at once (e.g. renaming a business). Both shards stay locked while `f` runs.
+    pub fn entry_pair<R>(
+        &self,
+        first: &str,
+        second: &str,
+        f: impl FnOnce(&mut Option<BusinessResponse>, &mut Option<BusinessResponse>) -> R,
+    ) -> R {
+        let (first_index, second_index) = (self.shard_index(first), self.shard_index(second));
+        if first_index == second_index {
+            let mut shard = Self::write_shard(&self.shards[first_index]);
+            let mut first_slot = shard.remove(first);
+            let mut second_slot = shard.remove(second);
+            let result = f(&mut first_slot, &mut second_slot);
+            put_back(&mut shard, first, first_slot);
+            put_back(&mut shard, second, second_slot);
+            drop(shard);
+            self.notify(first);
+            self.notify(second);
+            return result;
+        }
+
+        // Always lock the lower shard first so two renames in opposite directions can't deadlock.
+        let (low, high) = (first_index.min(second_index), first_index.max(second_index));
+        let mut low_shard = Self::write_shard(&self.shards[low]);
+        let mut high_shard = Self::write_shard(&self.shards[high]);
+        let (first_shard, second_shard) = if first_index == low {
+            (&mut *low_shard, &mut *high_shard)
+        } else {
+            (&mut *high_shard, &mut *low_shard)
+        };
+        let mut first_slot = first_shard.remove(first);
+        let mut second_slot = second_shard.remove(second);
+        let result = f(&mut first_slot, &mut second_slot);
+        put_back(first_shard, first, first_slot);
+        put_back(second_shard, second, second_slot);
+        drop((low_shard, high_shard));
+        self.notify(first);
+        self.notify(second);
+        result
+    }
+
+    /// Inserts a business unless the name is already taken. Returns whether it was inserted.
+    pub fn insert_new(&self, name: String, business: BusinessResponse) -> bool {
+        let mut shard = Self::write_shard(self.shard(&name));
+        if shard.contains_key(&name) {
+            return false;
+        }
+        shard.insert(name.clone(), business);
+        drop(shard);
+        self.notify(&name);
+        true
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        Self::read_shard(self.shard(name)).contains_key(name)
+    }
+
+    /// A copy of every business. Shards are locked one at a time, so this never blocks the whole store.
+    pub fn all(&self) -> Vec<BusinessResponse> {
+        self.shards
+            .iter()
+            .flat_map(|shard| Self::read_shard(shard).values().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| Self::read_shard(shard).len()).sum()
+    }
+
+    /// Counts businesses, reviews and photos without copying anything out of the store.
+    pub fn counts(&self) -> StoreCounts {
+        let mut counts = StoreCounts::default();
+        for shard in self.shards.iter() {
+            for business in Self::read_shard(shard).values() {
+                counts.businesses += 1;
+                counts.reviews += business.reviews.as_ref().map_or(0, |reviews| reviews.len());
+                counts.photos += business.photos.as_ref().map_or(0, |photos| photos.len());
+            }
+        }
+        counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether every shard can be read within `timeout`. A shard that stays write-locked longer than that is stuck.
+    pub fn is_reachable(&self, timeout: Duration) -> bool {
+        self.shards.iter().all(|shard| shard.try_read_for(timeout).is_some())
+    }
+}
+
+fn put_back(shard: &mut HashMap<String, BusinessResponse>, name: &str, slot: Option<BusinessResponse>) {
+    if let Some(business) = slot {
+        shard.insert(name.to_string(), business);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::business::{Business, Category};
+
+    fn business(name: &str) -> BusinessResponse {
+        BusinessResponse::new(
+            Business {
+                name: name.into(),
+                street_addr: "1 Main St".into(),
+                city: "Corvallis".into(),
+                state: "Oregon".into(),
+                zip: "97331".parse().unwrap(),
+                phone_num: "541-555-0100".parse().unwrap(),
+                category: Category {
+                    main_category: "Restaurant".into(),
+                    subcategory: "Diner".into(),
+                },
+                category_ids: Vec::new(),
+                email: None,
+                website: None,
+                latitude: None,
+                longitude: None,
+                hours: None,
+                attributes: Default::default(),
+            },
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn entry_pair_moves_a_business_between_shards() {
+        // Enough names that both the same-shard and different-shard paths get exercised.
+        let store = Store::with_shards(4);
+        for index in 0..16 {
+            let from = format!("business {index}");
+            let to = format!("renamed {index}");
+            assert!(store.insert_new(from.clone(), business(&from)));
+            store.entry_pair(&from, &to, |from_slot, to_slot| *to_slot = from_slot.take());
+            assert!(!store.contains(&from));
+            assert!(store.contains(&to));
+        }
+        assert_eq!(store.len(), 16);
+    }
+
+    /// Reads the store from inside the callback, which would deadlock if the shard were still locked.
+    #[derive(Default)]
+    struct ReadingObserver {
+        store: std::sync::OnceLock<Arc<Store>>,
+        seen: Mutex<Vec<(String, Option<u64>)>>,
+    }
+
+    impl StoreObserver for ReadingObserver {
+        fn business_changed(&self, name: &str, business: Option<&BusinessResponse>) {
+            let stored = self.store.get().unwrap().read(name, |business| business.version);
+            assert_eq!(stored, business.map(|business| business.version));
+            self.seen.lock().push((name.to_string(), stored));
+        }
+    }
+
+    #[test]
+    fn observers_hear_about_writes_after_the_shard_is_unlocked() {
+        let observer = Arc::new(ReadingObserver::default());
+        let mut store = Store::with_shards(2);
+        store.add_observer(observer.clone());
+        let store = Arc::new(store);
+        observer.store.set(store.clone()).ok();
+
+        store.insert_new("Diner".into(), business("Diner"));
+        store.update("Diner", BusinessResponse::touch);
+        store.update("Nowhere", BusinessResponse::touch);
+        store.entry_pair("Diner", "Cafe", |from, to| *to = from.take());
+        store.entry("Cafe", |slot| slot.take());
+        let seen = observer.seen.lock().clone();
+        assert_eq!(seen, [
+            ("Diner".to_string(), Some(0)),
+            ("Diner".to_string(), Some(1)),
+            ("Diner".to_string(), None),
+            ("Cafe".to_string(), Some(1)),
+            ("Cafe".to_string(), None),
+        ]);
+    }
+
+    #[test]
+    fn insert_new_refuses_taken_names() {
+        let store = Store::new();
+        assert!(store.insert_new("Diner".into(), business("Diner")));
+        assert!(!store.insert_new("Diner".into(), business("Diner")));
+        assert_eq!(store.all().len(), 1);
+    }
+}
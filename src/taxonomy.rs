@@ -0,0 +1,389 @@
+// The category tree. Every category has a stable numeric ID and at most one parent, and businesses refer to
+// categories by ID, so renaming or moving a category never touches the businesses in it.
+//
+// A fresh server starts from the bundled tree in data/categories.json, after which admins manage it through the
+// API. The tree is saved in the snapshot next to the businesses.
+//
+// Businesses still carry the free text `category` pair the API started with. When one comes in without any
+// category IDs, the pair is matched against the tree by name to fill them in.
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::business::Business;
+
+pub type CategoryId = u64;
+
+const DEFAULT_TREE: &str = include_str!("../data/categories.json");
+
+/// One category, the way it's stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CategoryRecord {
+    pub id: CategoryId,
+    pub name: String,
+    /// `None` for top level categories.
+    pub parent_id: Option<CategoryId>,
+}
+
+/// A category to create, or the new name and place of an existing one.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewCategory {
+    pub name: String,
+    pub parent_id: Option<CategoryId>,
+}
+
+/// A category with everything under it.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct CategoryNode {
+    pub id: CategoryId,
+    pub name: String,
+    pub parent_id: Option<CategoryId>,
+    /// Names from the top level category down to this one.
+    pub path: Vec<String>,
+    #[schema(no_recursion)]
+    pub children: Vec<CategoryNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TaxonomyError {
+    #[error("there's no category with ID {0}")]
+    NotFound(CategoryId),
+    #[error("category names can't be empty")]
+    EmptyName,
+    #[error("there's already a category called `{0}` in the same place")]
+    DuplicateName(String),
+    #[error("category {0} can't be moved under itself or one of its own subcategories")]
+    Cycle(CategoryId),
+    #[error("category {0} still has subcategories")]
+    HasChildren(CategoryId),
+    #[error("category {0} appears more than once")]
+    DuplicateId(CategoryId),
+}
+
+/// The bundled tree's file format: names with nested children, IDs are handed out in file order.
+#[derive(Deserialize)]
+struct DefaultCategory {
+    name: String,
+    #[serde(default)]
+    children: Vec<DefaultCategory>,
+}
+
+#[derive(Default)]
+struct Tree {
+    categories: BTreeMap<CategoryId, CategoryRecord>,
+    next_id: CategoryId,
+}
+
+impl Tree {
+    fn children(&self, parent_id: Option<CategoryId>) -> impl Iterator<Item = &CategoryRecord> {
+        self.categories.values().filter(move |category| category.parent_id == parent_id)
+    }
+
+    fn path(&self, id: CategoryId) -> Vec<String> {
+        let mut path = Vec::new();
+        let mut current = self.categories.get(&id);
+        while let Some(category) = current {
+            path.push(category.name.clone());
+            current = category.parent_id.and_then(|parent_id| self.categories.get(&parent_id));
+        }
+        path.reverse();
+        path
+    }
+
+    fn node(&self, category: &CategoryRecord) -> CategoryNode {
+        CategoryNode {
+            id: category.id,
+            name: category.name.clone(),
+            parent_id: category.parent_id,
+            path: self.path(category.id),
+            children: self.children(Some(category.id)).map(|child| self.node(child)).collect(),
+        }
+    }
+
+    /// Whether `ancestor` is `id` or above it. Stops at a loop that doesn't go through `ancestor`, which only a
+    /// tree that hasn't been checked yet (see `Taxonomy::replace`) can have.
+    fn is_descendant(&self, id: CategoryId, ancestor: CategoryId) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            if !seen.insert(id) {
+                return false;
+            }
+            current = self.categories.get(&id).and_then(|category| category.parent_id);
+        }
+        false
+    }
+
+    /// Checks a name and parent for category `id` (`None` for a new one).
+    fn check(&self, id: Option<CategoryId>, name: &str, parent_id: Option<CategoryId>) -> Result<(), TaxonomyError> {
+        if name.is_empty() {
+            return Err(TaxonomyError::EmptyName);
+        }
+        if let Some(parent_id) = parent_id {
+            if !self.categories.contains_key(&parent_id) {
+                return Err(TaxonomyError::NotFound(parent_id));
+            }
+            if let Some(id) = id.filter(|id| self.is_descendant(parent_id, *id)) {
+                return Err(TaxonomyError::Cycle(id));
+            }
+        }
+        let taken = self
+            .children(parent_id)
+            .any(|sibling| Some(sibling.id) != id && sibling.name.eq_ignore_ascii_case(name));
+        if taken {
+            return Err(TaxonomyError::DuplicateName(name.to_string()));
+        }
+        Ok(())
+    }
+
+    fn add_defaults(&mut self, categories: Vec<DefaultCategory>, parent_id: Option<CategoryId>) {
+        for category in categories {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.categories.insert(id, CategoryRecord { id, name: category.name, parent_id });
+            self.add_defaults(category.children, Some(id));
+        }
+    }
+
+    fn find_by_name(&self, name: &str) -> Option<&CategoryRecord> {
+        let name = name.trim();
+        self.categories.values().find(|category| category.name.eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct Taxonomy {
+    tree: RwLock<Tree>,
+    /// Shared by writes that put businesses in categories, exclusive while a category is deleted. See `linking`.
+    usage: RwLock<()>,
+}
+
+impl Default for Taxonomy {
+    fn default() -> Self {
+        Taxonomy::with_defaults()
+    }
+}
+
+impl Taxonomy {
+    /// The bundled starting tree.
+    pub fn with_defaults() -> Self {
+        let defaults: Vec<DefaultCategory> = serde_json::from_str(DEFAULT_TREE).expect("data/categories.json is valid");
+        let mut tree = Tree { categories: BTreeMap::new(), next_id: 1 };
+        tree.add_defaults(defaults, None);
+        Taxonomy { tree: RwLock::new(tree), usage: RwLock::new(()) }
+    }
+
+    /// Replaces the whole tree, e.g. with the one from a snapshot.
+    pub fn replace(&self, records: Vec<CategoryRecord>) -> Result<(), TaxonomyError> {
+        let mut tree = Tree::default();
+        for record in &records {
+            if tree.categories.insert(record.id, record.clone()).is_some() {
+                return Err(TaxonomyError::DuplicateId(record.id));
+            }
+        }
+        for record in &records {
+            if let Some(parent_id) = record.parent_id {
+                if !tree.categories.contains_key(&parent_id) {
+                    return Err(TaxonomyError::NotFound(parent_id));
+                }
+                // Every category in a loop gets here, so a loop is caught at one of them even when it's not above the
+                // others.
+                if tree.is_descendant(parent_id, record.id) {
+                    return Err(TaxonomyError::Cycle(record.id));
+                }
+            }
+        }
+        tree.next_id = records.iter().map(|record| record.id + 1).max().unwrap_or(1);
+        *self.tree.write() = tree;
+        Ok(())
+    }
+
+    /// Every category, flat, for saving.
+    pub fn records(&self) -> Vec<CategoryRecord> {
+        self.tree.read().categories.values().cloned().collect()
+    }
+
+    /// The top level categories with everything under them.
+    pub fn roots(&self) -> Vec<CategoryNode> {
+        let tree = self.tree.read();
+        tree.children(None).map(|category| tree.node(category)).collect()
+    }
+
+    pub fn get(&self, id: CategoryId) -> Option<CategoryNode> {
+        let tree = self.tree.read();
+        tree.categories.get(&id).map(|category| tree.node(category))
+    }
+
+    pub fn create(&self, new: NewCategory) -> Result<CategoryRecord, TaxonomyError> {
+        let mut tree = self.tree.write();
+        let name = new.name.trim().to_string();
+        tree.check(None, &name, new.parent_id)?;
+        let id = tree.next_id;
+        tree.next_id += 1;
+        let record = CategoryRecord { id, name, parent_id: new.parent_id };
+        tree.categories.insert(id, record.clone());
+        Ok(record)
+    }
+
+    /// Renames and/or moves a category. Its subcategories move with it.
+    pub fn update(&self, id: CategoryId, update: NewCategory) -> Result<CategoryRecord, TaxonomyError> {
+        let mut tree = self.tree.write();
+        if !tree.categories.contains_key(&id) {
+            return Err(TaxonomyError::NotFound(id));
+        }
+        let name = update.name.trim().to_string();
+        tree.check(Some(id), &name, update.parent_id)?;
+        let record = CategoryRecord { id, name, parent_id: update.parent_id };
+        tree.categories.insert(id, record.clone());
+        Ok(record)
+    }
+
+    /// Held from linking and validating a business until it's stored, so no business can start using a category
+    /// while it's being deleted. Take it before any store lock.
+    pub fn linking(&self) -> RwLockReadGuard<'_, ()> {
+        self.usage.read()
+    }
+
+    /// Held from checking that no business uses a category until it's deleted. Take it before any store lock.
+    pub fn deleting(&self) -> RwLockWriteGuard<'_, ()> {
+        self.usage.write()
+    }
+
+    /// Removes a category with no subcategories. Whether businesses still use it is up to the caller to check, under
+    /// `deleting`.
+    pub fn delete(&self, id: CategoryId) -> Result<CategoryRecord, TaxonomyError> {
+        let mut tree = self.tree.write();
+        if tree.children(Some(id)).next().is_some() {
+            return Err(TaxonomyError::HasChildren(id));
+        }
+        tree.categories.remove(&id).ok_or(TaxonomyError::NotFound(id))
+    }
+
+    /// The category and every category under it, or `None` if it doesn't exist.
+    pub fn subtree(&self, id: CategoryId) -> Option<HashSet<CategoryId>> {
+        let tree = self.tree.read();
+        if !tree.categories.contains_key(&id) {
+            return None;
+        }
+        Some(tree.categories.keys().copied().filter(|candidate| tree.is_descendant(*candidate, id)).collect())
+    }
+
+    /// The IDs in `ids` that aren't categories.
+    pub fn unknown(&self, ids: &[CategoryId]) -> Vec<CategoryId> {
+        let tree = self.tree.read();
+        ids.iter().copied().filter(|id| !tree.categories.contains_key(id)).collect()
+    }
+
+    /// Fills in the category IDs of a business that has none from its free text category pair. The subcategory
+    /// is enough when it's under the main category. When it isn't, both are used, and names that aren't in the
+    /// tree are skipped.
+    pub fn link(&self, business: &mut Business) {
+        if !business.category_ids.is_empty() {
+            return;
+        }
+        let tree = self.tree.read();
+        let main = tree.find_by_name(&business.category.main_category).map(|category| category.id);
+        // Prefer a subcategory of that name under the main category, there can be one elsewhere too.
+        let subcategory = main
+            .and_then(|main| {
+                tree.categories.values().find(|category| {
+                    category.name.eq_ignore_ascii_case(business.category.subcategory.trim())
+                        && tree.is_descendant(category.id, main)
+                })
+            })
+            .or_else(|| tree.find_by_name(&business.category.subcategory))
+            .map(|category| category.id);
+
+        let mut ids = match (main, subcategory) {
+            (Some(main), Some(subcategory)) if tree.is_descendant(subcategory, main) => vec![subcategory],
+            (main, subcategory) => main.into_iter().chain(subcategory).collect(),
+        };
+        ids.dedup();
+        business.category_ids = ids;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(taxonomy: &Taxonomy, name: &str) -> CategoryId {
+        taxonomy.tree.read().find_by_name(name).unwrap().id
+    }
+
+    #[test]
+    fn subtrees_follow_moves() {
+        let taxonomy = Taxonomy::with_defaults();
+        let (restaurants, pizza, shopping) = (id(&taxonomy, "Restaurants"), id(&taxonomy, "Pizza"), id(&taxonomy, "Shopping"));
+        let slices = taxonomy.create(NewCategory { name: "By the Slice".into(), parent_id: Some(pizza) }).unwrap();
+        assert!(taxonomy.subtree(restaurants).unwrap().contains(&slices.id));
+        assert_eq!(taxonomy.get(slices.id).unwrap().path, ["Restaurants", "Pizza", "By the Slice"]);
+
+        taxonomy.update(pizza, NewCategory { name: "Pizza".into(), parent_id: Some(shopping) }).unwrap();
+        assert!(!taxonomy.subtree(restaurants).unwrap().contains(&slices.id));
+        assert!(taxonomy.subtree(shopping).unwrap().contains(&slices.id));
+    }
+
+    #[test]
+    fn bad_changes_are_refused() {
+        let taxonomy = Taxonomy::with_defaults();
+        let (restaurants, pizza) = (id(&taxonomy, "Restaurants"), id(&taxonomy, "Pizza"));
+        let duplicate = NewCategory { name: "pizza".into(), parent_id: Some(restaurants) };
+        assert_eq!(taxonomy.create(duplicate), Err(TaxonomyError::DuplicateName("pizza".into())));
+        let cycle = NewCategory { name: "Restaurants".into(), parent_id: Some(pizza) };
+        assert_eq!(taxonomy.update(restaurants, cycle), Err(TaxonomyError::Cycle(restaurants)));
+        assert_eq!(taxonomy.delete(restaurants), Err(TaxonomyError::HasChildren(restaurants)));
+        assert!(taxonomy.delete(pizza).is_ok());
+    }
+
+    #[test]
+    fn loops_in_a_loaded_tree_are_refused() {
+        let taxonomy = Taxonomy::with_defaults();
+        let record = |id, parent_id| CategoryRecord { id, name: format!("Category {id}"), parent_id };
+        // 1 hangs off the loop between 2 and 3 without being part of it.
+        let records = vec![record(1, Some(2)), record(2, Some(3)), record(3, Some(2))];
+        assert_eq!(taxonomy.replace(records), Err(TaxonomyError::Cycle(2)));
+        assert!(taxonomy.get(id(&taxonomy, "Restaurants")).is_some());
+    }
+
+    #[test]
+    fn deletes_wait_for_businesses_being_linked() {
+        let taxonomy = Taxonomy::with_defaults();
+        let deleted = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let linking = taxonomy.linking();
+            scope.spawn(|| {
+                let _deleting = taxonomy.deleting();
+                deleted.store(true, std::sync::atomic::Ordering::SeqCst);
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!deleted.load(std::sync::atomic::Ordering::SeqCst));
+            drop(linking);
+        });
+        assert!(deleted.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn free_text_categories_are_linked_by_name() {
+        let taxonomy = Taxonomy::with_defaults();
+        let mut business: Business = serde_json::from_value(serde_json::json!({
+            "name": "Luigi's", "street_addr": "1 Main St", "city": "Houston", "state": "TX", "zip": "77002",
+            "phone_num": "713-555-0100", "category": { "main_category": "Restaurants", "subcategory": "Pizza" },
+            "email": null, "website": null
+        }))
+        .unwrap();
+        taxonomy.link(&mut business);
+        assert_eq!(business.category_ids, [id(&taxonomy, "Pizza")]);
+
+        business.category_ids.clear();
+        business.category.main_category = "Electronics".into();
+        business.category.subcategory = "Baby".into();
+        taxonomy.link(&mut business);
+        assert_eq!(business.category_ids, [id(&taxonomy, "Electronics"), id(&taxonomy, "Baby")]);
+    }
+}
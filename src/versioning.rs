@@ -0,0 +1,185 @@
+// API versions. Every business, review and photo route lives under `/v1`, `/v2`, ... and all versions share one store.
+// Requests without a version in the path are routed by their Accept header (`application/vnd.belp.v2+json`),
+// falling back to the default version, so clients from before versioning keep working unchanged.
+// Deprecated versions answer with Deprecation / Sunset headers and a link to the same route in the next version.
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::{self, HeaderName, HeaderValue, HttpDate};
+use serde::Deserialize;
+
+/// Every version this build can serve.
+pub const SUPPORTED_VERSIONS: &[u16] = &[1, 2];
+
+/// Routes that aren't part of the versioned API and are never rewritten.
+const UNVERSIONED_PATHS: &[&str] = &["/", "/healthz", "/readyz", "/version", "/metrics", "/openapi.json", "/docs"];
+
+const DEPRECATION_HEADER: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET_HEADER: HeaderName = HeaderName::from_static("sunset");
+pub const API_VERSION_HEADER: HeaderName = HeaderName::from_static("api-version");
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Deprecation {
+    /// When the version was deprecated, as `YYYY-MM-DD`.
+    pub since: String,
+    /// When the version will stop being served, as `YYYY-MM-DD`.
+    pub sunset: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersioningConfig {
+    /// The version used when neither the path nor the Accept header asks for one.
+    pub default_version: u16,
+    pub deprecations: BTreeMap<u16, Deprecation>,
+}
+
+impl Default for VersioningConfig {
+    fn default() -> Self {
+        VersioningConfig { default_version: 1, deprecations: BTreeMap::new() }
+    }
+}
+
+impl VersioningConfig {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !SUPPORTED_VERSIONS.contains(&self.default_version) {
+            problems.push(format!("default API version {} is not one of {SUPPORTED_VERSIONS:?}", self.default_version));
+        }
+        for (version, deprecation) in &self.deprecations {
+            if !SUPPORTED_VERSIONS.contains(version) {
+                problems.push(format!("deprecated API version {version} is not one of {SUPPORTED_VERSIONS:?}"));
+            }
+            for date in std::iter::once(&deprecation.since).chain(&deprecation.sunset) {
+                if parse_date(date).is_none() {
+                    problems.push(format!("API version {version} deprecation date `{date}` is not a YYYY-MM-DD date"));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Where a request should go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Negotiated {
+    /// Not a versioned route, leave it alone.
+    Unversioned,
+    /// The path already names a supported version.
+    Versioned(u16),
+    /// The path has no version, and should be served by the given one under `path`.
+    Rewrite { version: u16, path: String },
+    /// Asked for a version this build doesn't have.
+    Unsupported(u16),
+}
+
+/// Works out the API version for a request from its path, then its Accept header, then the default.
+pub fn negotiate(path: &str, accept: Option<&str>, config: &VersioningConfig) -> Negotiated {
+    if UNVERSIONED_PATHS.contains(&path) {
+        return Negotiated::Unversioned;
+    }
+    if let Some(version) = path_version(path) {
+        if SUPPORTED_VERSIONS.contains(&version) {
+            return Negotiated::Versioned(version);
+        }
+        return Negotiated::Unsupported(version);
+    }
+    let version = accept.and_then(accept_version).unwrap_or(config.default_version);
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Negotiated::Unsupported(version);
+    }
+    Negotiated::Rewrite { version, path: format!("/v{version}{path}") }
+}
+
+/// `/v2/business` -> 2.
+fn path_version(path: &str) -> Option<u16> {
+    let segment = path.strip_prefix("/v")?;
+    let digits = segment.split('/').next()?;
+    digits.parse().ok()
+}
+
+/// The version from a `application/vnd.belp.v<N>+json` media range, if the Accept header has one.
+fn accept_version(accept: &str) -> Option<u16> {
+    accept.split(',').find_map(|media_range| {
+        let media_type = media_range.split(';').next()?.trim().to_ascii_lowercase();
+        media_type.strip_prefix("application/vnd.belp.v")?.strip_suffix("+json")?.parse().ok()
+    })
+}
+
+/// Deprecation, Sunset and successor Link headers for a response from `version`, if it's deprecated.
+/// `path` is the versioned path that was served.
+pub fn deprecation_headers(version: u16, path: &str, config: &VersioningConfig) -> Vec<(HeaderName, HeaderValue)> {
+    let deprecation = match config.deprecations.get(&version) {
+        Some(deprecation) => deprecation,
+        None => return Vec::new(),
+    };
+    let mut headers = Vec::new();
+    // RFC 9745: the date the version was deprecated, as a structured field date.
+    if let Some(since) = parse_date(&deprecation.since) {
+        let seconds = since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        headers.push((DEPRECATION_HEADER, HeaderValue::from_str(&format!("@{seconds}")).expect("valid header")));
+    }
+    if let Some(sunset) = deprecation.sunset.as_deref().and_then(parse_date) {
+        let sunset = HttpDate::from(sunset).to_string();
+        headers.push((SUNSET_HEADER, HeaderValue::from_str(&sunset).expect("valid header")));
+    }
+    let successor = SUPPORTED_VERSIONS.iter().find(|&&newer| newer > version);
+    if let (Some(successor), Some(rest)) = (successor, path.strip_prefix(&format!("/v{version}"))) {
+        let link = format!("</v{successor}{rest}>; rel=\"successor-version\"");
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.push((header::LINK, link));
+        }
+    }
+    headers
+}
+
+/// Parses a `YYYY-MM-DD` date as midnight UTC.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    // Days since the epoch, from Howard Hinnant's `days_from_civil`.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_beats_accept_beats_default() {
+        let config = VersioningConfig::default();
+        assert_eq!(negotiate("/v2/business", Some("application/vnd.belp.v1+json"), &config), Negotiated::Versioned(2));
+        assert_eq!(
+            negotiate("/business", Some("text/html, application/vnd.belp.v2+json;q=0.9"), &config),
+            Negotiated::Rewrite { version: 2, path: "/v2/business".into() }
+        );
+        assert_eq!(
+            negotiate("/business", Some("application/json"), &config),
+            Negotiated::Rewrite { version: 1, path: "/v1/business".into() }
+        );
+        assert_eq!(negotiate("/v9/business", None, &config), Negotiated::Unsupported(9));
+        assert_eq!(negotiate("/healthz", Some("application/vnd.belp.v2+json"), &config), Negotiated::Unversioned);
+    }
+
+    #[test]
+    fn deprecated_versions_point_at_their_successor() {
+        let mut config = VersioningConfig::default();
+        config.deprecations.insert(1, Deprecation { since: "2026-01-01".into(), sunset: Some("2027-06-30".into()) });
+        let headers: std::collections::HashMap<_, _> = deprecation_headers(1, "/v1/business/Cafe", &config).into_iter().collect();
+        assert_eq!(headers[&DEPRECATION_HEADER], "@1767225600");
+        assert_eq!(headers[&SUNSET_HEADER], "Wed, 30 Jun 2027 00:00:00 GMT");
+        assert_eq!(headers[&header::LINK], "</v2/business/Cafe>; rel=\"successor-version\"");
+        assert!(deprecation_headers(2, "/v2/business", &config).is_empty());
+    }
+}
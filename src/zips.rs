@@ -0,0 +1,50 @@
+// The bundled offline ZIP code table (data/zip_centroids.csv): the state and rough centre of every ZIP code in use.
+// It's compiled into the binary and parsed the first time it's needed, so nothing is looked up over the network.
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const TABLE: &str = include_str!("../data/zip_centroids.csv");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZipInfo {
+    /// USPS state code, e.g. `TX`.
+    pub state: &'static str,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+fn table() -> &'static HashMap<u32, ZipInfo> {
+    static TABLE_BY_ZIP: OnceLock<HashMap<u32, ZipInfo>> = OnceLock::new();
+    TABLE_BY_ZIP.get_or_init(|| {
+        TABLE
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut columns = line.split(',');
+                let zip = columns.next()?.parse().ok()?;
+                let state = columns.next()?;
+                let latitude = columns.next()?.parse().ok()?;
+                let longitude = columns.next()?.parse().ok()?;
+                Some((zip, ZipInfo { state, latitude, longitude }))
+            })
+            .collect()
+    })
+}
+
+/// What the table knows about a five digit ZIP code.
+pub fn lookup(zip: u32) -> Option<ZipInfo> {
+    table().get(&zip).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_covers_leading_zero_zips() {
+        assert_eq!(lookup(77002).map(|info| info.state), Some("TX"));
+        // Stored as `02108`, which only parses to the right number if the zeros are read as part of it.
+        assert_eq!(lookup(2108).map(|info| info.state), Some("MA"));
+        assert!(lookup(99999).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+// Starts the real server with the snapshot backend, sends SIGTERM while writes are still coming in,
+// and checks that every write the server acknowledged made it into the snapshot.
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn start_server(port: u16, snapshot: &PathBuf) -> Child {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_PlaygroundSite"))
+        .args(["--host", "127.0.0.1", "--storage-backend", "snapshot", "--shutdown-timeout-secs", "10"])
+        .arg("--storage-path")
+        .arg(snapshot)
+        .env("PORT", port.to_string())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("could not start the server");
+
+    // Wait until the server says it's listening.
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+    for line in lines.by_ref() {
+        if line.unwrap().contains("Listening on") {
+            break;
+        }
+    }
+    thread::spawn(move || lines.for_each(drop));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "server never started listening");
+        thread::sleep(Duration::from_millis(20));
+    }
+    child
+}
+
+/// Sends a single request and returns the status code, or `None` if the server wasn't reachable.
+fn request(port: u16, method: &str, path: &str, body: &str) -> Option<u16> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(15))).ok()?;
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    response.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn business_json(name: &str) -> String {
+    format!(
+        r#"{{"business":{{"name":"{name}","street_addr":"1 Main St","city":"Houston","state":"Texas","zip":77001,"phone_num":7135550100,"category":{{"main_category":"Restaurant","subcategory":"Pizza"}},"email":null,"website":null}},"reviews":[],"photos":[]}}"#
+    )
+}
+
+#[test]
+fn sigterm_keeps_every_acknowledged_write() {
+    let snapshot = std::env::temp_dir().join(format!("belp-shutdown-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&snapshot);
+    let port = free_port();
+    let mut server = start_server(port, &snapshot);
+
+    let acknowledged = Arc::new(Mutex::new(Vec::new()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let (acknowledged, stop) = (acknowledged.clone(), stop.clone());
+            thread::spawn(move || {
+                for index in 0.. {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let name = format!("Business {writer}-{index}");
+                    match request(port, "POST", "/business", &business_json(&name)) {
+                        Some(200) => acknowledged.lock().unwrap().push(name),
+                        Some(status) => panic!("unexpected status {status} for {name}"),
+                        // Refused connections after the signal are expected, those writes were never acknowledged.
+                        None => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+            })
+        })
+        .collect();
+
+    // Let some load build up, then pull the plug in the middle of it.
+    thread::sleep(Duration::from_millis(500));
+    let pid = server.id().to_string();
+    let killed = Command::new("kill").args(["-TERM", &pid]).status().unwrap();
+    assert!(killed.success());
+
+    let status = server.wait().unwrap();
+    stop.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!(status.success(), "server exited with {status}");
+
+    let acknowledged = acknowledged.lock().unwrap();
+    assert!(!acknowledged.is_empty(), "no writes got through before the signal");
+    let saved: serde_json::Value = serde_json::from_slice(&std::fs::read(&snapshot).unwrap()).unwrap();
+    let saved_names: std::collections::HashSet<&str> = saved["businesses"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|business| business["business"]["name"].as_str())
+        .collect();
+    let lost: Vec<&String> = acknowledged.iter().filter(|name| !saved_names.contains(name.as_str())).collect();
+    assert!(lost.is_empty(), "{} acknowledged writes were lost: {lost:?}", lost.len());
+
+    // And the next start picks them all up again.
+    let port = free_port();
+    let mut server = start_server(port, &snapshot);
+    assert_eq!(request(port, "GET", &format!("/business/{}", acknowledged[0].replace(' ', "%20")), ""), Some(200));
+    Command::new("kill").args(["-INT", &server.id().to_string()]).status().unwrap();
+    assert!(server.wait().unwrap().success());
+    let _ = std::fs::remove_file(&snapshot);
+}